@@ -56,6 +56,18 @@ impl<T> Callbacks<T> {
             .map(|callback| callback.call(msg.clone()))
             .collect()
     }
+
+    /// Calls every callback in order, stopping at the first error, which means the message was rejected
+    pub async fn call_until_error(&self, msg: T) -> Result<()>
+    where
+        T: Clone,
+    {
+        for future in self.call_all(msg) {
+            future.await?;
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
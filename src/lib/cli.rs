@@ -27,6 +27,8 @@ pub struct Args {
     ///
     /// udpb:broadcast_ip:port (udp, broadcast mode)
     ///
+    /// udpm:group_ip:port (udp, multicast mode)
+    ///
     /// tcps:listen_ip:port (tcp, server mode)
     ///
     /// tcpc:dest_ip:port (tcp, client mode)
@@ -48,9 +50,9 @@ pub struct Args {
     #[arg(short, long)]
     verbose: bool,
 
-    /// Sets the IP and port that the server will be provided
+    /// Sets the IP and port that the server will be provided, use `[::]:8080` for IPv6 (dual-stack)
     #[arg(long, default_value = "0.0.0.0:8080")]
-    web_server: std::net::SocketAddrV4,
+    web_server: std::net::SocketAddr,
 
     /// Turns all log categories up to Trace to the log file, for more information check RUST_LOG env variable.
     #[arg(long)]
@@ -92,6 +94,10 @@ pub struct Args {
     /// Sets the default version used by the REST API, this will remove the prefix used by its path.
     #[arg(long, default_value = "1", value_names = ["1"])]
     default_api_version: u8,
+
+    /// Downloads the newest onboard log of an autopilot once it disarms
+    #[arg(long)]
+    download_logs_on_disarm: bool,
 }
 
 #[instrument(level = "trace")]
@@ -211,7 +217,7 @@ pub fn udp_server_timeout() -> Option<tokio::time::Duration> {
 }
 
 #[instrument(level = "debug")]
-pub fn web_server() -> std::net::SocketAddrV4 {
+pub fn web_server() -> std::net::SocketAddr {
     args().web_server
 }
 
@@ -245,6 +251,11 @@ pub fn default_api_version() -> u8 {
     args().default_api_version
 }
 
+#[instrument(level = "debug")]
+pub fn download_logs_on_disarm() -> bool {
+    args().download_logs_on_disarm
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -259,8 +270,10 @@ mod tests {
             ("tcpc:127.0.0.1:7000", true),
             ("tcps:0.0.0.0:5000", true),
             ("tcps:192.168.1.10:6000", true),
-            ("udpb:192.168.0.255:3000", false),
-            ("udpb:255.255.255.255:9999", false),
+            ("udpb:192.168.0.255:3000", true),
+            ("udpb:255.255.255.255:9999", true),
+            ("udpbroadcast:192.168.2.255:14550", true),
+            ("udpm:239.255.145.50:14550", true),
             ("udpc:127.0.0.1:1234", true),
             ("udpc:192.168.1.100:8080", true),
             ("udpout:127.0.0.1:1234", true),
@@ -269,6 +282,23 @@ mod tests {
             ("udps:192.168.1.5:6789", true),
             ("udpin:0.0.0.0:5000", true),
             ("udpin:192.168.1.5:6789", true),
+            ("udpout://[fd00::2]:14550", true),
+            ("udpout://192.168.2.1:14550?bind=192.168.2.2:14555", true),
+            ("udpin://[::]:14550", true),
+            ("tcpc://localhost:5760", true),
+            ("tcps://[::1]:5760", true),
+            ("tcpc+tls://localhost:5761", true),
+            ("tcps+tls://0.0.0.0:5761", false),
+            ("quicc://localhost:5762", true),
+            ("quics://0.0.0.0:5762", false),
+            ("ws://192.168.2.2:8080/mavlink", true),
+            (
+                "wss://fleet.example.com/ingress?token=abc123&format=json",
+                true,
+            ),
+            ("ws://192.168.2.2:8080?format=text", false),
+            ("udpout://192.168.1.100", false),
+            ("tcpc://:5760", false),
             ("tlogr:tests/files/00025-2024-04-22_18-49-07.tlog", true),
             ("tlogw:/tmp/little_potato.tlog", true),
             (
@@ -0,0 +1,395 @@
+use std::{
+    collections::HashSet,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use anyhow::{Context, Result};
+use indexmap::IndexMap;
+use ipnet::IpNet;
+use serde::Serialize;
+use tokio::{sync::RwLock, task::AbortHandle, time::Instant};
+use tracing::*;
+
+use crate::{
+    callbacks::Callbacks, drivers::generic_tasks::SendReceiveContext, protocol::Protocol,
+    stats::accumulated::driver::AccumulatedDriverStatsInner,
+};
+
+/// Snapshot of a client connected to a server driver
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientInfo {
+    pub address: SocketAddr,
+    pub connected_at_us: u64,
+    pub last_seen_us: u64,
+    pub stats: AccumulatedDriverStatsInner,
+}
+
+/// Why a client was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    Banned,
+    NotAllowed,
+    Full,
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::Banned => write!(f, "banned"),
+            Rejection::NotAllowed => write!(f, "not in the allowlist"),
+            Rejection::Full => write!(f, "maximum number of clients reached"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Client {
+    info: ClientInfo,
+    last_seen: Instant,
+    task: Option<AbortHandle>,
+}
+
+/// The clients currently connected to a server driver, shared between its tasks
+#[derive(Debug, Clone, Default)]
+pub struct ClientRegistry {
+    clients: Arc<RwLock<IndexMap<SocketAddr, Client>>>,
+    banned: Arc<RwLock<HashSet<IpAddr>>>,
+    allowlist: Arc<Vec<IpNet>>,
+    max_clients: Option<usize>,
+}
+
+impl ClientRegistry {
+    /// Only clients from these networks are accepted, or any client if empty
+    pub fn with_allowlist(mut self, allowlist: Vec<IpNet>) -> Self {
+        self.allowlist = Arc::new(allowlist);
+        self
+    }
+
+    pub fn with_max_clients(mut self, max_clients: usize) -> Self {
+        self.max_clients = Some(max_clients);
+        self
+    }
+
+    /// Checks whether a new or known client can be served
+    pub async fn admit(&self, address: &SocketAddr) -> std::result::Result<(), Rejection> {
+        if self.is_banned(&address.ip()).await {
+            return Err(Rejection::Banned);
+        }
+
+        if !self.allowlist.is_empty()
+            && !self
+                .allowlist
+                .iter()
+                .any(|network| network.contains(&address.ip()))
+        {
+            return Err(Rejection::NotAllowed);
+        }
+
+        if let Some(max_clients) = self.max_clients {
+            let clients = self.clients.read().await;
+            if !clients.contains_key(address) && clients.len() >= max_clients {
+                return Err(Rejection::Full);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn len(&self) -> usize {
+        self.clients.read().await.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.clients.read().await.is_empty()
+    }
+
+    pub async fn contains(&self, address: &SocketAddr) -> bool {
+        self.clients.read().await.contains_key(address)
+    }
+
+    pub async fn list(&self) -> Vec<ClientInfo> {
+        self.clients
+            .read()
+            .await
+            .values()
+            .map(|client| client.info.clone())
+            .collect()
+    }
+
+    pub async fn insert(&self, address: SocketAddr) {
+        let now_us = chrono::Utc::now().timestamp_micros() as u64;
+
+        let client = Client {
+            info: ClientInfo {
+                address,
+                connected_at_us: now_us,
+                last_seen_us: now_us,
+                stats: AccumulatedDriverStatsInner::default(),
+            },
+            last_seen: Instant::now(),
+            task: None,
+        };
+
+        if let Some(old_client) = self.clients.write().await.insert(address, client) {
+            if let Some(task) = old_client.task {
+                task.abort();
+            }
+        }
+
+        debug!("New client added: {address:?}");
+    }
+
+    /// Sets the task serving the client, so it can be aborted when the client is disconnected
+    pub async fn set_task(&self, address: &SocketAddr, task: AbortHandle) {
+        match self.clients.write().await.get_mut(address) {
+            Some(client) => client.task = Some(task),
+            None => task.abort(), // The client is already gone
+        }
+    }
+
+    /// Whether the task serving the client has stopped on its own, e.g. after a send error
+    pub async fn is_task_finished(&self, address: &SocketAddr) -> bool {
+        self.clients
+            .read()
+            .await
+            .get(address)
+            .and_then(|client| client.task.as_ref())
+            .is_some_and(|task| task.is_finished())
+    }
+
+    /// Forgets a client whose connection was already closed
+    pub async fn remove(&self, address: &SocketAddr) -> Option<ClientInfo> {
+        let client = self.clients.write().await.shift_remove(address)?;
+
+        debug!("Client removed: {address:?}");
+
+        Some(client.info)
+    }
+
+    /// Aborts the task serving the client and forgets it
+    pub async fn disconnect(&self, address: &SocketAddr) -> Option<ClientInfo> {
+        let client = self.clients.write().await.shift_remove(address)?;
+
+        if let Some(task) = &client.task {
+            task.abort();
+        }
+
+        debug!("Client disconnected: {address:?}");
+
+        Some(client.info)
+    }
+
+    pub async fn clear(&self) {
+        for (_address, client) in self.clients.write().await.drain(..) {
+            if let Some(task) = client.task {
+                task.abort();
+            }
+        }
+    }
+
+    /// The clients that have been silent for longer than `timeout`
+    pub async fn idle(&self, timeout: tokio::time::Duration) -> Vec<SocketAddr> {
+        self.clients
+            .read()
+            .await
+            .iter()
+            .filter(|(_address, client)| client.last_seen.elapsed() > timeout)
+            .map(|(address, _client)| *address)
+            .collect()
+    }
+
+    /// Disconnects every client that has been silent for longer than `timeout`
+    pub async fn disconnect_idle(&self, timeout: tokio::time::Duration) -> Vec<SocketAddr> {
+        let idle_clients = self.idle(timeout).await;
+
+        for address in &idle_clients {
+            debug!("Client {address} timed out.");
+            self.disconnect(address).await;
+        }
+
+        idle_clients
+    }
+
+    pub async fn update_input(&self, address: &SocketAddr, message: &Arc<Protocol>) {
+        if let Some(client) = self.clients.write().await.get_mut(address) {
+            client.last_seen = Instant::now();
+            client.info.last_seen_us = chrono::Utc::now().timestamp_micros() as u64;
+            client.info.stats.update_input(message);
+        }
+    }
+
+    pub async fn update_output(&self, address: &SocketAddr, message: &Arc<Protocol>) {
+        if let Some(client) = self.clients.write().await.get_mut(address) {
+            client.info.stats.update_output(message);
+        }
+    }
+
+    pub async fn banned(&self) -> Vec<IpAddr> {
+        self.banned.read().await.iter().copied().collect()
+    }
+
+    pub async fn is_banned(&self, address: &IpAddr) -> bool {
+        self.banned.read().await.contains(address)
+    }
+
+    /// Refuses any further traffic from this IP, disconnecting its current clients
+    pub async fn ban(&self, address: IpAddr) -> Vec<ClientInfo> {
+        self.banned.write().await.insert(address);
+
+        let addresses = self
+            .clients
+            .read()
+            .await
+            .keys()
+            .filter(|client_address| client_address.ip() == address)
+            .copied()
+            .collect::<Vec<_>>();
+
+        let mut disconnected = Vec::new();
+        for client_address in addresses {
+            if let Some(client) = self.disconnect(&client_address).await {
+                disconnected.push(client);
+            }
+        }
+
+        info!("Banned {address}");
+
+        disconnected
+    }
+
+    pub async fn unban(&self, address: &IpAddr) -> bool {
+        self.banned.write().await.remove(address)
+    }
+
+    pub async fn reset_stats(&self) {
+        for client in self.clients.write().await.values_mut() {
+            client.info.stats = AccumulatedDriverStatsInner::default();
+        }
+    }
+
+    /// Creates the context for the tasks of a single client, accounting the messages that went
+    /// through the driver callbacks to that client
+    pub fn client_context(
+        &self,
+        address: SocketAddr,
+        context: &SendReceiveContext,
+    ) -> SendReceiveContext {
+        let on_message_input = Callbacks::default();
+        on_message_input.add_callback({
+            let callbacks = context.on_message_input.clone();
+            let clients = self.clone();
+            move |message: Arc<Protocol>| {
+                let callbacks = callbacks.clone();
+                let clients = clients.clone();
+                async move {
+                    callbacks.call_until_error(message.clone()).await?;
+
+                    clients.update_input(&address, &message).await;
+
+                    Ok(())
+                }
+            }
+        });
+
+        let on_message_output = Callbacks::default();
+        on_message_output.add_callback({
+            let callbacks = context.on_message_output.clone();
+            let clients = self.clone();
+            move |message: Arc<Protocol>| {
+                let callbacks = callbacks.clone();
+                let clients = clients.clone();
+                async move {
+                    callbacks.call_until_error(message.clone()).await?;
+
+                    clients.update_output(&address, &message).await;
+
+                    Ok(())
+                }
+            }
+        });
+
+        SendReceiveContext {
+            hub_sender: context.hub_sender.clone(),
+            on_message_output,
+            on_message_input,
+            stats: context.stats.clone(),
+            driver: context.driver,
+        }
+    }
+}
+
+/// Parses a comma-separated list of networks and addresses, like `192.168.2.0/24,10.0.0.5`
+pub fn parse_allowlist(allowlist: &str) -> Result<Vec<IpNet>> {
+    allowlist
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                .context(format!("Invalid allowlist entry {entry:?}"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_client_registry() {
+        let clients = ClientRegistry::default();
+        let first: SocketAddr = "192.168.2.1:5000".parse().unwrap();
+        let second: SocketAddr = "192.168.2.2:5000".parse().unwrap();
+
+        clients.insert(first).await;
+        clients.insert(second).await;
+        assert_eq!(clients.len().await, 2);
+
+        let task = tokio::spawn(std::future::pending::<()>());
+        clients.set_task(&first, task.abort_handle()).await;
+        assert!(clients.disconnect(&first).await.is_some());
+        assert!(task.await.unwrap_err().is_cancelled());
+        assert!(!clients.contains(&first).await);
+
+        tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+        clients.insert(first).await;
+        let idle = clients
+            .disconnect_idle(tokio::time::Duration::from_millis(10))
+            .await;
+        assert_eq!(idle, vec![second]);
+        assert_eq!(clients.list().await.len(), 1);
+
+        let banned = clients.ban(first.ip()).await;
+        assert_eq!(banned.len(), 1);
+        assert!(clients.is_empty().await);
+        assert!(clients.is_banned(&first.ip()).await);
+        assert!(clients.unban(&first.ip()).await);
+        assert!(!clients.is_banned(&first.ip()).await);
+    }
+
+    #[tokio::test]
+    async fn test_client_admission() {
+        let clients = ClientRegistry::default()
+            .with_allowlist(parse_allowlist("192.168.2.0/24, 10.0.0.5").unwrap())
+            .with_max_clients(1);
+        let first: SocketAddr = "192.168.2.1:5000".parse().unwrap();
+        let second: SocketAddr = "10.0.0.5:5000".parse().unwrap();
+        let outsider: SocketAddr = "10.0.0.6:5000".parse().unwrap();
+
+        assert_eq!(clients.admit(&outsider).await, Err(Rejection::NotAllowed));
+        assert_eq!(clients.admit(&first).await, Ok(()));
+
+        clients.insert(first).await;
+        assert_eq!(clients.admit(&first).await, Ok(()));
+        assert_eq!(clients.admit(&second).await, Err(Rejection::Full));
+
+        clients.ban(first.ip()).await;
+        assert_eq!(clients.admit(&first).await, Err(Rejection::Banned));
+        assert_eq!(clients.admit(&second).await, Ok(()));
+
+        assert!(parse_allowlist("192.168.2.0/33").is_err());
+    }
+}
@@ -8,7 +8,7 @@ use tracing::*;
 
 use crate::{
     callbacks::{Callbacks, MessageCallback},
-    drivers::{Driver, DriverInfo},
+    drivers::{generic_tasks::SendReceiveContext, Driver, DriverInfo},
     protocol::Protocol,
     stats::{
         accumulated::driver::{AccumulatedDriverStats, AccumulatedDriverStatsProvider},
@@ -71,11 +71,13 @@ impl Driver for FakeSink {
         while let Ok(message) = hub_receiver.recv().await {
             self.stats.write().await.stats.update_input(&message);
 
-            for future in self.on_message_input.call_all(message.clone()) {
-                if let Err(error) = future.await {
-                    debug!("Dropping message: on_message_input callback returned error: {error:?}");
-                    continue;
-                }
+            if let Err(error) = self
+                .on_message_input
+                .call_until_error(message.clone())
+                .await
+            {
+                debug!("Dropping message: on_message_input callback returned error: {error:?}");
+                continue;
             }
 
             let version = match &**message {
@@ -165,8 +167,8 @@ impl DriverInfo for FakeSinkInfo {
         ]
     }
 
-    fn create_endpoint_from_url(&self, _url: &url::Url) -> Option<Arc<dyn Driver>> {
-        Some(Arc::new(FakeSink::builder("Unnamed").print().build()))
+    fn create_endpoint_from_url(&self, _url: &url::Url) -> Result<Arc<dyn Driver>> {
+        Ok(Arc::new(FakeSink::builder("Unnamed").print().build()))
     }
 }
 
@@ -214,6 +216,14 @@ impl FakeSourceBuilder {
 #[async_trait::async_trait]
 impl Driver for FakeSource {
     async fn run(&self, hub_sender: broadcast::Sender<Arc<Protocol>>) -> Result<()> {
+        let context = SendReceiveContext {
+            hub_sender,
+            on_message_output: self.on_message_output.clone(),
+            on_message_input: Callbacks::default(),
+            stats: self.stats.clone(),
+            driver: self.uuid,
+        };
+
         let mut sequence = 0;
 
         use mavlink::ardupilotmega::{
@@ -252,16 +262,18 @@ impl Driver for FakeSource {
 
             let message = Arc::new(Protocol::new("fake_source", packet));
 
-            self.stats.write().await.stats.update_output(&message);
+            context.stats.write().await.stats.update_output(&message);
 
-            for future in self.on_message_output.call_all(message.clone()) {
-                if let Err(error) = future.await {
-                    debug!("Dropping message: on_message_input callback returned error: {error:?}");
-                    continue;
-                }
+            if let Err(error) = context
+                .on_message_output
+                .call_until_error(message.clone())
+                .await
+            {
+                debug!("Dropping message: on_message_input callback returned error: {error:?}");
+                continue;
             }
 
-            if let Err(error) = hub_sender.send(message) {
+            if let Err(error) = context.send_to_hub(message) {
                 error!("Failed to send message to hub: {error:?}");
             }
 
@@ -323,7 +335,7 @@ impl DriverInfo for FakeSourceInfo {
         ]
     }
 
-    fn create_endpoint_from_url(&self, url: &url::Url) -> Option<Arc<dyn Driver>> {
+    fn create_endpoint_from_url(&self, url: &url::Url) -> Result<Arc<dyn Driver>> {
         let period: u64 = url
             .query_pairs()
             .find_map(|(key, value)| {
@@ -335,7 +347,7 @@ impl DriverInfo for FakeSourceInfo {
             })
             .unwrap_or(10);
 
-        Some(Arc::new(
+        Ok(Arc::new(
             FakeSource::builder("Unnamed", std::time::Duration::from_millis(period)).build(),
         ))
     }
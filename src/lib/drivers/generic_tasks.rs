@@ -7,7 +7,10 @@ use tokio::sync::{broadcast, RwLock};
 use tracing::*;
 
 use crate::{
-    callbacks::Callbacks, protocol::Protocol, stats::accumulated::driver::AccumulatedDriverStats,
+    callbacks::Callbacks,
+    protocol::Protocol,
+    stats::{accumulated::driver::AccumulatedDriverStats, driver::DriverUuid},
+    vehicles,
 };
 
 #[derive(Clone)]
@@ -16,6 +19,20 @@ pub struct SendReceiveContext {
     pub on_message_output: Callbacks<Arc<Protocol>>,
     pub on_message_input: Callbacks<Arc<Protocol>>,
     pub stats: Arc<RwLock<AccumulatedDriverStats>>,
+    /// The driver the link belongs to
+    pub driver: DriverUuid,
+}
+
+impl SendReceiveContext {
+    /// Sends a message received from the link to the hub, marked with the driver it came through
+    pub fn send_to_hub(
+        &self,
+        message: Arc<Protocol>,
+    ) -> Result<usize, broadcast::error::SendError<Arc<Protocol>>> {
+        message.set_driver(self.driver);
+
+        self.hub_sender.send(message)
+    }
 }
 
 #[instrument(level = "debug", skip(writer, reader, context))]
@@ -77,14 +94,16 @@ where
 
         context.stats.write().await.stats.update_input(&message);
 
-        for future in context.on_message_input.call_all(message.clone()) {
-            if let Err(error) = future.await {
-                debug!("Dropping message: on_message_input callback returned error: {error:?}");
-                continue;
-            }
+        if let Err(error) = context
+            .on_message_input
+            .call_until_error(message.clone())
+            .await
+        {
+            debug!("Dropping message: on_message_input callback returned error: {error:?}");
+            continue;
         }
 
-        if let Err(send_error) = context.hub_sender.send(message) {
+        if let Err(send_error) = context.send_to_hub(message) {
             error!("Failed to send message to hub: {send_error:?}");
             continue;
         }
@@ -97,6 +116,21 @@ where
     Ok(())
 }
 
+/// Whether a message from the hub should be sent through the link with the given origin.
+/// Messages don't go back to where they came from, only go to their destination when they have one,
+/// and don't go anywhere when the server answers them itself.
+pub async fn should_send(message: &Protocol, origin: &str) -> bool {
+    if message.origin == origin {
+        return false; // Don't do loopback
+    }
+
+    if let Some(destination) = &message.destination {
+        return destination == origin;
+    }
+
+    !vehicles::answers_locally(message).await
+}
+
 /// Receives messages from the HUB Channel and sends them to a Sink
 #[instrument(level = "debug", skip(writer, context))]
 pub async fn default_send_task<S>(
@@ -122,17 +156,19 @@ where
             }
         };
 
-        if message.origin.eq(&identifier) {
-            continue; // Don't do loopback
+        if !should_send(&message, identifier).await {
+            continue;
         }
 
         context.stats.write().await.stats.update_output(&message);
 
-        for future in context.on_message_output.call_all(message.clone()) {
-            if let Err(error) = future.await {
-                debug!("Dropping message: on_message_output callback returned error: {error:?}");
-                continue;
-            }
+        if let Err(error) = context
+            .on_message_output
+            .call_until_error(message.clone())
+            .await
+        {
+            debug!("Dropping message: on_message_output callback returned error: {error:?}");
+            continue;
         }
 
         if let Err(error) = writer.send((**message).clone()).await {
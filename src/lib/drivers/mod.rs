@@ -1,10 +1,13 @@
+pub mod clients;
 pub mod fake;
 pub mod generic_tasks;
+pub mod quic;
 pub mod rest;
 pub mod serial;
 pub mod tcp;
 pub mod tlog;
 pub mod udp;
+pub mod websocket;
 pub mod zenoh;
 
 use std::sync::Arc;
@@ -15,19 +18,29 @@ use tokio::sync::broadcast;
 use tracing::*;
 use url::Url;
 
-use crate::{protocol::Protocol, stats::accumulated::driver::AccumulatedDriverStatsProvider};
+use crate::{
+    callbacks::MessageCallback, protocol::Protocol,
+    stats::accumulated::driver::AccumulatedDriverStatsProvider,
+};
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum Type {
     FakeSink,
     FakeSource,
+    QuicClient,
+    QuicServer,
     Serial,
     TlogWriter,
     TlogReader,
     TcpClient,
+    TcpClientTls,
     TcpServer,
+    TcpServerTls,
+    UdpBroadcast,
     UdpClient,
+    UdpMulticast,
     UdpServer,
+    WebsocketClient,
     Zenoh,
 }
 
@@ -49,6 +62,11 @@ pub trait Driver: Send + Sync + AccumulatedDriverStatsProvider + std::fmt::Debug
 
     fn uuid(&self) -> &uuid::Uuid;
 
+    /// The clients connected to this driver, for drivers that serve multiple clients
+    fn clients(&self) -> Option<clients::ClientRegistry> {
+        None
+    }
+
     fn generate_uuid(name: &str) -> uuid::Uuid
     where
         Self: Sized,
@@ -72,7 +90,7 @@ pub trait DriverInfo: Sync + Send {
     fn cli_example_legacy(&self) -> Vec<String>;
     fn cli_example_url(&self) -> Vec<String>;
 
-    fn create_endpoint_from_url(&self, url: &Url) -> Option<Arc<dyn Driver>>;
+    fn create_endpoint_from_url(&self, url: &Url) -> Result<Arc<dyn Driver>>;
 
     fn default_scheme(&self) -> Option<&'static str> {
         self.valid_schemes().first().copied()
@@ -159,26 +177,108 @@ fn process_old_format(entry: &str) -> Option<DriverDescriptionLegacy> {
 }
 
 pub fn create_driver_from_entry(entry: &str) -> Result<Arc<dyn Driver>, String> {
-    endpoints()
-        .iter()
-        .filter_map(|endpoint| {
-            let driver_ext = &endpoint.driver_ext;
-            if let Some(legacy_entry) = process_old_format(entry) {
-                if legacy_entry.typ != endpoint.typ {
-                    return None;
+    let endpoints = endpoints();
+
+    let (driver_ext, url) = if let Some(legacy_entry) = process_old_format(entry) {
+        let driver_ext = &endpoints
+            .iter()
+            .find(|endpoint| endpoint.typ == legacy_entry.typ)
+            .ok_or_else(|| format!("Found no driver for entry: {entry}"))?
+            .driver_ext;
+
+        let url = driver_ext
+            .url_from_legacy(legacy_entry)
+            .map_err(|error| format!("{error:?}"))?;
+
+        (driver_ext, url)
+    } else {
+        let url = Url::parse(entry).map_err(|error| error.to_string())?;
+
+        let driver_ext = &endpoints
+            .iter()
+            .find(|endpoint| endpoint.driver_ext.valid_schemes().contains(&url.scheme()))
+            .ok_or_else(|| format!("Found no driver for entry: {entry}"))?
+            .driver_ext;
+
+        (driver_ext, url)
+    };
+
+    driver_ext
+        .create_endpoint_from_url(&url)
+        .map_err(|error| format!("Failed to create driver for entry {entry:?}: {error:?}"))
+}
+
+/// Returns the `host:port` of a network endpoint URL, where the host can be a hostname or an IP
+/// literal, with IPv6 ones enclosed in brackets. The port can only be omitted for schemes with a
+/// well-known one, like `ws` and `wss`
+pub fn host_port_from_url(url: &Url) -> Result<String> {
+    let host = url
+        .host_str()
+        .filter(|host| !host.is_empty())
+        .context(format!("Missing host in {url}"))?;
+    let port = url
+        .port_or_known_default()
+        .context(format!("Missing port in {url}"))?;
+
+    Ok(format!("{host}:{port}"))
+}
+
+/// Resolves a `host:port` address. Hostnames are resolved on every call, so callers can follow
+/// DNS changes on each reconnection
+pub async fn resolve_address(address: &str) -> Result<std::net::SocketAddr> {
+    tokio::net::lookup_host(address)
+        .await
+        .context(format!("Failed to resolve {address:?}"))?
+        .next()
+        .context(format!("No address found for {address:?}"))
+}
+
+/// The builders of the drivers, for the options shared by every endpoint URL
+pub(crate) trait CallbacksBuilder: Sized {
+    fn add_on_message_input(self, callback: impl MessageCallback<Arc<Protocol>>) -> Self;
+    fn add_on_message_output(self, callback: impl MessageCallback<Arc<Protocol>>) -> Self;
+}
+
+macro_rules! impl_callbacks_builder {
+    ($($builder:ty),* $(,)?) => {
+        $(
+            impl CallbacksBuilder for $builder {
+                fn add_on_message_input(self, callback: impl MessageCallback<Arc<Protocol>>) -> Self {
+                    self.on_message_input(callback)
                 }
-                return driver_ext
-                    .create_endpoint_from_url(&driver_ext.url_from_legacy(legacy_entry).unwrap());
-            }
 
-            let url = Url::parse(entry).map_err(|e| e.to_string()).ok()?;
-            if driver_ext.valid_schemes().contains(&url.scheme()) {
-                return driver_ext.create_endpoint_from_url(&url);
+                fn add_on_message_output(self, callback: impl MessageCallback<Arc<Protocol>>) -> Self {
+                    self.on_message_output(callback)
+                }
             }
-            None
-        })
-        .next()
-        .ok_or_else(|| format!("Found no driver for entry: {entry}"))
+        )*
+    };
+}
+
+impl_callbacks_builder!(
+    quic::client::QuicClientBuilder,
+    quic::server::QuicServerBuilder,
+    serial::SerialBuilder,
+    tcp::client::TcpClientBuilder,
+    tcp::server::TcpServerBuilder,
+    udp::broadcast::UdpBroadcastBuilder,
+    udp::client::UdpClientBuilder,
+    udp::multicast::UdpMulticastBuilder,
+    udp::server::UdpServerBuilder,
+    websocket::client::WebsocketClientBuilder,
+);
+
+/// Adds the script named by the `script` option of an endpoint URL to its driver
+#[cfg_attr(not(feature = "scripting"), allow(unused_variables))]
+pub(crate) fn with_script<B: CallbacksBuilder>(builder: B, url: &Url) -> Result<B> {
+    #[cfg(feature = "scripting")]
+    if let Some(script) = crate::scripting::Script::from_url(url)? {
+        return Ok(builder
+            .add_on_message_input(script.on_message_input())
+            .add_on_message_output(script.on_message_output()));
+    }
+
+    Ok(builder)
 }
 
 #[derive(Debug)]
@@ -201,14 +301,30 @@ pub fn endpoints() -> Vec<ExtInfo> {
             driver_ext: Box::new(serial::SerialInfo),
             typ: Type::Serial,
         },
+        ExtInfo {
+            driver_ext: Box::new(quic::client::QuicClientInfo),
+            typ: Type::QuicClient,
+        },
+        ExtInfo {
+            driver_ext: Box::new(quic::server::QuicServerInfo),
+            typ: Type::QuicServer,
+        },
         ExtInfo {
             driver_ext: Box::new(tcp::client::TcpClientInfo),
             typ: Type::TcpClient,
         },
+        ExtInfo {
+            driver_ext: Box::new(tcp::tls::TcpClientTlsInfo),
+            typ: Type::TcpClientTls,
+        },
         ExtInfo {
             driver_ext: Box::new(tcp::server::TcpServerInfo),
             typ: Type::TcpServer,
         },
+        ExtInfo {
+            driver_ext: Box::new(tcp::tls::TcpServerTlsInfo),
+            typ: Type::TcpServerTls,
+        },
         ExtInfo {
             driver_ext: Box::new(udp::client::UdpClientInfo),
             typ: Type::UdpClient,
@@ -217,6 +333,18 @@ pub fn endpoints() -> Vec<ExtInfo> {
             driver_ext: Box::new(udp::server::UdpServerInfo),
             typ: Type::UdpServer,
         },
+        ExtInfo {
+            driver_ext: Box::new(websocket::client::WebsocketClientInfo),
+            typ: Type::WebsocketClient,
+        },
+        ExtInfo {
+            driver_ext: Box::new(udp::broadcast::UdpBroadcastInfo),
+            typ: Type::UdpBroadcast,
+        },
+        ExtInfo {
+            driver_ext: Box::new(udp::multicast::UdpMulticastInfo),
+            typ: Type::UdpMulticast,
+        },
         ExtInfo {
             driver_ext: Box::new(fake::FakeSinkInfo),
             typ: Type::FakeSink,
@@ -309,13 +437,13 @@ mod tests {
             while let Ok(message) = hub_receiver.recv().await {
                 self.stats.write().await.stats.update_output(&message);
 
-                for future in self.on_message_input.call_all(message.clone()) {
-                    if let Err(error) = future.await {
-                        debug!(
-                            "Dropping message: on_message_input callback returned error: {error:?}"
-                        );
-                        continue;
-                    }
+                if let Err(error) = self
+                    .on_message_input
+                    .call_until_error(message.clone())
+                    .await
+                {
+                    debug!("Dropping message: on_message_input callback returned error: {error:?}");
+                    continue;
                 }
 
                 trace!("Message sent: {message:?}");
@@ -367,8 +495,8 @@ mod tests {
             vec![]
         }
 
-        fn create_endpoint_from_url(&self, _url: &url::Url) -> Option<Arc<dyn Driver>> {
-            None
+        fn create_endpoint_from_url(&self, _url: &url::Url) -> Result<Arc<dyn Driver>> {
+            Err(anyhow!("Not creatable from an URL"))
         }
     }
 
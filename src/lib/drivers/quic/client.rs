@@ -0,0 +1,220 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::Result;
+use tokio::sync::{broadcast, RwLock};
+use tracing::*;
+
+use crate::{
+    callbacks::{Callbacks, MessageCallback},
+    drivers::{
+        generic_tasks::SendReceiveContext,
+        host_port_from_url,
+        quic::{client_config, quic_send_receive_run},
+        resolve_address,
+        tcp::tls::ClientTls,
+        with_script, Driver, DriverInfo,
+    },
+    protocol::Protocol,
+    stats::{
+        accumulated::driver::{AccumulatedDriverStats, AccumulatedDriverStatsProvider},
+        driver::DriverUuid,
+    },
+};
+
+#[derive(Debug)]
+pub struct QuicClient {
+    pub remote_addr: String,
+    tls: ClientTls,
+    name: arc_swap::ArcSwap<String>,
+    uuid: DriverUuid,
+    on_message_input: Callbacks<Arc<Protocol>>,
+    on_message_output: Callbacks<Arc<Protocol>>,
+    stats: Arc<RwLock<AccumulatedDriverStats>>,
+}
+
+pub struct QuicClientBuilder(QuicClient);
+
+impl QuicClientBuilder {
+    pub fn build(self) -> QuicClient {
+        self.0
+    }
+
+    pub fn on_message_input<C>(self, callback: C) -> Self
+    where
+        C: MessageCallback<Arc<Protocol>>,
+    {
+        self.0.on_message_input.add_callback(callback.into_boxed());
+        self
+    }
+
+    pub fn on_message_output<C>(self, callback: C) -> Self
+    where
+        C: MessageCallback<Arc<Protocol>>,
+    {
+        self.0.on_message_output.add_callback(callback.into_boxed());
+        self
+    }
+}
+
+impl QuicClient {
+    #[instrument(level = "debug")]
+    pub fn builder(name: &str, remote_addr: &str, tls: ClientTls) -> QuicClientBuilder {
+        let name = Arc::new(name.to_string());
+
+        QuicClientBuilder(Self {
+            remote_addr: remote_addr.to_string(),
+            tls,
+            name: arc_swap::ArcSwap::new(name.clone()),
+            uuid: Self::generate_uuid(remote_addr),
+            on_message_input: Callbacks::default(),
+            on_message_output: Callbacks::default(),
+            stats: Arc::new(RwLock::new(AccumulatedDriverStats::new(
+                name,
+                &QuicClientInfo,
+            ))),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Driver for QuicClient {
+    #[instrument(level = "debug", skip(self, hub_sender))]
+    async fn run(&self, hub_sender: broadcast::Sender<Arc<Protocol>>) -> Result<()> {
+        let server_addr = &self.remote_addr;
+        let server_name = self.tls.server_name();
+        let config = client_config(&self.tls)?;
+
+        let context = SendReceiveContext {
+            hub_sender,
+            on_message_output: self.on_message_output.clone(),
+            on_message_input: self.on_message_input.clone(),
+            stats: self.stats.clone(),
+            driver: self.uuid,
+        };
+
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
+        let mut first = true;
+        loop {
+            if first {
+                first = false;
+            } else {
+                interval.tick().await;
+            }
+
+            // Hostnames are resolved again on each reconnection
+            let remote_addr = match resolve_address(server_addr).await {
+                Ok(remote_addr) => remote_addr,
+                Err(error) => {
+                    error!("Failed resolving QuicClient remote address: {error:?}");
+                    continue;
+                }
+            };
+
+            // Bound to any interface, so the connection migrates when the local address changes
+            let local_addr = if remote_addr.is_ipv6() {
+                SocketAddr::from((std::net::Ipv6Addr::UNSPECIFIED, 0))
+            } else {
+                SocketAddr::from((std::net::Ipv4Addr::UNSPECIFIED, 0))
+            };
+
+            let endpoint = match quinn::Endpoint::client(local_addr) {
+                Ok(endpoint) => endpoint,
+                Err(error) => {
+                    error!("Failed binding QuicClient to address {local_addr:?}: {error:?}");
+                    continue;
+                }
+            };
+
+            debug!("Trying to connect...");
+
+            let connection = match endpoint.connect_with(config.clone(), remote_addr, &server_name)
+            {
+                Ok(connecting) => match connecting.await {
+                    Ok(connection) => connection,
+                    Err(error) => {
+                        error!("Failed connecting: {error:?}");
+                        continue;
+                    }
+                },
+                Err(error) => {
+                    error!("Failed connecting: {error:?}");
+                    continue;
+                }
+            };
+
+            debug!("Successfully connected");
+
+            if let Err(reason) = quic_send_receive_run(connection, server_addr, &context).await {
+                warn!("Driver send/receive tasks closed: {reason:?}");
+            }
+
+            debug!("Restarting connection loop...");
+        }
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    fn info(&self) -> Box<dyn DriverInfo> {
+        return Box::new(QuicClientInfo);
+    }
+
+    fn name(&self) -> Arc<String> {
+        self.name.load_full()
+    }
+
+    fn uuid(&self) -> &DriverUuid {
+        &self.uuid
+    }
+}
+
+#[async_trait::async_trait]
+impl AccumulatedDriverStatsProvider for QuicClient {
+    async fn stats(&self) -> AccumulatedDriverStats {
+        self.stats.read().await.clone()
+    }
+
+    async fn reset_stats(&self) {
+        let mut stats = self.stats.write().await;
+        stats.stats.input = None;
+        stats.stats.output = None
+    }
+}
+
+pub struct QuicClientInfo;
+impl DriverInfo for QuicClientInfo {
+    fn name(&self) -> &'static str {
+        "QuicClient"
+    }
+    fn valid_schemes(&self) -> &'static [&'static str] {
+        &["quicc", "quicclient"]
+    }
+
+    fn cli_example_legacy(&self) -> Vec<String> {
+        vec![]
+    }
+
+    fn cli_example_url(&self) -> Vec<String> {
+        let first_schema = &self.valid_schemes()[0];
+        let second_schema = &self.valid_schemes()[1];
+        vec![
+            format!("{first_schema}://<HOST>:<PORT>?ca=<CA_PEM?>&cert=<CERT_PEM?>&key=<KEY_PEM?>&domain=<NAME?>")
+                .to_string(),
+            url::Url::parse(&format!("{first_schema}://ops.example.com:5762"))
+                .unwrap()
+                .to_string(),
+            url::Url::parse(&format!(
+                "{second_schema}://10.0.0.1:5762?ca=/etc/mavlink/ca.pem&domain=ops.example.com"
+            ))
+            .unwrap()
+            .to_string(),
+        ]
+    }
+
+    fn create_endpoint_from_url(&self, url: &url::Url) -> Result<Arc<dyn Driver>> {
+        let address = host_port_from_url(url)?;
+        let builder = QuicClient::builder("QuicClient", &address, ClientTls::from_url(url)?);
+
+        let builder = with_script(builder, url)?;
+
+        Ok(Arc::new(builder.build()))
+    }
+}
@@ -0,0 +1,330 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use futures::SinkExt;
+use mavlink_codec::codec::MavlinkCodec;
+use quinn::{Connection, SendDatagramError, SendStream};
+use tokio::sync::broadcast;
+use tokio_util::{
+    codec::{FramedRead, FramedWrite},
+    io::StreamReader,
+};
+use tracing::*;
+
+use crate::{
+    drivers::{
+        generic_tasks::{default_receive_task, should_send, SendReceiveContext},
+        tcp::tls::{ClientTls, ServerTls},
+    },
+    stats::messages::MessageId,
+};
+
+pub mod client;
+pub mod server;
+
+const ALPN: &[u8] = b"mavlink";
+
+type Codec = MavlinkCodec<true, true, false, false, false, false>;
+
+/// Messages that are part of a handshake, and would break it if lost: commands, parameters,
+/// missions, FTP and log transfers. Everything else is telemetry, which is better late than
+/// blocking, and goes through unreliable datagrams.
+const RELIABLE_MESSAGE_IDS: &[MessageId] = &[
+    11,  // SET_MODE
+    20,  // PARAM_REQUEST_READ
+    21,  // PARAM_REQUEST_LIST
+    22,  // PARAM_VALUE
+    23,  // PARAM_SET
+    37,  // MISSION_REQUEST_PARTIAL_LIST
+    38,  // MISSION_WRITE_PARTIAL_LIST
+    39,  // MISSION_ITEM
+    40,  // MISSION_REQUEST
+    41,  // MISSION_SET_CURRENT
+    43,  // MISSION_REQUEST_LIST
+    44,  // MISSION_COUNT
+    45,  // MISSION_CLEAR_ALL
+    46,  // MISSION_ITEM_REACHED
+    47,  // MISSION_ACK
+    51,  // MISSION_REQUEST_INT
+    73,  // MISSION_ITEM_INT
+    75,  // COMMAND_INT
+    76,  // COMMAND_LONG
+    77,  // COMMAND_ACK
+    80,  // COMMAND_CANCEL
+    110, // FILE_TRANSFER_PROTOCOL
+    117, // LOG_REQUEST_LIST
+    118, // LOG_ENTRY
+    119, // LOG_REQUEST_DATA
+    120, // LOG_DATA
+    121, // LOG_ERASE
+    122, // LOG_REQUEST_END
+    320, // PARAM_EXT_REQUEST_READ
+    321, // PARAM_EXT_REQUEST_LIST
+    322, // PARAM_EXT_VALUE
+    323, // PARAM_EXT_SET
+    324, // PARAM_EXT_ACK
+];
+
+fn is_reliable(message_id: MessageId) -> bool {
+    RELIABLE_MESSAGE_IDS.contains(&message_id)
+}
+
+/// Keep-alives let both sides notice a path change, or a dead link, within a couple of seconds
+fn transport_config() -> Arc<quinn::TransportConfig> {
+    let mut transport = quinn::TransportConfig::default();
+    transport
+        .keep_alive_interval(Some(std::time::Duration::from_secs(1)))
+        .max_idle_timeout(Some(quinn::VarInt::from_u32(10_000).into()));
+
+    Arc::new(transport)
+}
+
+fn client_config(tls: &ClientTls) -> Result<quinn::ClientConfig> {
+    let mut crypto = (*tls.config()).clone();
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+    let crypto = quinn::crypto::rustls::QuicClientConfig::try_from(crypto)
+        .context("TLS configuration not usable by QUIC")?;
+
+    let mut config = quinn::ClientConfig::new(Arc::new(crypto));
+    config.transport_config(transport_config());
+
+    Ok(config)
+}
+
+fn server_config(tls: &ServerTls) -> Result<quinn::ServerConfig> {
+    let mut crypto = (*tls.config()).clone();
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+    let crypto = quinn::crypto::rustls::QuicServerConfig::try_from(crypto)
+        .context("TLS configuration not usable by QUIC")?;
+
+    let mut config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    config.transport_config(transport_config());
+    // Clients changing their address, like LTE modems do, keep their connection
+    config.migration(true);
+
+    Ok(config)
+}
+
+/// Runs the send and receive tasks over a QUIC connection, until it is lost.
+/// Each side sends reliable messages over its own unidirectional stream, so the peer only has to
+/// accept it once there is something to read.
+#[instrument(level = "debug", skip(connection, context))]
+async fn quic_send_receive_run(
+    connection: Connection,
+    identifier: &str,
+    context: &SendReceiveContext,
+) -> Result<()> {
+    let stream = connection.open_uni().await?;
+    let mut writer = FramedWrite::new(stream, Codec::default());
+
+    let datagrams = futures::stream::unfold(connection.clone(), |connection| async move {
+        let datagram = connection
+            .read_datagram()
+            .await
+            .map_err(std::io::Error::other);
+
+        Some((datagram, connection))
+    });
+    let mut datagram_reader = FramedRead::new(StreamReader::new(datagrams), Codec::default());
+
+    tokio::select! {
+        result = quic_send_task(&connection, &mut writer, identifier, context) => {
+            if let Err(error) = result {
+                error!("Error in send task for {identifier}: {error:?}");
+            }
+        }
+        result = default_receive_task(&mut datagram_reader, identifier, context) => {
+            if let Err(error) = result {
+                error!("Error in datagram receive task for {identifier}: {error:?}");
+            }
+        }
+        result = async {
+            let stream = connection.accept_uni().await?;
+            let mut stream_reader = FramedRead::new(stream, Codec::default());
+
+            default_receive_task(&mut stream_reader, identifier, context).await
+        } => {
+            if let Err(error) = result {
+                error!("Error in stream receive task for {identifier}: {error:?}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Receives messages from the HUB Channel and sends them as datagrams, or over the reliable stream
+#[instrument(level = "debug", skip(connection, writer, context))]
+async fn quic_send_task(
+    connection: &Connection,
+    writer: &mut FramedWrite<SendStream, Codec>,
+    identifier: &str,
+    context: &SendReceiveContext,
+) -> Result<()> {
+    let mut hub_receiver = context.hub_sender.subscribe();
+
+    loop {
+        let message = match hub_receiver.recv().await {
+            Ok(message) => message,
+            Err(broadcast::error::RecvError::Closed) => {
+                error!("Hub channel closed!");
+                break;
+            }
+            Err(broadcast::error::RecvError::Lagged(count)) => {
+                warn!("Channel lagged by {count} messages.");
+                continue;
+            }
+        };
+
+        if !should_send(&message, identifier).await {
+            continue;
+        }
+
+        context.stats.write().await.stats.update_output(&message);
+
+        if let Err(error) = context
+            .on_message_output
+            .call_until_error(message.clone())
+            .await
+        {
+            debug!("Dropping message: on_message_output callback returned error: {error:?}");
+            continue;
+        }
+
+        let reliable = is_reliable(message.message_id())
+            || match connection.send_datagram(Bytes::copy_from_slice(message.as_slice())) {
+                Ok(()) => false,
+                // Over the path MTU, so it has to be split by the stream
+                Err(SendDatagramError::TooLarge) => true,
+                Err(error) => {
+                    error!("Failed to send datagram: {error:?}");
+                    break;
+                }
+            };
+
+        if reliable {
+            if let Err(error) = writer.send((**message).clone()).await {
+                error!("Failed to send message: {error:?}");
+                break;
+            }
+        }
+
+        trace!("Message sent to {identifier}: {:?}", message.as_slice());
+    }
+
+    debug!("Driver sender task stopped!");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use bytes::{BufMut, BytesMut};
+    use mavlink::{
+        ardupilotmega::{MavMessage, COMMAND_LONG_DATA, HEARTBEAT_DATA},
+        MavHeader,
+    };
+    use mavlink_codec::{v2::V2Packet, Packet};
+
+    use super::*;
+    use crate::{
+        drivers::{
+            quic::{client::QuicClient, server::QuicServer},
+            Driver,
+        },
+        protocol::Protocol,
+    };
+
+    fn write_temp(dir: &tempfile::TempDir, name: &str, content: &str) -> PathBuf {
+        let path = dir.path().join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn message(data: &MavMessage) -> Arc<Protocol> {
+        let mut writer = BytesMut::with_capacity(V2Packet::MAX_PACKET_SIZE).writer();
+        mavlink::write_v2_msg(&mut writer, MavHeader::default(), data).unwrap();
+
+        let packet = Packet::V2(V2Packet::new(writer.into_inner().freeze()));
+
+        Arc::new(Protocol::new("test", packet))
+    }
+
+    #[tokio::test]
+    async fn test_datagrams_and_stream_over_localhost() {
+        let certificate =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let cert = write_temp(&dir, "server.pem", &certificate.cert.pem());
+        let key = write_temp(&dir, "server.key", &certificate.key_pair.serialize_pem());
+
+        // A port just handed out by the system, as the server binds its own endpoint
+        let local_addr = std::net::UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let server = Arc::new(
+            QuicServer::builder(
+                "test",
+                &local_addr,
+                ServerTls::new(cert.clone(), key, None).unwrap(),
+            )
+            .build(),
+        );
+        let client = Arc::new(
+            QuicClient::builder(
+                "test",
+                &local_addr,
+                ClientTls::new("localhost", Some(cert), None).unwrap(),
+            )
+            .build(),
+        );
+
+        let (server_hub, mut server_receiver) = broadcast::channel(16);
+        let (client_hub, _client_receiver) = broadcast::channel(16);
+
+        tokio::spawn({
+            let server = server.clone();
+            async move { server.run(server_hub).await }
+        });
+        tokio::spawn({
+            let client = client.clone();
+            let client_hub = client_hub.clone();
+            async move { client.run(client_hub).await }
+        });
+
+        tokio::time::timeout(tokio::time::Duration::from_secs(5), async {
+            while server.clients().unwrap().is_empty().await {
+                tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        let heartbeat = message(&MavMessage::HEARTBEAT(HEARTBEAT_DATA::default()));
+        let command = message(&MavMessage::COMMAND_LONG(COMMAND_LONG_DATA::default()));
+        client_hub.send(heartbeat.clone()).unwrap();
+        client_hub.send(command.clone()).unwrap();
+
+        let mut received = vec![];
+        tokio::time::timeout(tokio::time::Duration::from_secs(5), async {
+            while received.len() < 2 {
+                let message = server_receiver.recv().await.unwrap();
+                received.push(message.message_id());
+            }
+        })
+        .await
+        .unwrap();
+
+        received.sort();
+        assert_eq!(received, vec![0, 76]);
+        assert!(!is_reliable(heartbeat.message_id()));
+        assert!(is_reliable(command.message_id()));
+    }
+}
@@ -0,0 +1,293 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::{Context, Result};
+use ipnet::IpNet;
+use tokio::sync::{broadcast, RwLock};
+use tracing::*;
+
+use crate::{
+    callbacks::{Callbacks, MessageCallback},
+    drivers::{
+        clients::{parse_allowlist, ClientRegistry},
+        generic_tasks::SendReceiveContext,
+        host_port_from_url,
+        quic::{quic_send_receive_run, server_config},
+        resolve_address,
+        tcp::tls::ServerTls,
+        with_script, Driver, DriverInfo,
+    },
+    protocol::Protocol,
+    stats::{
+        accumulated::driver::{AccumulatedDriverStats, AccumulatedDriverStatsProvider},
+        driver::DriverUuid,
+    },
+};
+
+#[derive(Debug)]
+pub struct QuicServer {
+    pub local_addr: String,
+    tls: ServerTls,
+    name: arc_swap::ArcSwap<String>,
+    uuid: DriverUuid,
+    on_message_input: Callbacks<Arc<Protocol>>,
+    on_message_output: Callbacks<Arc<Protocol>>,
+    stats: Arc<RwLock<AccumulatedDriverStats>>,
+    clients: ClientRegistry,
+}
+
+pub struct QuicServerBuilder(QuicServer);
+
+impl QuicServerBuilder {
+    pub fn build(self) -> QuicServer {
+        self.0
+    }
+
+    /// Connections beyond this number are refused
+    pub fn max_clients(mut self, max_clients: usize) -> Self {
+        self.0.clients = self.0.clients.with_max_clients(max_clients);
+        self
+    }
+
+    /// Connections from outside these networks are refused
+    pub fn allow(mut self, allowlist: Vec<IpNet>) -> Self {
+        self.0.clients = self.0.clients.with_allowlist(allowlist);
+        self
+    }
+
+    pub fn on_message_input<C>(self, callback: C) -> Self
+    where
+        C: MessageCallback<Arc<Protocol>>,
+    {
+        self.0.on_message_input.add_callback(callback.into_boxed());
+        self
+    }
+
+    pub fn on_message_output<C>(self, callback: C) -> Self
+    where
+        C: MessageCallback<Arc<Protocol>>,
+    {
+        self.0.on_message_output.add_callback(callback.into_boxed());
+        self
+    }
+}
+
+impl QuicServer {
+    #[instrument(level = "debug")]
+    pub fn builder(name: &str, local_addr: &str, tls: ServerTls) -> QuicServerBuilder {
+        let name = Arc::new(name.to_string());
+
+        QuicServerBuilder(Self {
+            local_addr: local_addr.to_string(),
+            tls,
+            name: arc_swap::ArcSwap::new(name.clone()),
+            uuid: Self::generate_uuid(local_addr),
+            on_message_input: Callbacks::default(),
+            on_message_output: Callbacks::default(),
+            stats: Arc::new(RwLock::new(AccumulatedDriverStats::new(
+                name,
+                &QuicServerInfo,
+            ))),
+            clients: ClientRegistry::default(),
+        })
+    }
+
+    async fn accept_client(&self, incoming: quinn::Incoming, context: &SendReceiveContext) {
+        // Clients are known by the address they connected from, even after they migrate
+        let remote_addr = incoming.remote_address();
+
+        if let Err(rejection) = self.clients.admit(&remote_addr).await {
+            warn!("Refusing QUIC client {remote_addr}: {rejection}");
+            context.stats.write().await.stats.update_rejected();
+            incoming.refuse();
+            return;
+        }
+
+        self.clients.insert(remote_addr).await;
+
+        let task = tokio::spawn({
+            let clients = self.clients.clone();
+            let context = clients.client_context(remote_addr, context);
+            async move {
+                let result = QuicServer::handle_client(incoming, remote_addr, context).await;
+
+                clients.remove(&remote_addr).await;
+
+                result
+            }
+        });
+
+        self.clients
+            .set_task(&remote_addr, task.abort_handle())
+            .await;
+    }
+
+    /// Handles communication with a single client
+    #[instrument(level = "debug", skip(incoming, context))]
+    async fn handle_client(
+        incoming: quinn::Incoming,
+        remote_addr: SocketAddr,
+        context: SendReceiveContext,
+    ) -> Result<()> {
+        let connection = match incoming.await {
+            Ok(connection) => connection,
+            Err(error) => {
+                warn!("QUIC handshake failed: {error:?}");
+                return Ok(());
+            }
+        };
+
+        debug!("New QUIC client");
+
+        if let Err(reason) =
+            quic_send_receive_run(connection, &remote_addr.to_string(), &context).await
+        {
+            warn!("Driver send/receive tasks closed: {reason:?}");
+        }
+
+        debug!("QUIC Client connection terminated");
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Driver for QuicServer {
+    #[instrument(level = "debug", skip(self, hub_sender))]
+    async fn run(&self, hub_sender: broadcast::Sender<Arc<Protocol>>) -> Result<()> {
+        let config = server_config(&self.tls)?;
+
+        let context = SendReceiveContext {
+            hub_sender,
+            on_message_output: self.on_message_output.clone(),
+            on_message_input: self.on_message_input.clone(),
+            stats: self.stats.clone(),
+            driver: self.uuid,
+        };
+
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
+        let mut first = true;
+        let endpoint = loop {
+            if first {
+                first = false;
+            } else {
+                interval.tick().await;
+            }
+
+            let local_addr = match resolve_address(&self.local_addr).await {
+                Ok(local_addr) => local_addr,
+                Err(error) => {
+                    error!("Failed resolving QuicServer local address: {error:?}");
+                    continue;
+                }
+            };
+
+            debug!("Trying to bind to address {local_addr:?}...");
+
+            match quinn::Endpoint::server(config.clone(), local_addr) {
+                Ok(endpoint) => break endpoint,
+                Err(error) => {
+                    error!("Failed to bind QUIC Server to {local_addr:?}: {error:?}");
+                }
+            }
+        };
+
+        debug!("Waiting for clients...");
+
+        while let Some(incoming) = endpoint.accept().await {
+            self.accept_client(incoming, &context).await;
+        }
+
+        debug!("QUIC endpoint closed");
+
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    fn info(&self) -> Box<dyn DriverInfo> {
+        return Box::new(QuicServerInfo);
+    }
+
+    fn name(&self) -> Arc<String> {
+        self.name.load_full()
+    }
+
+    fn uuid(&self) -> &DriverUuid {
+        &self.uuid
+    }
+
+    fn clients(&self) -> Option<ClientRegistry> {
+        Some(self.clients.clone())
+    }
+}
+
+#[async_trait::async_trait]
+impl AccumulatedDriverStatsProvider for QuicServer {
+    async fn stats(&self) -> AccumulatedDriverStats {
+        self.stats.read().await.clone()
+    }
+
+    async fn reset_stats(&self) {
+        let mut stats = self.stats.write().await;
+        stats.stats.input = None;
+        stats.stats.output = None;
+        stats.stats.rejected = 0;
+
+        self.clients.reset_stats().await;
+    }
+}
+
+pub struct QuicServerInfo;
+impl DriverInfo for QuicServerInfo {
+    fn name(&self) -> &'static str {
+        "QuicServer"
+    }
+    fn valid_schemes(&self) -> &'static [&'static str] {
+        &["quics", "quicserver"]
+    }
+
+    fn cli_example_legacy(&self) -> Vec<String> {
+        vec![]
+    }
+
+    fn cli_example_url(&self) -> Vec<String> {
+        let first_schema = &self.valid_schemes()[0];
+        let second_schema = &self.valid_schemes()[1];
+        vec![
+            format!("{first_schema}://<IP>:<PORT>?cert=<CERT_PEM>&key=<KEY_PEM>&client_ca=<CA_PEM?>&allow=<NETWORKS?>&max_clients=<NUMBER?>")
+                .to_string(),
+            url::Url::parse(&format!(
+                "{first_schema}://0.0.0.0:5762?cert=/etc/mavlink/server.pem&key=/etc/mavlink/server.key"
+            ))
+            .unwrap()
+            .to_string(),
+            url::Url::parse(&format!(
+                "{second_schema}://[::]:5762?cert=/etc/mavlink/server.pem&key=/etc/mavlink/server.key&client_ca=/etc/mavlink/ca.pem&max_clients=16"
+            ))
+            .unwrap()
+            .to_string(),
+        ]
+    }
+
+    fn create_endpoint_from_url(&self, url: &url::Url) -> Result<Arc<dyn Driver>> {
+        let address = host_port_from_url(url)?;
+        let mut builder = QuicServer::builder("QuicServer", &address, ServerTls::from_url(url)?);
+
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "max_clients" => {
+                    builder = builder.max_clients(
+                        value
+                            .parse()
+                            .context(format!("Invalid max_clients {value:?}"))?,
+                    )
+                }
+                "allow" => builder = builder.allow(parse_allowlist(&value)?),
+                _ => (),
+            }
+        }
+
+        let builder = with_script(builder, url)?;
+
+        Ok(Arc::new(builder.build()))
+    }
+}
@@ -2,21 +2,20 @@ pub mod data;
 
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use axum::extract::ws;
 use tokio::sync::{broadcast, RwLock};
 use tracing::*;
 
 use crate::{
     callbacks::{Callbacks, MessageCallback},
-    drivers::{generic_tasks::SendReceiveContext, Driver, DriverInfo},
-    mavlink_json::MAVLinkJSON,
+    drivers::{clients::ClientRegistry, generic_tasks::SendReceiveContext, Driver, DriverInfo},
     protocol::Protocol,
     stats::{
         accumulated::driver::{AccumulatedDriverStats, AccumulatedDriverStatsProvider},
         driver::DriverUuid,
     },
-    web::routes::v1::rest::websocket,
+    web::routes::v1::rest::websocket::{self, ClientInput},
 };
 
 #[derive(Debug)]
@@ -69,39 +68,26 @@ impl Rest {
     #[instrument(level = "debug", skip_all)]
     async fn receive_task(
         context: &SendReceiveContext,
-        ws_receiver: &mut broadcast::Receiver<String>,
+        ws_receiver: &mut broadcast::Receiver<ClientInput>,
     ) -> Result<()> {
-        while let Ok(message) = ws_receiver.recv().await {
-            let Ok(content) =
-                json5::from_str::<MAVLinkJSON<mavlink::ardupilotmega::MavMessage>>(&message)
-            else {
-                debug!("Failed to parse message, not a valid MAVLinkMessage: {message:?}");
-                continue;
-            };
-
-            let bus_message = Arc::new(Protocol::from_mavlink_raw(
-                content.header.inner,
-                &content.message,
-                "Ws",
-            ));
+        let clients = websocket::clients();
 
-            trace!("Received message: {bus_message:?}");
-
-            context.stats.write().await.stats.update_input(&bus_message);
-
-            for future in context.on_message_input.call_all(bus_message.clone()) {
-                if let Err(error) = future.await {
-                    debug!("Dropping message: on_message_input callback returned error: {error:?}");
+        loop {
+            let ClientInput { address, message } = match ws_receiver.recv().await {
+                Ok(input) => input,
+                Err(broadcast::error::RecvError::Closed) => {
+                    error!("Client channel closed!");
+                    break;
+                }
+                Err(broadcast::error::RecvError::Lagged(count)) => {
+                    warn!("Client channel lagged by {count} messages.");
                     continue;
                 }
-            }
+            };
 
-            if let Err(error) = context.hub_sender.send(bus_message) {
-                error!("Failed to send message to hub: {error:?}");
-                continue;
-            }
+            clients.update_input(&address, &message).await;
 
-            trace!("Message sent to hub");
+            Self::send_to_hub(context, message).await;
         }
 
         debug!("Driver receiver task stopped!");
@@ -109,13 +95,43 @@ impl Rest {
         Ok(())
     }
 
+    async fn send_to_hub(context: &SendReceiveContext, bus_message: Arc<Protocol>) {
+        trace!("Received message: {bus_message:?}");
+
+        context.stats.write().await.stats.update_input(&bus_message);
+
+        if let Err(error) = context
+            .on_message_input
+            .call_until_error(bus_message.clone())
+            .await
+        {
+            debug!("Dropping message: on_message_input callback returned error: {error:?}");
+            return;
+        }
+
+        if let Err(error) = context.send_to_hub(bus_message) {
+            error!("Failed to send message to hub: {error:?}");
+            return;
+        }
+
+        trace!("Message sent to hub");
+    }
+
+    /// HTTP callers have no connection to close, so the silent ones are expired here
+    #[instrument(level = "debug", skip_all)]
+    async fn expire_task() -> Result<()> {
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(5));
+
+        loop {
+            interval.tick().await;
+            websocket::expire_http_clients().await;
+        }
+    }
+
     #[instrument(level = "debug", skip_all)]
     async fn send_task(context: &SendReceiveContext) -> Result<()> {
         let mut hub_receiver = context.hub_sender.subscribe();
 
-        let origin = "Ws";
-        let uuid = uuid::Uuid::new_v5(&uuid::Uuid::NAMESPACE_URL, origin.as_bytes());
-
         loop {
             let message = match hub_receiver.recv().await {
                 Ok(message) => message,
@@ -129,21 +145,21 @@ impl Rest {
                 }
             };
 
-            if message.origin.eq(origin) {
-                continue; // Don't do loopback
-            }
-
             context.stats.write().await.stats.update_output(&message);
 
-            for future in context.on_message_output.call_all(message.clone()) {
-                if let Err(error) = future.await {
-                    debug!(
-                        "Dropping message: on_message_output callback returned error: {error:?}"
-                    );
-                    continue;
-                }
+            if let Err(error) = context
+                .on_message_output
+                .call_until_error(message.clone())
+                .await
+            {
+                debug!("Dropping message: on_message_output callback returned error: {error:?}");
+                continue;
             }
 
+            // Messages are also sent back to the other clients, but never to the one they came from.
+            // Binary clients also get the messages that can't be decoded by the dialect.
+            websocket::broadcast(&message, ws::Message::Binary(message.as_slice().to_vec())).await;
+
             let Ok(mavlink_json) = message.to_mavlink_json().await else {
                 continue;
             };
@@ -151,7 +167,7 @@ impl Rest {
             let json_string = parse_query(&mavlink_json);
             data::update((mavlink_json.header, mavlink_json.message));
 
-            websocket::broadcast(uuid, ws::Message::Text(json_string)).await;
+            websocket::broadcast(&message, ws::Message::Text(json_string)).await;
         }
 
         debug!("Driver sender task stopped!");
@@ -175,6 +191,7 @@ impl Driver for Rest {
             on_message_output: self.on_message_output.clone(),
             on_message_input: self.on_message_input.clone(),
             stats: self.stats.clone(),
+            driver: self.uuid,
         };
 
         let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
@@ -199,6 +216,11 @@ impl Driver for Rest {
                         error!("Error in rest receive task: {e:?}");
                     }
                 }
+                result = Rest::expire_task() => {
+                    if let Err(e) = result {
+                        error!("Error in rest expire task: {e:?}");
+                    }
+                }
             }
         }
     }
@@ -215,6 +237,10 @@ impl Driver for Rest {
     fn uuid(&self) -> &DriverUuid {
         &self.uuid
     }
+
+    fn clients(&self) -> Option<ClientRegistry> {
+        Some(websocket::clients())
+    }
 }
 
 #[async_trait::async_trait]
@@ -226,7 +252,9 @@ impl AccumulatedDriverStatsProvider for Rest {
     async fn reset_stats(&self) {
         let mut stats = self.stats.write().await;
         stats.stats.input = None;
-        stats.stats.output = None
+        stats.stats.output = None;
+
+        websocket::clients().reset_stats().await;
     }
 }
 
@@ -248,7 +276,7 @@ impl DriverInfo for RestInfo {
         vec![]
     }
 
-    fn create_endpoint_from_url(&self, _url: &url::Url) -> Option<Arc<dyn Driver>> {
-        None
+    fn create_endpoint_from_url(&self, _url: &url::Url) -> Result<Arc<dyn Driver>> {
+        Err(anyhow!("Rest driver can't be created from an URL"))
     }
 }
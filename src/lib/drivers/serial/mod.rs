@@ -12,7 +12,7 @@ use crate::{
     callbacks::{Callbacks, MessageCallback},
     drivers::{
         generic_tasks::{default_send_receive_run, SendReceiveContext},
-        Driver, DriverInfo,
+        with_script, Driver, DriverInfo,
     },
     protocol::Protocol,
     stats::{
@@ -84,6 +84,7 @@ impl Driver for Serial {
             on_message_output: self.on_message_output.clone(),
             on_message_input: self.on_message_input.clone(),
             stats: self.stats.clone(),
+            driver: self.uuid,
         };
 
         let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
@@ -182,7 +183,7 @@ impl DriverInfo for SerialInfo {
         ]
     }
 
-    fn create_endpoint_from_url(&self, url: &url::Url) -> Option<Arc<dyn Driver>> {
+    fn create_endpoint_from_url(&self, url: &url::Url) -> Result<Arc<dyn Driver>> {
         let port_name = url.path().to_string();
         let baud_rate = url
             .query_pairs()
@@ -195,8 +196,10 @@ impl DriverInfo for SerialInfo {
             })
             .unwrap_or(115200); // Commun baudrate between flight controllers
 
-        Some(Arc::new(
-            Serial::builder("Serial", &port_name, baud_rate).build(),
-        ))
+        let builder = Serial::builder("Serial", &port_name, baud_rate);
+
+        let builder = with_script(builder, url)?;
+
+        Ok(Arc::new(builder.build()))
     }
 }
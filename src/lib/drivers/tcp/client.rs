@@ -1,20 +1,22 @@
 use std::sync::Arc;
 
 use anyhow::Result;
-use futures::StreamExt;
-use mavlink_codec::codec::MavlinkCodec;
 use tokio::{
     net::TcpStream,
     sync::{broadcast, RwLock},
 };
-use tokio_util::codec::Framed;
 use tracing::*;
 
 use crate::{
     callbacks::{Callbacks, MessageCallback},
     drivers::{
-        generic_tasks::{default_send_receive_run, SendReceiveContext},
-        Driver, DriverInfo,
+        generic_tasks::SendReceiveContext,
+        host_port_from_url,
+        tcp::{
+            tcp_send_receive_run,
+            tls::{ClientTls, TcpClientTlsInfo, TLS_HANDSHAKE_TIMEOUT},
+        },
+        with_script, Driver, DriverInfo,
     },
     protocol::Protocol,
     stats::{
@@ -26,6 +28,7 @@ use crate::{
 #[derive(Debug)]
 pub struct TcpClient {
     pub remote_addr: String,
+    tls: Option<ClientTls>,
     name: arc_swap::ArcSwap<String>,
     uuid: DriverUuid,
     on_message_input: Callbacks<Arc<Protocol>>,
@@ -40,6 +43,16 @@ impl TcpClientBuilder {
         self.0
     }
 
+    /// Goes through a TLS handshake on every connection
+    pub fn tls(mut self, tls: ClientTls) -> Self {
+        self.0.tls = Some(tls);
+        self.0.stats = Arc::new(RwLock::new(AccumulatedDriverStats::new(
+            self.0.name.load_full(),
+            &TcpClientTlsInfo,
+        )));
+        self
+    }
+
     pub fn on_message_input<C>(self, callback: C) -> Self
     where
         C: MessageCallback<Arc<Protocol>>,
@@ -64,6 +77,7 @@ impl TcpClient {
 
         TcpClientBuilder(Self {
             remote_addr: remote_addr.to_string(),
+            tls: None,
             name: arc_swap::ArcSwap::new(name.clone()),
             uuid: Self::generate_uuid(remote_addr),
             on_message_input: Callbacks::default(),
@@ -87,6 +101,7 @@ impl Driver for TcpClient {
             on_message_output: self.on_message_output.clone(),
             on_message_input: self.on_message_input.clone(),
             stats: self.stats.clone(),
+            driver: self.uuid,
         };
 
         let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
@@ -110,12 +125,24 @@ impl Driver for TcpClient {
 
             debug!("Successfully connected");
 
-            let codec = MavlinkCodec::<true, true, false, false, false, false>::default();
-            let (writer, reader) = Framed::new(stream, codec).split();
+            let result = match &self.tls {
+                Some(tls) => {
+                    match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, tls.connect(stream)).await {
+                        Ok(Ok(stream)) => tcp_send_receive_run(stream, server_addr, &context).await,
+                        Ok(Err(error)) => {
+                            error!("TLS handshake failed: {error:?}");
+                            continue;
+                        }
+                        Err(_) => {
+                            error!("TLS handshake timed out after {TLS_HANDSHAKE_TIMEOUT:?}");
+                            continue;
+                        }
+                    }
+                }
+                None => tcp_send_receive_run(stream, server_addr, &context).await,
+            };
 
-            if let Err(reason) =
-                default_send_receive_run(writer, reader, server_addr, &context).await
-            {
+            if let Err(reason) = result {
                 warn!("Driver send/receive tasks closed: {reason:?}");
             }
 
@@ -125,6 +152,10 @@ impl Driver for TcpClient {
 
     #[instrument(level = "debug", skip(self))]
     fn info(&self) -> Box<dyn DriverInfo> {
+        if self.tls.is_some() {
+            return Box::new(TcpClientTlsInfo);
+        }
+
         return Box::new(TcpClientInfo);
     }
 
@@ -183,11 +214,12 @@ impl DriverInfo for TcpClientInfo {
         ]
     }
 
-    fn create_endpoint_from_url(&self, url: &url::Url) -> Option<Arc<dyn Driver>> {
-        let host = url.host_str().unwrap();
-        let port = url.port().unwrap();
-        Some(Arc::new(
-            TcpClient::builder("TcpClient", &format!("{host}:{port}")).build(),
-        ))
+    fn create_endpoint_from_url(&self, url: &url::Url) -> Result<Arc<dyn Driver>> {
+        let address = host_port_from_url(url)?;
+        let builder = TcpClient::builder("TcpClient", &address);
+
+        let builder = with_script(builder, url)?;
+
+        Ok(Arc::new(builder.build()))
     }
 }
@@ -1,2 +1,28 @@
+use anyhow::Result;
+use futures::StreamExt;
+use mavlink_codec::codec::MavlinkCodec;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::Framed;
+use tracing::*;
+
+use super::generic_tasks::{default_send_receive_run, SendReceiveContext};
+
 pub mod client;
 pub mod server;
+pub mod tls;
+
+/// Runs the send and receive tasks over a connected stream, either plain TCP or TLS
+#[instrument(level = "debug", skip(stream, context))]
+async fn tcp_send_receive_run<S>(
+    stream: S,
+    identifier: &str,
+    context: &SendReceiveContext,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + std::marker::Unpin,
+{
+    let codec = MavlinkCodec::<true, true, false, false, false, false>::default();
+    let (writer, reader) = Framed::new(stream, codec).split();
+
+    default_send_receive_run(writer, reader, identifier, context).await
+}
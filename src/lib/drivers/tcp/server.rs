@@ -1,20 +1,24 @@
 use std::{net::SocketAddr, sync::Arc};
 
-use anyhow::{anyhow, Result};
-use futures::StreamExt;
-use mavlink_codec::codec::MavlinkCodec;
+use anyhow::{Context, Result};
+use ipnet::IpNet;
 use tokio::{
     net::{TcpListener, TcpStream},
     sync::{broadcast, RwLock},
 };
-use tokio_util::codec::Framed;
 use tracing::*;
 
 use crate::{
     callbacks::{Callbacks, MessageCallback},
     drivers::{
-        generic_tasks::{default_send_receive_run, SendReceiveContext},
-        Driver, DriverInfo,
+        clients::{parse_allowlist, ClientRegistry},
+        generic_tasks::SendReceiveContext,
+        host_port_from_url, resolve_address,
+        tcp::{
+            tcp_send_receive_run,
+            tls::{ServerTls, TcpServerTlsInfo, TLS_HANDSHAKE_TIMEOUT},
+        },
+        with_script, Driver, DriverInfo,
     },
     protocol::Protocol,
     stats::{
@@ -26,11 +30,14 @@ use crate::{
 #[derive(Debug)]
 pub struct TcpServer {
     pub local_addr: String,
+    pub idle_timeout: Option<tokio::time::Duration>,
+    tls: Option<ServerTls>,
     name: arc_swap::ArcSwap<String>,
     uuid: DriverUuid,
     on_message_input: Callbacks<Arc<Protocol>>,
     on_message_output: Callbacks<Arc<Protocol>>,
     stats: Arc<RwLock<AccumulatedDriverStats>>,
+    clients: ClientRegistry,
 }
 
 pub struct TcpServerBuilder(TcpServer);
@@ -40,6 +47,34 @@ impl TcpServerBuilder {
         self.0
     }
 
+    /// Connections beyond this number are refused
+    pub fn max_clients(mut self, max_clients: usize) -> Self {
+        self.0.clients = self.0.clients.with_max_clients(max_clients);
+        self
+    }
+
+    /// Connections from outside these networks are refused
+    pub fn allow(mut self, allowlist: Vec<IpNet>) -> Self {
+        self.0.clients = self.0.clients.with_allowlist(allowlist);
+        self
+    }
+
+    /// Requires every client to go through a TLS handshake
+    pub fn tls(mut self, tls: ServerTls) -> Self {
+        self.0.tls = Some(tls);
+        self.0.stats = Arc::new(RwLock::new(AccumulatedDriverStats::new(
+            self.0.name.load_full(),
+            &TcpServerTlsInfo,
+        )));
+        self
+    }
+
+    /// Clients that don't send anything for this long are disconnected
+    pub fn idle_timeout(mut self, idle_timeout: tokio::time::Duration) -> Self {
+        self.0.idle_timeout = Some(idle_timeout);
+        self
+    }
+
     pub fn on_message_input<C>(self, callback: C) -> Self
     where
         C: MessageCallback<Arc<Protocol>>,
@@ -64,6 +99,8 @@ impl TcpServer {
 
         TcpServerBuilder(Self {
             local_addr: local_addr.to_string(),
+            idle_timeout: None,
+            tls: None,
             name: arc_swap::ArcSwap::new(name.clone()),
             uuid: Self::generate_uuid(local_addr),
             on_message_input: Callbacks::default(),
@@ -72,23 +109,101 @@ impl TcpServer {
                 name,
                 &TcpServerInfo,
             ))),
+            clients: ClientRegistry::default(),
         })
     }
 
+    /// Creates a builder with the options shared by the plain and TLS variants
+    pub(crate) fn builder_from_url(name: &str, url: &url::Url) -> Result<TcpServerBuilder> {
+        let address = host_port_from_url(url)?;
+        let mut builder = TcpServer::builder(name, &address);
+
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "max_clients" => {
+                    builder = builder.max_clients(
+                        value
+                            .parse()
+                            .context(format!("Invalid max_clients {value:?}"))?,
+                    )
+                }
+                "allow" => builder = builder.allow(parse_allowlist(&value)?),
+                "idle_timeout" => {
+                    let seconds = value
+                        .parse()
+                        .context(format!("Invalid idle_timeout {value:?}"))?;
+                    builder = builder.idle_timeout(tokio::time::Duration::from_secs_f64(seconds));
+                }
+                _ => (),
+            }
+        }
+
+        let builder = with_script(builder, url)?;
+
+        Ok(builder)
+    }
+
+    async fn accept_client(
+        &self,
+        socket: TcpStream,
+        remote_addr: SocketAddr,
+        context: &SendReceiveContext,
+    ) {
+        if let Err(rejection) = self.clients.admit(&remote_addr).await {
+            warn!("Refusing TCP client {remote_addr}: {rejection}");
+            context.stats.write().await.stats.update_rejected();
+            return;
+        }
+
+        self.clients.insert(remote_addr).await;
+
+        let task = tokio::spawn({
+            let clients = self.clients.clone();
+            let context = clients.client_context(remote_addr, context);
+            let tls = self.tls.clone();
+            async move {
+                let result =
+                    TcpServer::handle_client(socket, remote_addr.to_string(), tls, context).await;
+
+                clients.remove(&remote_addr).await;
+
+                result
+            }
+        });
+
+        self.clients
+            .set_task(&remote_addr, task.abort_handle())
+            .await;
+    }
+
     /// Handles communication with a single client
-    #[instrument(level = "debug", skip(stream, context))]
+    #[instrument(level = "debug", skip(stream, tls, context))]
     async fn handle_client(
         stream: TcpStream,
         remote_addr: String,
+        tls: Option<ServerTls>,
         context: SendReceiveContext,
     ) -> Result<()> {
         debug!("New TCP client");
 
-        let codec = MavlinkCodec::<true, true, false, false, false, false>::default();
-        let (writer, reader) = Framed::new(stream, codec).split();
+        let result = match tls {
+            Some(tls) => {
+                match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, tls.accept(stream)).await {
+                    Ok(Ok(stream)) => tcp_send_receive_run(stream, &remote_addr, &context).await,
+                    Ok(Err(error)) => {
+                        warn!("TLS handshake failed: {error:?}");
+                        return Ok(());
+                    }
+                    Err(_) => {
+                        warn!("TLS handshake timed out after {TLS_HANDSHAKE_TIMEOUT:?}");
+                        return Ok(());
+                    }
+                }
+            }
+            None => tcp_send_receive_run(stream, &remote_addr, &context).await,
+        };
 
-        if let Err(reason) = default_send_receive_run(writer, reader, &remote_addr, &context).await
-        {
+        if let Err(reason) = result {
             warn!("Driver send/receive tasks closed: {reason:?}");
         }
 
@@ -102,53 +217,66 @@ impl TcpServer {
 impl Driver for TcpServer {
     #[instrument(level = "debug", skip(self, hub_sender))]
     async fn run(&self, hub_sender: broadcast::Sender<Arc<Protocol>>) -> Result<()> {
-        let local_addr = self.local_addr.parse::<SocketAddr>()?;
-
         let context = SendReceiveContext {
             hub_sender,
             on_message_output: self.on_message_output.clone(),
             on_message_input: self.on_message_input.clone(),
             stats: self.stats.clone(),
+            driver: self.uuid,
         };
 
         let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
         let mut first = true;
-        loop {
+        let listener = loop {
             if first {
                 first = false;
             } else {
                 interval.tick().await;
             }
 
+            let local_addr = match resolve_address(&self.local_addr).await {
+                Ok(local_addr) => local_addr,
+                Err(error) => {
+                    error!("Failed resolving TcpServer local address: {error:?}");
+                    continue;
+                }
+            };
+
             debug!("Trying to bind to address {local_addr:?}...");
 
-            let listener = match TcpListener::bind(&local_addr).await {
-                Ok(listener) => listener,
+            match TcpListener::bind(&local_addr).await {
+                Ok(listener) => break listener,
                 Err(error) => {
-                    error!(
-                        "Failed to bind TCP Server to {:?}: {error:?}",
-                        self.local_addr
-                    );
-
-                    return Err(anyhow!("Failed to bind TCP Server"));
+                    error!("Failed to bind TCP Server to {local_addr:?}: {error:?}");
                 }
-            };
+            }
+        };
 
-            debug!("Waiting for clients...");
+        debug!("Waiting for clients...");
 
-            match listener.accept().await {
-                Ok((socket, remote_addr)) => {
-                    let remote_addr = remote_addr.to_string();
+        // Idle clients are checked at a fraction of the timeout, so they don't overstay much
+        let mut idle_interval = tokio::time::interval(
+            self.idle_timeout
+                .map(|timeout| timeout / 4)
+                .unwrap_or(tokio::time::Duration::from_secs(1))
+                .max(tokio::time::Duration::from_millis(100)),
+        );
 
-                    tokio::spawn(TcpServer::handle_client(
-                        socket,
-                        remote_addr,
-                        context.clone(),
-                    ));
+        loop {
+            tokio::select! {
+                result = listener.accept() => {
+                    match result {
+                        Ok((socket, remote_addr)) => self.accept_client(socket, remote_addr, &context).await,
+                        Err(error) => {
+                            error!("Failed to accept TCP connection: {error:?}");
+                            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+                        }
+                    }
                 }
-                Err(error) => {
-                    error!("Failed to accept TCP connection: {error:?}");
-                    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+                _ = idle_interval.tick(), if self.idle_timeout.is_some() => {
+                    if let Some(idle_timeout) = self.idle_timeout {
+                        self.clients.disconnect_idle(idle_timeout).await;
+                    }
                 }
             }
         }
@@ -156,6 +284,10 @@ impl Driver for TcpServer {
 
     #[instrument(level = "debug", skip(self))]
     fn info(&self) -> Box<dyn DriverInfo> {
+        if self.tls.is_some() {
+            return Box::new(TcpServerTlsInfo);
+        }
+
         return Box::new(TcpServerInfo);
     }
 
@@ -166,6 +298,10 @@ impl Driver for TcpServer {
     fn uuid(&self) -> &DriverUuid {
         &self.uuid
     }
+
+    fn clients(&self) -> Option<ClientRegistry> {
+        Some(self.clients.clone())
+    }
 }
 
 #[async_trait::async_trait]
@@ -177,7 +313,10 @@ impl AccumulatedDriverStatsProvider for TcpServer {
     async fn reset_stats(&self) {
         let mut stats = self.stats.write().await;
         stats.stats.input = None;
-        stats.stats.output = None
+        stats.stats.output = None;
+        stats.stats.rejected = 0;
+
+        self.clients.reset_stats().await;
     }
 }
 
@@ -205,21 +344,99 @@ impl DriverInfo for TcpServerInfo {
         let first_schema = &self.valid_schemes()[0];
         let second_schema = &self.valid_schemes()[1];
         vec![
-            format!("{first_schema}://<IP>:<PORT>").to_string(),
-            url::Url::parse(&format!("{first_schema}://0.0.0.0:14550"))
-                .unwrap()
+            format!("{first_schema}://<IP>:<PORT>?allow=<NETWORKS?>&max_clients=<NUMBER?>&idle_timeout=<SECONDS?>")
                 .to_string(),
-            url::Url::parse(&format!("{second_schema}://127.0.0.1:14660"))
+            url::Url::parse(&format!("{first_schema}://0.0.0.0:14550"))
                 .unwrap()
                 .to_string(),
+            url::Url::parse(&format!(
+                "{second_schema}://127.0.0.1:14660?allow=192.168.2.0/24,10.0.0.5&max_clients=4&idle_timeout=30"
+            ))
+            .unwrap()
+            .to_string(),
         ]
     }
 
-    fn create_endpoint_from_url(&self, url: &url::Url) -> Option<Arc<dyn Driver>> {
-        let host = url.host_str().unwrap();
-        let port = url.port().unwrap();
-        Some(Arc::new(
-            TcpServer::builder("TcpServer", &format!("{host}:{port}")).build(),
+    fn create_endpoint_from_url(&self, url: &url::Url) -> Result<Arc<dyn Driver>> {
+        Ok(Arc::new(
+            TcpServer::builder_from_url("TcpServer", url)?.build(),
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Polls until the condition holds, failing the test after a few seconds
+    async fn wait_until<F, Fut>(mut condition: F)
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = bool>,
+    {
+        tokio::time::timeout(tokio::time::Duration::from_secs(5), async {
+            while !condition().await {
+                tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_clients_lifecycle() {
+        // A port just handed out by the system, as the server binds its own listener
+        let local_addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (hub_sender, _hub_receiver) = broadcast::channel(16);
+        let server = Arc::new(
+            TcpServer::builder("test", &local_addr.to_string())
+                .max_clients(1)
+                .build(),
+        );
+
+        tokio::spawn({
+            let server = server.clone();
+            async move { server.run(hub_sender).await }
+        });
+
+        let clients = server.clients().unwrap();
+        let count = |expected: usize| {
+            let clients = clients.clone();
+            move || {
+                let clients = clients.clone();
+                async move { clients.list().await.len() == expected }
+            }
+        };
+
+        let first = tokio::time::timeout(tokio::time::Duration::from_secs(5), async {
+            loop {
+                match TcpStream::connect(local_addr).await {
+                    Ok(stream) => break stream,
+                    Err(_) => tokio::time::sleep(tokio::time::Duration::from_millis(10)).await,
+                }
+            }
+        })
+        .await
+        .unwrap();
+        wait_until(count(1)).await;
+
+        // Refused, as it is over the limit
+        let _second = TcpStream::connect(local_addr).await.unwrap();
+        wait_until(|| {
+            let server = server.clone();
+            async move { server.stats().await.stats.rejected == 1 }
+        })
+        .await;
+        assert_eq!(clients.list().await.len(), 1);
+
+        drop(first);
+        wait_until(count(0)).await;
+
+        // The same listener keeps accepting new clients
+        let _third = TcpStream::connect(local_addr).await.unwrap();
+        wait_until(count(1)).await;
+    }
+}
@@ -0,0 +1,357 @@
+use std::{io::BufReader, path::PathBuf, sync::Arc};
+
+use anyhow::{anyhow, Context, Result};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::{
+    client, rustls,
+    rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName},
+    server, TlsAcceptor, TlsConnector,
+};
+use tracing::*;
+
+use crate::drivers::{
+    host_port_from_url,
+    tcp::{client::TcpClient, server::TcpServer},
+    with_script, Driver, DriverInfo,
+};
+
+/// Handshakes still running after this long are given up, so a stalled peer doesn't hold a
+/// client slot or the connection loop
+pub(crate) const TLS_HANDSHAKE_TIMEOUT: tokio::time::Duration =
+    tokio::time::Duration::from_secs(10);
+
+/// TLS side of a `TcpClient`: verifies the server, and optionally authenticates itself to it
+#[derive(Clone)]
+pub struct ClientTls {
+    config: Arc<rustls::ClientConfig>,
+    connector: TlsConnector,
+    server_name: ServerName<'static>,
+}
+
+impl std::fmt::Debug for ClientTls {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientTls")
+            .field("server_name", &self.server_name)
+            .finish()
+    }
+}
+
+impl ClientTls {
+    /// Server certificates are verified against `ca`, or the well-known web roots when not
+    /// given. `identity` is the certificate and key used for mutual TLS.
+    pub fn new(
+        server_name: &str,
+        ca: Option<PathBuf>,
+        identity: Option<(PathBuf, PathBuf)>,
+    ) -> Result<Self> {
+        let roots = match ca {
+            Some(ca) => load_roots(&ca)?,
+            None => rustls::RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            },
+        };
+
+        let builder = rustls::ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots);
+
+        let config = match identity {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(load_certs(&cert)?, load_key(&key)?)
+                .context("Invalid client certificate")?,
+            None => builder.with_no_client_auth(),
+        };
+
+        let server_name = ServerName::try_from(server_name.to_string())
+            .context(format!("Invalid TLS server name {server_name:?}"))?;
+
+        let config = Arc::new(config);
+
+        Ok(Self {
+            connector: TlsConnector::from(config.clone()),
+            config,
+            server_name,
+        })
+    }
+
+    pub fn from_url(url: &url::Url) -> Result<Self> {
+        let server_name = match path_option(url, "domain")? {
+            Some(domain) => domain.display().to_string(),
+            None => match url.host().context(format!("Missing host in {url}"))? {
+                url::Host::Domain(domain) => domain.to_string(),
+                url::Host::Ipv4(ip) => ip.to_string(),
+                url::Host::Ipv6(ip) => ip.to_string(),
+            },
+        };
+
+        let identity = match (path_option(url, "cert")?, path_option(url, "key")?) {
+            (Some(cert), Some(key)) => Some((cert, key)),
+            (None, None) => None,
+            _ => return Err(anyhow!("Both cert and key are needed for mutual TLS")),
+        };
+
+        Self::new(&server_name, path_option(url, "ca")?, identity)
+    }
+
+    pub(crate) fn config(&self) -> Arc<rustls::ClientConfig> {
+        self.config.clone()
+    }
+
+    pub(crate) fn server_name(&self) -> String {
+        self.server_name.to_str().to_string()
+    }
+
+    pub async fn connect<IO>(&self, stream: IO) -> std::io::Result<client::TlsStream<IO>>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.connector
+            .connect(self.server_name.clone(), stream)
+            .await
+    }
+}
+
+/// TLS side of a `TcpServer`: presents its certificate, and optionally requires client ones
+#[derive(Clone)]
+pub struct ServerTls {
+    config: Arc<rustls::ServerConfig>,
+    acceptor: TlsAcceptor,
+}
+
+impl std::fmt::Debug for ServerTls {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerTls").finish()
+    }
+}
+
+impl ServerTls {
+    /// When `client_ca` is given, only clients with a certificate signed by it are accepted
+    pub fn new(cert: PathBuf, key: PathBuf, client_ca: Option<PathBuf>) -> Result<Self> {
+        let builder = rustls::ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?;
+
+        let builder = match client_ca {
+            Some(client_ca) => {
+                let verifier = rustls::server::WebPkiClientVerifier::builder_with_provider(
+                    Arc::new(load_roots(&client_ca)?),
+                    provider(),
+                )
+                .build()?;
+
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        let config = builder
+            .with_single_cert(load_certs(&cert)?, load_key(&key)?)
+            .context("Invalid server certificate")?;
+
+        let config = Arc::new(config);
+
+        Ok(Self {
+            acceptor: TlsAcceptor::from(config.clone()),
+            config,
+        })
+    }
+
+    pub fn from_url(url: &url::Url) -> Result<Self> {
+        let cert = path_option(url, "cert")?.context("Missing cert option")?;
+        let key = path_option(url, "key")?.context("Missing key option")?;
+
+        Self::new(cert, key, path_option(url, "client_ca")?)
+    }
+
+    pub(crate) fn config(&self) -> Arc<rustls::ServerConfig> {
+        self.config.clone()
+    }
+
+    pub async fn accept<IO>(&self, stream: IO) -> std::io::Result<server::TlsStream<IO>>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.acceptor.accept(stream).await
+    }
+}
+
+/// Uses an explicit provider, so it doesn't depend on which crypto backends are enabled in the build
+fn provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn path_option(url: &url::Url, name: &str) -> Result<Option<PathBuf>> {
+    let Some(path) = url
+        .query_pairs()
+        .find_map(|(key, value)| (key == name).then(|| value.to_string()))
+    else {
+        return Ok(None);
+    };
+
+    let path = shellexpand::full(&path)
+        .context(format!("Failed to expand {name} path"))?
+        .to_string();
+
+    Ok(Some(PathBuf::from(path)))
+}
+
+fn load_certs(path: &PathBuf) -> Result<Vec<CertificateDer<'static>>> {
+    let file = std::fs::File::open(path).context(format!("Failed to open {path:?}"))?;
+
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .context(format!("Failed to read certificates from {path:?}"))?;
+
+    if certs.is_empty() {
+        return Err(anyhow!("No certificates found in {path:?}"));
+    }
+
+    Ok(certs)
+}
+
+fn load_key(path: &PathBuf) -> Result<PrivateKeyDer<'static>> {
+    let file = std::fs::File::open(path).context(format!("Failed to open {path:?}"))?;
+
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .context(format!("Failed to read private key from {path:?}"))?
+        .context(format!("No private key found in {path:?}"))
+}
+
+fn load_roots(path: &PathBuf) -> Result<rustls::RootCertStore> {
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots
+            .add(cert)
+            .context(format!("Invalid CA certificate in {path:?}"))?;
+    }
+
+    Ok(roots)
+}
+
+pub struct TcpClientTlsInfo;
+impl DriverInfo for TcpClientTlsInfo {
+    fn name(&self) -> &'static str {
+        "TcpClientTls"
+    }
+    fn valid_schemes(&self) -> &'static [&'static str] {
+        &["tcpc+tls", "tcpclient+tls"]
+    }
+
+    fn cli_example_legacy(&self) -> Vec<String> {
+        vec![]
+    }
+
+    fn cli_example_url(&self) -> Vec<String> {
+        let first_schema = &self.valid_schemes()[0];
+        let second_schema = &self.valid_schemes()[1];
+        vec![
+            format!("{first_schema}://<HOST>:<PORT>?ca=<CA_PEM?>&cert=<CERT_PEM?>&key=<KEY_PEM?>&domain=<NAME?>")
+                .to_string(),
+            url::Url::parse(&format!("{first_schema}://ops.example.com:5761"))
+                .unwrap()
+                .to_string(),
+            url::Url::parse(&format!(
+                "{second_schema}://10.0.0.1:5761?ca=/etc/mavlink/ca.pem&cert=/etc/mavlink/vehicle.pem&key=/etc/mavlink/vehicle.key"
+            ))
+            .unwrap()
+            .to_string(),
+        ]
+    }
+
+    fn create_endpoint_from_url(&self, url: &url::Url) -> Result<Arc<dyn Driver>> {
+        let address = host_port_from_url(url)?;
+        let builder = TcpClient::builder("TcpClientTls", &address).tls(ClientTls::from_url(url)?);
+
+        let builder = with_script(builder, url)?;
+
+        Ok(Arc::new(builder.build()))
+    }
+}
+
+pub struct TcpServerTlsInfo;
+impl DriverInfo for TcpServerTlsInfo {
+    fn name(&self) -> &'static str {
+        "TcpServerTls"
+    }
+    fn valid_schemes(&self) -> &'static [&'static str] {
+        &["tcps+tls", "tcpserver+tls"]
+    }
+
+    fn cli_example_legacy(&self) -> Vec<String> {
+        vec![]
+    }
+
+    fn cli_example_url(&self) -> Vec<String> {
+        let first_schema = &self.valid_schemes()[0];
+        let second_schema = &self.valid_schemes()[1];
+        vec![
+            format!("{first_schema}://<IP>:<PORT>?cert=<CERT_PEM>&key=<KEY_PEM>&client_ca=<CA_PEM?>")
+                .to_string(),
+            url::Url::parse(&format!(
+                "{first_schema}://0.0.0.0:5761?cert=/etc/mavlink/server.pem&key=/etc/mavlink/server.key"
+            ))
+            .unwrap()
+            .to_string(),
+            url::Url::parse(&format!(
+                "{second_schema}://[::]:5761?cert=/etc/mavlink/server.pem&key=/etc/mavlink/server.key&client_ca=/etc/mavlink/ca.pem"
+            ))
+            .unwrap()
+            .to_string(),
+        ]
+    }
+
+    fn create_endpoint_from_url(&self, url: &url::Url) -> Result<Arc<dyn Driver>> {
+        let builder =
+            TcpServer::builder_from_url("TcpServerTls", url)?.tls(ServerTls::from_url(url)?);
+
+        Ok(Arc::new(builder.build()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_temp(dir: &tempfile::TempDir, name: &str, content: &str) -> PathBuf {
+        let path = dir.path().join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_mutual_tls_handshake() {
+        let server = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let client = rcgen::generate_simple_self_signed(vec!["vehicle".to_string()]).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let server_cert = write_temp(&dir, "server.pem", &server.cert.pem());
+        let server_key = write_temp(&dir, "server.key", &server.key_pair.serialize_pem());
+        let client_cert = write_temp(&dir, "client.pem", &client.cert.pem());
+        let client_key = write_temp(&dir, "client.key", &client.key_pair.serialize_pem());
+
+        let server_tls =
+            ServerTls::new(server_cert.clone(), server_key, Some(client_cert.clone())).unwrap();
+        let client_tls = ClientTls::new(
+            "localhost",
+            Some(server_cert.clone()),
+            Some((client_cert, client_key)),
+        )
+        .unwrap();
+        let anonymous_tls = ClientTls::new("localhost", Some(server_cert), None).unwrap();
+
+        let (client_stream, server_stream) = tokio::io::duplex(16 * 1024);
+        let (client_result, server_result) = tokio::join!(
+            client_tls.connect(client_stream),
+            server_tls.accept(server_stream)
+        );
+        assert!(client_result.is_ok());
+        assert!(server_result.is_ok());
+
+        // Clients without a certificate are refused by the server
+        let (client_stream, server_stream) = tokio::io::duplex(16 * 1024);
+        let (_client_result, server_result) = tokio::join!(
+            anonymous_tls.connect(client_stream),
+            server_tls.accept(server_stream)
+        );
+        assert!(server_result.is_err());
+    }
+}
@@ -9,7 +9,7 @@ use tracing::*;
 
 use crate::{
     callbacks::{Callbacks, MessageCallback},
-    drivers::{Driver, DriverInfo},
+    drivers::{generic_tasks::SendReceiveContext, Driver, DriverInfo},
     protocol::Protocol,
     stats::{
         accumulated::driver::{AccumulatedDriverStats, AccumulatedDriverStatsProvider},
@@ -60,11 +60,11 @@ impl TlogReader {
         })
     }
 
-    #[instrument(level = "debug", skip(self, reader, hub_sender))]
+    #[instrument(level = "debug", skip(self, reader, context))]
     async fn handle_file(
         &self,
         reader: tokio::io::BufReader<tokio::fs::File>,
-        hub_sender: broadcast::Sender<Arc<Protocol>>,
+        context: SendReceiveContext,
     ) -> Result<()> {
         let source_name = self.path.as_path().display().to_string();
 
@@ -122,16 +122,18 @@ impl TlogReader {
 
             let message = Arc::new(message);
 
-            self.stats.write().await.stats.update_input(&message);
+            context.stats.write().await.stats.update_input(&message);
 
-            for future in self.on_message_input.call_all(message.clone()) {
-                if let Err(error) = future.await {
-                    debug!("Dropping message: on_message_input callback returned error: {error:?}");
-                    continue;
-                }
+            if let Err(error) = context
+                .on_message_input
+                .call_until_error(message.clone())
+                .await
+            {
+                debug!("Dropping message: on_message_input callback returned error: {error:?}");
+                continue;
             }
 
-            if let Err(error) = hub_sender.send(message) {
+            if let Err(error) = context.send_to_hub(message) {
                 error!("Failed to send message to hub: {error:?}");
             }
         }
@@ -145,7 +147,15 @@ impl Driver for TlogReader {
         let file = tokio::fs::File::open(self.path.clone()).await?;
         let reader = tokio::io::BufReader::with_capacity(1024, file);
 
-        TlogReader::handle_file(self, reader, hub_sender).await
+        let context = SendReceiveContext {
+            hub_sender,
+            on_message_output: Callbacks::default(),
+            on_message_input: self.on_message_input.clone(),
+            stats: self.stats.clone(),
+            driver: self.uuid,
+        };
+
+        TlogReader::handle_file(self, reader, context).await
     }
 
     #[instrument(level = "debug", skip(self))]
@@ -221,8 +231,8 @@ impl DriverInfo for TlogReaderInfo {
         url::Url::parse(&format!("{scheme}://{path_string}")).context("Failed to parse URL")
     }
 
-    fn create_endpoint_from_url(&self, url: &url::Url) -> Option<Arc<dyn Driver>> {
-        Some(Arc::new(
+    fn create_endpoint_from_url(&self, url: &url::Url) -> Result<Arc<dyn Driver>> {
+        Ok(Arc::new(
             TlogReader::builder("TlogReader", url.path().into()).build(),
         ))
     }
@@ -75,13 +75,15 @@ impl TlogWriter {
 
                     self.stats.write().await.stats.update_output(&message);
 
-                    for future in self.on_message_output.call_all(message.clone()) {
-                        if let Err(error) = future.await {
-                            debug!(
-                                "Dropping message: on_message_input callback returned error: {error:?}"
-                            );
-                            continue;
-                        }
+                    if let Err(error) = self
+                        .on_message_output
+                        .call_until_error(message.clone())
+                        .await
+                    {
+                        debug!(
+                            "Dropping message: on_message_input callback returned error: {error:?}"
+                        );
+                        continue;
                     }
 
                     let raw_bytes = message.bytes();
@@ -165,8 +167,8 @@ impl DriverInfo for TlogWriterInfo {
         ]
     }
 
-    fn create_endpoint_from_url(&self, url: &url::Url) -> Option<Arc<dyn Driver>> {
-        Some(Arc::new(
+    fn create_endpoint_from_url(&self, url: &url::Url) -> Result<Arc<dyn Driver>> {
+        Ok(Arc::new(
             TlogWriter::builder("TlogWriter", url.path().into()).build(),
         ))
     }
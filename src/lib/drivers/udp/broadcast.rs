@@ -0,0 +1,425 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use mavlink_codec::{codec::MavlinkCodec, error::DecoderError, Packet};
+use tokio::{
+    net::UdpSocket,
+    sync::{broadcast, RwLock},
+};
+use tokio_util::udp::UdpFramed;
+use tracing::*;
+
+use crate::{
+    callbacks::{Callbacks, MessageCallback},
+    drivers::{
+        generic_tasks::{should_send, SendReceiveContext},
+        host_port_from_url, with_script, Driver, DriverInfo,
+    },
+    protocol::Protocol,
+    stats::{
+        accumulated::driver::{AccumulatedDriverStats, AccumulatedDriverStatsProvider},
+        driver::DriverUuid,
+    },
+};
+
+#[derive(Debug)]
+pub struct UdpBroadcast {
+    pub broadcast_addr: String,
+    name: arc_swap::ArcSwap<String>,
+    uuid: DriverUuid,
+    on_message_input: Callbacks<Arc<Protocol>>,
+    on_message_output: Callbacks<Arc<Protocol>>,
+    stats: Arc<RwLock<AccumulatedDriverStats>>,
+}
+
+/// Where the messages are being sent to: the broadcast address, or the first peer that replied to it
+#[derive(Debug)]
+struct Target {
+    broadcast_addr: SocketAddr,
+    peer: Option<(SocketAddr, tokio::time::Instant)>,
+}
+
+impl Target {
+    fn new(broadcast_addr: SocketAddr) -> Self {
+        Self {
+            broadcast_addr,
+            peer: None,
+        }
+    }
+
+    /// Returns the current destination, falling back to broadcast when the peer went silent
+    fn addr(&mut self, timeout: Option<tokio::time::Duration>) -> SocketAddr {
+        if let (Some((peer_addr, last_seen)), Some(timeout)) = (self.peer, timeout) {
+            if last_seen.elapsed() > timeout {
+                debug!("Peer {peer_addr} timed out, going back to broadcast");
+                self.peer = None;
+            }
+        }
+
+        self.peer
+            .map(|(peer_addr, _)| peer_addr)
+            .unwrap_or(self.broadcast_addr)
+    }
+
+    fn refresh(&mut self, addr: SocketAddr) {
+        match &mut self.peer {
+            Some((peer_addr, last_seen)) if *peer_addr == addr => {
+                *last_seen = tokio::time::Instant::now();
+            }
+            Some(_) => (),
+            None => {
+                debug!("Peer {addr} replied, switching from broadcast to unicast");
+                self.peer = Some((addr, tokio::time::Instant::now()));
+            }
+        }
+    }
+}
+
+pub struct UdpBroadcastBuilder(UdpBroadcast);
+
+impl UdpBroadcastBuilder {
+    pub fn build(self) -> UdpBroadcast {
+        self.0
+    }
+
+    pub fn on_message_input<C>(self, callback: C) -> Self
+    where
+        C: MessageCallback<Arc<Protocol>>,
+    {
+        self.0.on_message_input.add_callback(callback.into_boxed());
+        self
+    }
+
+    pub fn on_message_output<C>(self, callback: C) -> Self
+    where
+        C: MessageCallback<Arc<Protocol>>,
+    {
+        self.0.on_message_output.add_callback(callback.into_boxed());
+        self
+    }
+}
+
+impl UdpBroadcast {
+    #[instrument(level = "debug")]
+    pub fn builder(name: &str, broadcast_addr: &str) -> UdpBroadcastBuilder {
+        let name = Arc::new(name.to_string());
+
+        UdpBroadcastBuilder(Self {
+            broadcast_addr: broadcast_addr.to_string(),
+            name: arc_swap::ArcSwap::new(name.clone()),
+            uuid: Self::generate_uuid(broadcast_addr),
+            on_message_input: Callbacks::default(),
+            on_message_output: Callbacks::default(),
+            stats: Arc::new(RwLock::new(AccumulatedDriverStats::new(
+                name,
+                &UdpBroadcastInfo,
+            ))),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Driver for UdpBroadcast {
+    #[instrument(level = "debug", skip(self, hub_sender))]
+    async fn run(&self, hub_sender: broadcast::Sender<Arc<Protocol>>) -> Result<()> {
+        let local_addr = "0.0.0.0:0".parse::<SocketAddr>().unwrap();
+        let broadcast_addr = self.broadcast_addr.parse::<SocketAddr>()?;
+
+        let context = SendReceiveContext {
+            hub_sender,
+            on_message_output: self.on_message_output.clone(),
+            on_message_input: self.on_message_input.clone(),
+            stats: self.stats.clone(),
+            driver: self.uuid,
+        };
+
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
+        let mut first = true;
+        loop {
+            if first {
+                first = false;
+            } else {
+                interval.tick().await;
+            }
+
+            debug!("Trying to bind to address {local_addr:?}...");
+
+            let socket = match UdpSocket::bind(local_addr).await {
+                Ok(socket) => socket,
+                Err(error) => {
+                    error!("Failed binding UdpBroadcast to address {local_addr:?}: {error:?}");
+                    continue;
+                }
+            };
+
+            if let Err(error) = socket.set_broadcast(true) {
+                error!("Failed enabling SO_BROADCAST on UdpBroadcast socket: {error:?}");
+                continue;
+            }
+
+            debug!("UdpBroadcast successfully bound to {local_addr}. Broadcasting to {broadcast_addr:?}...");
+
+            let target = Arc::new(Mutex::new(Target::new(broadcast_addr)));
+
+            let codec = MavlinkCodec::<true, true, false, false, false, false>::default();
+            let (writer, reader) = UdpFramed::new(socket, codec).split();
+
+            if let Err(reason) =
+                udp_broadcast_send_receive_run(writer, reader, &target, &context).await
+            {
+                warn!("Driver send/receive tasks closed: {reason:?}");
+            }
+        }
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    fn info(&self) -> Box<dyn DriverInfo> {
+        return Box::new(UdpBroadcastInfo);
+    }
+
+    fn name(&self) -> Arc<String> {
+        self.name.load_full()
+    }
+
+    fn uuid(&self) -> &DriverUuid {
+        &self.uuid
+    }
+}
+
+#[instrument(level = "debug", skip(writer, reader, context))]
+async fn udp_broadcast_send_receive_run<S, T>(
+    mut writer: S,
+    mut reader: T,
+    target: &Mutex<Target>,
+    context: &SendReceiveContext,
+) -> Result<()>
+where
+    S: Sink<(Packet, SocketAddr), Error = std::io::Error> + std::marker::Unpin,
+    T: Stream<Item = std::io::Result<(std::result::Result<Packet, DecoderError>, SocketAddr)>>
+        + std::marker::Unpin,
+{
+    tokio::select! {
+        result = udp_broadcast_send_task(&mut writer, target, context) => {
+            if let Err(error) = result {
+                error!("Error in send task: {error:?}");
+            }
+        }
+        result = udp_broadcast_receive_task(&mut reader, target, context) => {
+            if let Err(error) = result {
+                error!("Error in receive task: {error:?}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Receives messages from the HUB Channel and sends them to the current target
+#[instrument(level = "debug", skip(writer, context))]
+async fn udp_broadcast_send_task<S>(
+    writer: &mut S,
+    target: &Mutex<Target>,
+    context: &SendReceiveContext,
+) -> Result<()>
+where
+    S: Sink<(Packet, SocketAddr), Error = std::io::Error> + std::marker::Unpin,
+{
+    let mut hub_receiver = context.hub_sender.subscribe();
+
+    let peer_timeout = crate::cli::udp_server_timeout();
+
+    loop {
+        let message = match hub_receiver.recv().await {
+            Ok(message) => message,
+            Err(broadcast::error::RecvError::Closed) => {
+                error!("Hub channel closed!");
+                break;
+            }
+            Err(broadcast::error::RecvError::Lagged(count)) => {
+                warn!("Channel lagged by {count} messages.");
+                continue;
+            }
+        };
+
+        let remote_addr = target.lock().unwrap().addr(peer_timeout);
+
+        if !should_send(&message, &remote_addr.to_string()).await {
+            continue;
+        }
+
+        context.stats.write().await.stats.update_output(&message);
+
+        if let Err(error) = context
+            .on_message_output
+            .call_until_error(message.clone())
+            .await
+        {
+            debug!(
+                client = ?remote_addr, "Dropping message: on_message_output callback returned error: {error:?}"
+            );
+            continue;
+        }
+
+        if let Err(io_error) = writer.send(((**message).clone(), remote_addr)).await {
+            match io_error.kind() {
+                std::io::ErrorKind::ConnectionRefused => {
+                    trace!(client = ?remote_addr, "Failed send message: {io_error}");
+                    continue;
+                }
+                _ => {
+                    error!(client = ?remote_addr, "Failed to send message: {io_error:?}");
+                }
+            }
+            break;
+        }
+
+        trace!("Message sent to {remote_addr}: {:?}", message.as_slice());
+    }
+
+    debug!("Driver sender task stopped!");
+
+    Ok(())
+}
+
+/// Receives messages from the socket, locks the target on the replying peer and sends them to the HUB Channel
+#[instrument(level = "debug", skip(reader, context))]
+async fn udp_broadcast_receive_task<T>(
+    reader: &mut T,
+    target: &Mutex<Target>,
+    context: &SendReceiveContext,
+) -> Result<()>
+where
+    T: Stream<Item = std::io::Result<(std::result::Result<Packet, DecoderError>, SocketAddr)>>
+        + std::marker::Unpin,
+{
+    loop {
+        let (packet, remote_addr) = match reader.next().await {
+            Some(Ok((Ok(packet), remote_addr))) => (packet, remote_addr),
+            Some(Ok((Err(decode_error), remote_addr))) => {
+                error!(origin = ?remote_addr, "Failed to decode packet: {decode_error:?}");
+                continue;
+            }
+            Some(Err(io_error)) => {
+                error!("Critical error trying to decode data from: {io_error:?}");
+                break;
+            }
+            None => break,
+        };
+
+        target.lock().unwrap().refresh(remote_addr);
+
+        let message = Arc::new(Protocol::new(&remote_addr.to_string(), packet));
+
+        trace!(origin = ?remote_addr, "Received message: {message:?}");
+
+        context.stats.write().await.stats.update_input(&message);
+
+        if let Err(error) = context
+            .on_message_input
+            .call_until_error(message.clone())
+            .await
+        {
+            debug!(origin = ?remote_addr, "Dropping message: on_message_input callback returned error: {error:?}");
+            continue;
+        }
+
+        if let Err(send_error) = context.send_to_hub(message) {
+            error!(origin = ?remote_addr, "Failed to send message to hub: {send_error:?}");
+            continue;
+        }
+
+        trace!(origin = ?remote_addr, "Message sent to hub");
+    }
+
+    debug!("Driver receiver task stopped!");
+
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl AccumulatedDriverStatsProvider for UdpBroadcast {
+    async fn stats(&self) -> AccumulatedDriverStats {
+        self.stats.read().await.clone()
+    }
+
+    async fn reset_stats(&self) {
+        let mut stats = self.stats.write().await;
+        stats.stats.input = None;
+        stats.stats.output = None
+    }
+}
+
+pub struct UdpBroadcastInfo;
+impl DriverInfo for UdpBroadcastInfo {
+    fn name(&self) -> &'static str {
+        "UdpBroadcast"
+    }
+    fn valid_schemes(&self) -> &'static [&'static str] {
+        &["udpbroadcast", "udpb"]
+    }
+
+    fn cli_example_legacy(&self) -> Vec<String> {
+        let first_schema = &self.valid_schemes()[0];
+        let second_schema = &self.valid_schemes()[1];
+        vec![
+            format!("{first_schema}:<BROADCAST_IP>:<PORT>"),
+            format!("{first_schema}:192.168.2.255:14550"),
+            format!("{second_schema}:255.255.255.255:14550"),
+        ]
+    }
+
+    fn cli_example_url(&self) -> Vec<String> {
+        let first_schema = &self.valid_schemes()[0];
+        let second_schema = &self.valid_schemes()[1];
+        vec![
+            format!("{first_schema}://<BROADCAST_IP>:<PORT>").to_string(),
+            url::Url::parse(&format!("{first_schema}://192.168.2.255:14550"))
+                .unwrap()
+                .to_string(),
+            url::Url::parse(&format!("{second_schema}://255.255.255.255:14550"))
+                .unwrap()
+                .to_string(),
+        ]
+    }
+
+    fn create_endpoint_from_url(&self, url: &url::Url) -> Result<Arc<dyn Driver>> {
+        let address = host_port_from_url(url)?;
+        let builder = UdpBroadcast::builder("UdpBroadcast", &address);
+
+        let builder = with_script(builder, url)?;
+
+        Ok(Arc::new(builder.build()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_locks_on_first_peer() {
+        let broadcast_addr = "192.168.2.255:14550".parse::<SocketAddr>().unwrap();
+        let first_peer = "192.168.2.10:14550".parse::<SocketAddr>().unwrap();
+        let second_peer = "192.168.2.11:14550".parse::<SocketAddr>().unwrap();
+
+        let mut target = Target::new(broadcast_addr);
+        assert_eq!(target.addr(None), broadcast_addr);
+
+        target.refresh(first_peer);
+        target.refresh(second_peer);
+        assert_eq!(target.addr(None), first_peer);
+
+        target.peer = Some((
+            first_peer,
+            tokio::time::Instant::now() - tokio::time::Duration::from_secs(2),
+        ));
+        assert_eq!(
+            target.addr(Some(tokio::time::Duration::from_secs(1))),
+            broadcast_addr
+        );
+    }
+}
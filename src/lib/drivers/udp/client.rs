@@ -12,7 +12,10 @@ use tracing::*;
 
 use crate::{
     callbacks::{Callbacks, MessageCallback},
-    drivers::{generic_tasks::SendReceiveContext, udp::udp_send_task, Driver, DriverInfo},
+    drivers::{
+        generic_tasks::SendReceiveContext, host_port_from_url, resolve_address, udp::udp_send_task,
+        with_script, Driver, DriverInfo,
+    },
     protocol::Protocol,
     stats::{
         accumulated::driver::{AccumulatedDriverStats, AccumulatedDriverStatsProvider},
@@ -23,6 +26,7 @@ use crate::{
 #[derive(Debug)]
 pub struct UdpClient {
     pub remote_addr: String,
+    pub local_addr: Option<String>,
     name: arc_swap::ArcSwap<String>,
     uuid: DriverUuid,
     on_message_input: Callbacks<Arc<Protocol>>,
@@ -37,6 +41,12 @@ impl UdpClientBuilder {
         self.0
     }
 
+    /// Local address and port to send from, instead of any interface and a random port
+    pub fn bind(mut self, local_addr: &str) -> Self {
+        self.0.local_addr = Some(local_addr.to_string());
+        self
+    }
+
     pub fn on_message_input<C>(self, callback: C) -> Self
     where
         C: MessageCallback<Arc<Protocol>>,
@@ -61,6 +71,7 @@ impl UdpClient {
 
         UdpClientBuilder(Self {
             remote_addr: remote_addr.to_string(),
+            local_addr: None,
             name: arc_swap::ArcSwap::new(name.clone()),
             uuid: Self::generate_uuid(remote_addr),
             on_message_input: Callbacks::default(),
@@ -77,14 +88,12 @@ impl UdpClient {
 impl Driver for UdpClient {
     #[instrument(level = "debug", skip(self, hub_sender))]
     async fn run(&self, hub_sender: broadcast::Sender<Arc<Protocol>>) -> Result<()> {
-        let local_addr = "0.0.0.0:0".parse::<SocketAddr>().unwrap();
-        let remote_addr = self.remote_addr.parse::<SocketAddr>()?;
-
         let context = SendReceiveContext {
             hub_sender,
             on_message_output: self.on_message_output.clone(),
             on_message_input: self.on_message_input.clone(),
             stats: self.stats.clone(),
+            driver: self.uuid,
         };
 
         let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
@@ -96,6 +105,29 @@ impl Driver for UdpClient {
                 interval.tick().await;
             }
 
+            // Hostnames are resolved again on each reconnection
+            let remote_addr = match resolve_address(&self.remote_addr).await {
+                Ok(remote_addr) => remote_addr,
+                Err(error) => {
+                    error!("Failed resolving UdpClient remote address: {error:?}");
+                    continue;
+                }
+            };
+
+            let local_addr = match &self.local_addr {
+                Some(local_addr) => match resolve_address(local_addr).await {
+                    Ok(local_addr) => local_addr,
+                    Err(error) => {
+                        error!("Failed resolving UdpClient local address: {error:?}");
+                        continue;
+                    }
+                },
+                None if remote_addr.is_ipv6() => {
+                    SocketAddr::from((std::net::Ipv6Addr::UNSPECIFIED, 0))
+                }
+                None => SocketAddr::from((std::net::Ipv4Addr::UNSPECIFIED, 0)),
+            };
+
             debug!("Trying to bind to address {local_addr:?}...");
 
             let socket = match UdpSocket::bind(local_addr).await {
@@ -198,14 +230,16 @@ where
 
         context.stats.write().await.stats.update_input(&message);
 
-        for future in context.on_message_input.call_all(message.clone()) {
-            if let Err(error) = future.await {
-                debug!(origin = ?remote_addr, "Dropping message: on_message_input callback returned error: {error:?}");
-                continue;
-            }
+        if let Err(error) = context
+            .on_message_input
+            .call_until_error(message.clone())
+            .await
+        {
+            debug!(origin = ?remote_addr, "Dropping message: on_message_input callback returned error: {error:?}");
+            continue;
         }
 
-        if let Err(send_error) = context.hub_sender.send(message) {
+        if let Err(send_error) = context.send_to_hub(message) {
             error!(origin = ?remote_addr, "Failed to send message to hub: {send_error:?}");
             continue;
         }
@@ -254,21 +288,31 @@ impl DriverInfo for UdpClientInfo {
         let first_schema = &self.valid_schemes()[0];
         let second_schema = &self.valid_schemes()[1];
         vec![
-            format!("{first_schema}://<IP>:<PORT>").to_string(),
+            format!("{first_schema}://<IP>:<PORT>?bind=<LOCAL_IP:LOCAL_PORT?>").to_string(),
             url::Url::parse(&format!("{first_schema}://0.0.0.0:14550"))
                 .unwrap()
                 .to_string(),
-            url::Url::parse(&format!("{second_schema}://192.168.2.1:14660"))
-                .unwrap()
-                .to_string(),
+            url::Url::parse(&format!(
+                "{second_schema}://192.168.2.1:14660?bind=192.168.2.2:14555"
+            ))
+            .unwrap()
+            .to_string(),
         ]
     }
 
-    fn create_endpoint_from_url(&self, url: &url::Url) -> Option<Arc<dyn Driver>> {
-        let host = url.host_str().unwrap();
-        let port = url.port().unwrap();
-        Some(Arc::new(
-            UdpClient::builder("UdpClient", &format!("{host}:{port}")).build(),
-        ))
+    fn create_endpoint_from_url(&self, url: &url::Url) -> Result<Arc<dyn Driver>> {
+        let address = host_port_from_url(url)?;
+        let mut builder = UdpClient::builder("UdpClient", &address);
+
+        if let Some(local_addr) = url
+            .query_pairs()
+            .find_map(|(key, value)| (key == "bind").then(|| value.to_string()))
+        {
+            builder = builder.bind(&local_addr);
+        }
+
+        let builder = with_script(builder, url)?;
+
+        Ok(Arc::new(builder.build()))
     }
 }
@@ -6,9 +6,11 @@ use mavlink_codec::Packet;
 use tokio::sync::broadcast;
 use tracing::*;
 
-use super::generic_tasks::SendReceiveContext;
+use super::generic_tasks::{should_send, SendReceiveContext};
 
+pub mod broadcast;
 pub mod client;
+pub mod multicast;
 pub mod server;
 
 /// Receives messages from the HUB Channel and sends them to a Sink
@@ -36,19 +38,21 @@ where
             }
         };
 
-        if message.origin.eq(&remote_addr.to_string()) {
-            continue; // Don't do loopback
+        if !should_send(&message, &remote_addr.to_string()).await {
+            continue;
         }
 
         context.stats.write().await.stats.update_output(&message);
 
-        for future in context.on_message_output.call_all(message.clone()) {
-            if let Err(error) = future.await {
-                debug!(
-                    client = ?remote_addr, "Dropping message: on_message_output callback returned error: {error:?}"
-                );
-                continue;
-            }
+        if let Err(error) = context
+            .on_message_output
+            .call_until_error(message.clone())
+            .await
+        {
+            debug!(
+                client = ?remote_addr, "Dropping message: on_message_output callback returned error: {error:?}"
+            );
+            continue;
         }
 
         if let Err(io_error) = writer.send(((**message).clone(), *remote_addr)).await {
@@ -0,0 +1,583 @@
+use std::{
+    collections::HashSet,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Context, Result};
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use mavlink_codec::{codec::MavlinkCodec, error::DecoderError, Packet};
+use tokio::{
+    net::UdpSocket,
+    sync::{broadcast, RwLock},
+};
+use tokio_util::udp::UdpFramed;
+use tracing::*;
+
+use crate::{
+    callbacks::{Callbacks, MessageCallback},
+    drivers::{
+        clients::ClientRegistry,
+        generic_tasks::{should_send, SendReceiveContext},
+        host_port_from_url, with_script, Driver, DriverInfo,
+    },
+    protocol::Protocol,
+    stats::{
+        accumulated::driver::{AccumulatedDriverStats, AccumulatedDriverStatsProvider},
+        driver::DriverUuid,
+    },
+};
+
+#[derive(Debug)]
+pub struct UdpMulticast {
+    pub group_addr: String,
+    pub interface: Option<String>,
+    pub ttl: u32,
+    pub loopback: bool,
+    name: arc_swap::ArcSwap<String>,
+    uuid: DriverUuid,
+    on_message_input: Callbacks<Arc<Protocol>>,
+    on_message_output: Callbacks<Arc<Protocol>>,
+    stats: Arc<RwLock<AccumulatedDriverStats>>,
+    /// The other members of the group that sent something, as the clients of the driver
+    senders: ClientRegistry,
+}
+
+pub struct UdpMulticastBuilder(UdpMulticast);
+
+impl UdpMulticastBuilder {
+    pub fn build(self) -> UdpMulticast {
+        self.0
+    }
+
+    /// Interface name (e.g. `eth0`) or IPv4 address used to join and send to the group
+    pub fn interface(mut self, interface: &str) -> Self {
+        self.0.interface = Some(interface.to_string());
+        self
+    }
+
+    pub fn ttl(mut self, ttl: u32) -> Self {
+        self.0.ttl = ttl;
+        self
+    }
+
+    /// Whether the messages sent should also be delivered to the other group members in this host
+    pub fn loopback(mut self, loopback: bool) -> Self {
+        self.0.loopback = loopback;
+        self
+    }
+
+    pub fn on_message_input<C>(self, callback: C) -> Self
+    where
+        C: MessageCallback<Arc<Protocol>>,
+    {
+        self.0.on_message_input.add_callback(callback.into_boxed());
+        self
+    }
+
+    pub fn on_message_output<C>(self, callback: C) -> Self
+    where
+        C: MessageCallback<Arc<Protocol>>,
+    {
+        self.0.on_message_output.add_callback(callback.into_boxed());
+        self
+    }
+}
+
+impl UdpMulticast {
+    #[instrument(level = "debug")]
+    pub fn builder(name: &str, group_addr: &str) -> UdpMulticastBuilder {
+        let name = Arc::new(name.to_string());
+
+        UdpMulticastBuilder(Self {
+            group_addr: group_addr.to_string(),
+            interface: None,
+            ttl: 1,
+            loopback: true,
+            name: arc_swap::ArcSwap::new(name.clone()),
+            uuid: Self::generate_uuid(group_addr),
+            on_message_input: Callbacks::default(),
+            on_message_output: Callbacks::default(),
+            stats: Arc::new(RwLock::new(AccumulatedDriverStats::new(
+                name,
+                &UdpMulticastInfo,
+            ))),
+            senders: ClientRegistry::default(),
+        })
+    }
+
+    /// Creates a socket bound to the group port and joined to the group, to receive from it
+    fn create_receive_socket(
+        &self,
+        group_addr: SocketAddrV4,
+        interface: Ipv4Addr,
+    ) -> Result<UdpSocket> {
+        use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+
+        // Allow other consumers in this host to join the same group
+        socket.set_reuse_address(true)?;
+        #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+        socket.set_reuse_port(true)?;
+
+        let local_addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, group_addr.port());
+        socket.bind(&SockAddr::from(local_addr))?;
+
+        socket.join_multicast_v4(group_addr.ip(), &interface)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(UdpSocket::from_std(socket.into())?)
+    }
+
+    /// Creates a socket to send to the group, bound to its own port so its looped back messages
+    /// can be told apart from the ones of other consumers in this host
+    fn create_send_socket(&self, interface: Ipv4Addr) -> Result<UdpSocket> {
+        use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+
+        socket.bind(&SockAddr::from(SocketAddrV4::new(interface, 0)))?;
+        socket.set_multicast_if_v4(&interface)?;
+        socket.set_multicast_ttl_v4(self.ttl)?;
+        socket.set_multicast_loop_v4(self.loopback)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(UdpSocket::from_std(socket.into())?)
+    }
+}
+
+/// Accepts either an interface name or an IPv4 address
+fn resolve_interface(interface: Option<&str>) -> Result<Ipv4Addr> {
+    let Some(interface) = interface else {
+        return Ok(Ipv4Addr::UNSPECIFIED);
+    };
+
+    if let Ok(address) = interface.parse::<Ipv4Addr>() {
+        return Ok(address);
+    }
+
+    if_addrs::get_if_addrs()?
+        .into_iter()
+        .find_map(|iface| match iface.ip() {
+            IpAddr::V4(address) if iface.name == interface => Some(address),
+            _ => None,
+        })
+        .context(format!("No IPv4 address found for interface {interface:?}"))
+}
+
+/// Addresses the send socket could be sending from, used to ignore our own multicast loopback
+fn local_addresses(port: u16) -> HashSet<SocketAddr> {
+    if_addrs::get_if_addrs()
+        .map(|interfaces| {
+            interfaces
+                .into_iter()
+                .map(|iface| SocketAddr::new(iface.ip(), port))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[async_trait::async_trait]
+impl Driver for UdpMulticast {
+    #[instrument(level = "debug", skip(self, hub_sender))]
+    async fn run(&self, hub_sender: broadcast::Sender<Arc<Protocol>>) -> Result<()> {
+        let group_addr = match self.group_addr.parse::<SocketAddr>()? {
+            SocketAddr::V4(group_addr) if group_addr.ip().is_multicast() => group_addr,
+            group_addr => {
+                return Err(anyhow!(
+                    "{group_addr:?} is not an IPv4 multicast group address"
+                ))
+            }
+        };
+
+        let context = SendReceiveContext {
+            hub_sender,
+            on_message_output: self.on_message_output.clone(),
+            on_message_input: self.on_message_input.clone(),
+            stats: self.stats.clone(),
+            driver: self.uuid,
+        };
+
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
+        let mut first = true;
+        loop {
+            if first {
+                first = false;
+            } else {
+                interval.tick().await;
+            }
+
+            let interface = match resolve_interface(self.interface.as_deref()) {
+                Ok(interface) => interface,
+                Err(error) => {
+                    error!("Failed resolving UdpMulticast interface: {error:?}");
+                    continue;
+                }
+            };
+
+            debug!("Trying to join multicast group {group_addr:?} on interface {interface:?}...");
+
+            let sockets =
+                self.create_receive_socket(group_addr, interface)
+                    .and_then(|receive_socket| {
+                        Ok((receive_socket, self.create_send_socket(interface)?))
+                    });
+            let (receive_socket, send_socket) = match sockets {
+                Ok(sockets) => sockets,
+                Err(error) => {
+                    error!("Failed joining UdpMulticast group {group_addr:?}: {error:?}");
+                    continue;
+                }
+            };
+
+            debug!("UdpMulticast successfully joined group {group_addr:?}");
+
+            let own_addresses = match send_socket.local_addr() {
+                Ok(send_addr) => local_addresses(send_addr.port()),
+                Err(error) => {
+                    error!("Failed getting the UdpMulticast send address: {error:?}");
+                    continue;
+                }
+            };
+
+            let mut writer = UdpFramed::new(
+                send_socket,
+                MavlinkCodec::<true, true, false, false, false, false>::default(),
+            );
+            let mut reader = UdpFramed::new(
+                receive_socket,
+                MavlinkCodec::<true, true, false, false, false, false>::default(),
+            );
+
+            let group_addr = SocketAddr::V4(group_addr);
+
+            tokio::select! {
+                result = udp_multicast_send_task(&mut writer, &group_addr, &self.senders, &context) => {
+                    if let Err(error) = result {
+                        error!("Error in send task for {group_addr}: {error:?}");
+                    }
+                }
+                result = udp_multicast_receive_task(&mut reader, &own_addresses, &self.senders, &context) => {
+                    if let Err(error) = result {
+                        error!("Error in receive task for {group_addr}: {error:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    fn info(&self) -> Box<dyn DriverInfo> {
+        return Box::new(UdpMulticastInfo);
+    }
+
+    fn name(&self) -> Arc<String> {
+        self.name.load_full()
+    }
+
+    fn uuid(&self) -> &DriverUuid {
+        &self.uuid
+    }
+
+    fn clients(&self) -> Option<ClientRegistry> {
+        Some(self.senders.clone())
+    }
+}
+
+/// Receives messages from the HUB Channel and sends them to the multicast group
+#[instrument(level = "debug", skip(writer, senders, context))]
+async fn udp_multicast_send_task<S>(
+    writer: &mut S,
+    group_addr: &SocketAddr,
+    senders: &ClientRegistry,
+    context: &SendReceiveContext,
+) -> Result<()>
+where
+    S: Sink<(Packet, SocketAddr), Error = std::io::Error> + std::marker::Unpin,
+{
+    let mut hub_receiver = context.hub_sender.subscribe();
+
+    loop {
+        let message = match hub_receiver.recv().await {
+            Ok(message) => message,
+            Err(broadcast::error::RecvError::Closed) => {
+                error!("Hub channel closed!");
+                break;
+            }
+            Err(broadcast::error::RecvError::Lagged(count)) => {
+                warn!("Channel lagged by {count} messages.");
+                continue;
+            }
+        };
+
+        // Every group member already got what any other member sent, so don't echo it back
+        if let Ok(origin) = message.origin.parse::<SocketAddr>() {
+            if senders.contains(&origin).await {
+                continue;
+            }
+        }
+
+        // Answers to a member go through the group, like everything else
+        let origin = match &message.destination {
+            Some(destination) => match destination.parse::<SocketAddr>() {
+                Ok(member) if senders.contains(&member).await => destination.clone(),
+                _ => group_addr.to_string(),
+            },
+            None => group_addr.to_string(),
+        };
+        if !should_send(&message, &origin).await {
+            continue;
+        }
+
+        context.stats.write().await.stats.update_output(&message);
+
+        if let Err(error) = context
+            .on_message_output
+            .call_until_error(message.clone())
+            .await
+        {
+            debug!("Dropping message: on_message_output callback returned error: {error:?}");
+            continue;
+        }
+
+        if let Err(io_error) = writer.send(((**message).clone(), *group_addr)).await {
+            error!("Failed to send message to {group_addr}: {io_error:?}");
+            break;
+        }
+
+        trace!("Message sent to {group_addr}: {:?}", message.as_slice());
+    }
+
+    debug!("Driver sender task stopped!");
+
+    Ok(())
+}
+
+/// Receives messages from the multicast group and sends them to the HUB Channel
+#[instrument(level = "debug", skip(reader, own_addresses, senders, context))]
+async fn udp_multicast_receive_task<T>(
+    reader: &mut T,
+    own_addresses: &HashSet<SocketAddr>,
+    senders: &ClientRegistry,
+    context: &SendReceiveContext,
+) -> Result<()>
+where
+    T: Stream<Item = std::io::Result<(std::result::Result<Packet, DecoderError>, SocketAddr)>>
+        + std::marker::Unpin,
+{
+    loop {
+        let (packet, sender_addr) = match reader.next().await {
+            Some(Ok((Ok(packet), sender_addr))) => (packet, sender_addr),
+            Some(Ok((Err(decode_error), sender_addr))) => {
+                error!(origin = ?sender_addr, "Failed to decode packet: {decode_error:?}");
+                continue;
+            }
+            Some(Err(io_error)) => {
+                error!("Critical error trying to decode data from: {io_error:?}");
+                break;
+            }
+            None => break,
+        };
+
+        if own_addresses.contains(&sender_addr) {
+            continue; // Our own message, looped back by the kernel
+        }
+
+        if let Err(rejection) = senders.admit(&sender_addr).await {
+            trace!(origin = ?sender_addr, "Dropping message: {rejection}");
+            context.stats.write().await.stats.update_rejected();
+            continue;
+        }
+
+        let message = Arc::new(Protocol::new(&sender_addr.to_string(), packet));
+
+        trace!(origin = ?sender_addr, "Received message: {message:?}");
+
+        context.stats.write().await.stats.update_input(&message);
+
+        if !senders.contains(&sender_addr).await {
+            senders.insert(sender_addr).await;
+        }
+        senders.update_input(&sender_addr, &message).await;
+
+        if let Err(error) = context
+            .on_message_input
+            .call_until_error(message.clone())
+            .await
+        {
+            debug!(origin = ?sender_addr, "Dropping message: on_message_input callback returned error: {error:?}");
+            continue;
+        }
+
+        if let Err(send_error) = context.send_to_hub(message) {
+            error!(origin = ?sender_addr, "Failed to send message to hub: {send_error:?}");
+            continue;
+        }
+
+        trace!(origin = ?sender_addr, "Message sent to hub");
+    }
+
+    debug!("Driver receiver task stopped!");
+
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl AccumulatedDriverStatsProvider for UdpMulticast {
+    async fn stats(&self) -> AccumulatedDriverStats {
+        self.stats.read().await.clone()
+    }
+
+    async fn reset_stats(&self) {
+        let mut stats = self.stats.write().await;
+        stats.stats.input = None;
+        stats.stats.output = None;
+        stats.stats.rejected = 0;
+
+        self.senders.reset_stats().await;
+    }
+}
+
+pub struct UdpMulticastInfo;
+impl DriverInfo for UdpMulticastInfo {
+    fn name(&self) -> &'static str {
+        "UdpMulticast"
+    }
+    fn valid_schemes(&self) -> &'static [&'static str] {
+        &["udpmulticast", "udpm"]
+    }
+
+    fn cli_example_legacy(&self) -> Vec<String> {
+        let first_schema = &self.valid_schemes()[0];
+        let second_schema = &self.valid_schemes()[1];
+        vec![
+            format!("{first_schema}:<GROUP_IP>:<PORT>"),
+            format!("{first_schema}:239.255.145.50:14550"),
+            format!("{second_schema}:239.255.145.50:14550"),
+        ]
+    }
+
+    fn cli_example_url(&self) -> Vec<String> {
+        let first_schema = &self.valid_schemes()[0];
+        let second_schema = &self.valid_schemes()[1];
+        vec![
+            format!(
+                "{first_schema}://<GROUP_IP>:<PORT>?iface=<INTERFACE?>&ttl=<TTL?>&loopback=<BOOL?>"
+            )
+            .to_string(),
+            url::Url::parse(&format!("{first_schema}://239.255.145.50:14550"))
+                .unwrap()
+                .to_string(),
+            url::Url::parse(&format!(
+                "{second_schema}://239.255.145.50:14550?iface=eth0&ttl=2&loopback=false"
+            ))
+            .unwrap()
+            .to_string(),
+        ]
+    }
+
+    fn create_endpoint_from_url(&self, url: &url::Url) -> Result<Arc<dyn Driver>> {
+        let address = host_port_from_url(url)?;
+        let mut builder = UdpMulticast::builder("UdpMulticast", &address);
+
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "iface" => builder = builder.interface(&value),
+                "ttl" => {
+                    builder = builder.ttl(value.parse().context(format!("Invalid ttl {value:?}"))?)
+                }
+                "loopback" => {
+                    builder = builder.loopback(
+                        value
+                            .parse()
+                            .context(format!("Invalid loopback {value:?}"))?,
+                    )
+                }
+                _ => (),
+            }
+        }
+
+        let builder = with_script(builder, url)?;
+
+        Ok(Arc::new(builder.build()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mavlink::{
+        ardupilotmega::{MavMessage, HEARTBEAT_DATA},
+        MavHeader,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_resolve_interface() {
+        assert_eq!(resolve_interface(None).unwrap(), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(
+            resolve_interface(Some("192.168.2.1")).unwrap(),
+            Ipv4Addr::new(192, 168, 2, 1)
+        );
+        #[cfg(target_os = "linux")]
+        assert_eq!(resolve_interface(Some("lo")).unwrap(), Ipv4Addr::LOCALHOST);
+        assert!(resolve_interface(Some("not-an-interface")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_senders() {
+        let (hub_sender, mut hub_receiver) = broadcast::channel(16);
+        let context = SendReceiveContext {
+            hub_sender,
+            on_message_output: Callbacks::default(),
+            on_message_input: Callbacks::default(),
+            stats: Arc::new(RwLock::new(AccumulatedDriverStats::new(
+                Arc::new("test".to_string()),
+                &UdpMulticastInfo,
+            ))),
+            driver: DriverUuid::nil(),
+        };
+
+        let packet = || {
+            let message = MavMessage::HEARTBEAT(HEARTBEAT_DATA::default());
+            (*Protocol::from_mavlink_raw(MavHeader::default(), &message, "")).clone()
+        };
+        // Another consumer in this host, using the group port, and the driver itself
+        let own_addr = SocketAddr::from(([127, 0, 0, 1], 40000));
+        let neighbor_addr = SocketAddr::from(([127, 0, 0, 1], 14550));
+        let member_addr = SocketAddr::from(([192, 168, 2, 2], 14550));
+        let mut reader = futures::stream::iter(
+            [neighbor_addr, own_addr, member_addr, member_addr]
+                .map(|address| Ok((Ok(packet()), address))),
+        );
+
+        let senders = ClientRegistry::default();
+        udp_multicast_receive_task(&mut reader, &HashSet::from([own_addr]), &senders, &context)
+            .await
+            .unwrap();
+
+        let mut origins = Vec::new();
+        while let Ok(message) = hub_receiver.try_recv() {
+            origins.push(message.origin.clone());
+        }
+        assert_eq!(
+            origins,
+            vec![
+                neighbor_addr.to_string(),
+                member_addr.to_string(),
+                member_addr.to_string()
+            ]
+        );
+
+        let senders = senders.list().await;
+        assert_eq!(
+            senders
+                .iter()
+                .map(|sender| sender.address)
+                .collect::<Vec<_>>(),
+            vec![neighbor_addr, member_addr]
+        );
+        assert!(senders[1].stats.input.is_some());
+    }
+}
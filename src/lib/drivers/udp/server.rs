@@ -1,7 +1,8 @@
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, sync::Arc};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use futures::{Stream, StreamExt};
+use ipnet::IpNet;
 use mavlink_codec::{codec::MavlinkCodec, error::DecoderError, Packet};
 use tokio::{
     net::UdpSocket,
@@ -13,7 +14,13 @@ use tracing::*;
 
 use crate::{
     callbacks::{Callbacks, MessageCallback},
-    drivers::{generic_tasks::SendReceiveContext, udp::udp_send_task, Driver, DriverInfo},
+    drivers::{
+        clients::{parse_allowlist, ClientRegistry},
+        generic_tasks::SendReceiveContext,
+        host_port_from_url, resolve_address,
+        udp::udp_send_task,
+        with_script, Driver, DriverInfo,
+    },
     protocol::Protocol,
     stats::{
         accumulated::driver::{AccumulatedDriverStats, AccumulatedDriverStatsProvider},
@@ -29,16 +36,9 @@ pub struct UdpServer {
     on_message_input: Callbacks<Arc<Protocol>>,
     on_message_output: Callbacks<Arc<Protocol>>,
     stats: Arc<RwLock<AccumulatedDriverStats>>,
+    clients: ClientRegistry,
 }
 
-type Clients = HashMap<
-    SocketAddr,
-    (
-        JoinHandle<std::result::Result<(), anyhow::Error>>,
-        tokio::time::Instant,
-    ),
->;
-
 pub struct UdpServerBuilder(UdpServer);
 
 impl UdpServerBuilder {
@@ -46,6 +46,18 @@ impl UdpServerBuilder {
         self.0
     }
 
+    /// Senders beyond this number are ignored
+    pub fn max_clients(mut self, max_clients: usize) -> Self {
+        self.0.clients = self.0.clients.with_max_clients(max_clients);
+        self
+    }
+
+    /// Packets from outside these networks are dropped
+    pub fn allow(mut self, allowlist: Vec<IpNet>) -> Self {
+        self.0.clients = self.0.clients.with_allowlist(allowlist);
+        self
+    }
+
     pub fn on_message_input<C>(self, callback: C) -> Self
     where
         C: MessageCallback<Arc<Protocol>>,
@@ -78,6 +90,7 @@ impl UdpServer {
                 name,
                 &UdpServerInfo,
             ))),
+            clients: ClientRegistry::default(),
         })
     }
 }
@@ -86,13 +99,12 @@ impl UdpServer {
 impl Driver for UdpServer {
     #[instrument(level = "debug", skip(self, hub_sender))]
     async fn run(&self, hub_sender: broadcast::Sender<Arc<Protocol>>) -> Result<()> {
-        let local_addr = self.local_addr.parse::<SocketAddr>()?;
-
         let context = SendReceiveContext {
             hub_sender,
             on_message_output: self.on_message_output.clone(),
             on_message_input: self.on_message_input.clone(),
             stats: self.stats.clone(),
+            driver: self.uuid,
         };
 
         let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
@@ -104,6 +116,14 @@ impl Driver for UdpServer {
                 interval.tick().await;
             }
 
+            let local_addr = match resolve_address(&self.local_addr).await {
+                Ok(local_addr) => local_addr,
+                Err(error) => {
+                    error!("Failed resolving UdpServer local address: {error:?}");
+                    continue;
+                }
+            };
+
             debug!("Trying to bind to address {local_addr:?}...");
 
             let socket = match UdpSocket::bind(&local_addr).await {
@@ -119,9 +139,21 @@ impl Driver for UdpServer {
             let codec = MavlinkCodec::<true, true, false, false, false, false>::default();
             let (_writer, mut reader) = UdpFramed::new(socket.clone(), codec).split();
 
-            if let Err(error) = udp_receive_task(&mut reader, socket, local_addr, &context).await {
+            if let Err(error) = udp_receive_task(
+                &mut reader,
+                socket,
+                local_addr,
+                &self.clients,
+                crate::cli::udp_server_timeout(),
+                &context,
+            )
+            .await
+            {
                 error!("Error in receive task for {local_addr}: {error:?}");
             }
+
+            // The clients were served by the socket that is now gone
+            self.clients.clear().await;
         }
     }
 
@@ -137,24 +169,26 @@ impl Driver for UdpServer {
     fn uuid(&self) -> &DriverUuid {
         &self.uuid
     }
+
+    fn clients(&self) -> Option<ClientRegistry> {
+        Some(self.clients.clone())
+    }
 }
 
 /// Receives messages from a Stream and sends them to the HUB Channel
-#[instrument(level = "debug", skip(reader, context))]
+#[instrument(level = "debug", skip(reader, clients, context))]
 async fn udp_receive_task<T>(
     reader: &mut T,
     socket: Arc<UdpSocket>,
     local_addr: SocketAddr,
+    clients: &ClientRegistry,
+    client_timeout: Option<tokio::time::Duration>,
     context: &SendReceiveContext,
 ) -> Result<()>
 where
     T: Stream<Item = std::io::Result<(std::result::Result<Packet, DecoderError>, SocketAddr)>>
         + std::marker::Unpin,
 {
-    let mut clients: Clients = HashMap::new();
-
-    let client_timeout = crate::cli::udp_server_timeout();
-
     loop {
         let (packet, client_addr) = match reader.next().await {
             Some(Ok((Ok(packet), client_addr))) => (packet, client_addr),
@@ -169,66 +203,46 @@ where
             None => break,
         };
 
+        // Idle clients go first, so they free their slots for this one
+        if let Some(timeout) = client_timeout {
+            clients.disconnect_idle(timeout).await;
+        }
+
+        if let Err(rejection) = clients.admit(&client_addr).await {
+            trace!(origin = ?client_addr, "Dropping message: {rejection}");
+            context.stats.write().await.stats.update_rejected();
+            continue;
+        }
+
         let message = Arc::new(Protocol::new(&client_addr.to_string(), packet));
 
         trace!(origin = ?client_addr, "Received message: {message:?}");
 
         context.stats.write().await.stats.update_input(&message);
 
-        for future in context.on_message_input.call_all(message.clone()) {
-            if let Err(error) = future.await {
-                debug!(origin = ?client_addr, "Dropping message: on_message_input callback returned error: {error:?}");
-                continue;
-            }
+        if let Err(error) = context
+            .on_message_input
+            .call_until_error(message.clone())
+            .await
+        {
+            debug!(origin = ?client_addr, "Dropping message: on_message_input callback returned error: {error:?}");
+            continue;
         }
 
         // Update clients
-        {
-            let socket = socket.clone();
-
-            clients
-                .entry(client_addr)
-                .and_modify(|(_task, time)| {
-                    // Time refresh
-                    *time = tokio::time::Instant::now();
-                })
-                .or_insert_with(move || {
-                    let task = spawn_send_task(socket.clone(), client_addr, context);
-
-                    debug!("New client added: {client_addr:?}");
-
-                    let current_time = tokio::time::Instant::now();
-
-                    (task, current_time)
-                });
+        if !clients.contains(&client_addr).await {
+            clients.insert(client_addr).await;
+            let task = spawn_send_task(socket.clone(), client_addr, clients, context);
+            clients.set_task(&client_addr, task.abort_handle()).await;
+        } else if clients.is_task_finished(&client_addr).await {
+            debug!("Recreating sending task for client {client_addr:?}");
+            let task = spawn_send_task(socket.clone(), client_addr, clients, context);
+            clients.set_task(&client_addr, task.abort_handle()).await;
         }
 
-        {
-            let socket = socket.clone();
-
-            clients.retain(move |addr, (task, time)| {
-                // Client Timeout
-                if let Some(timeout) = client_timeout {
-                    if time.elapsed() > timeout {
-                        debug!("Client {addr} timed out.");
-
-                        task.abort();
+        clients.update_input(&client_addr, &message).await;
 
-                        return false;
-                    }
-                }
-
-                // Client task recreation
-                if task.is_finished() {
-                    debug!("Recreating sending task for client {addr:?}");
-                    *task = spawn_send_task(socket.clone(), *addr, context);
-                }
-
-                true
-            });
-        }
-
-        if let Err(send_error) = context.hub_sender.send(message) {
+        if let Err(send_error) = context.send_to_hub(message) {
             error!(origin = ?client_addr, "Failed to send message to hub: {send_error:?}");
             continue;
         }
@@ -244,13 +258,14 @@ where
 fn spawn_send_task(
     socket: Arc<UdpSocket>,
     client_addr: SocketAddr,
+    clients: &ClientRegistry,
     context: &SendReceiveContext,
 ) -> JoinHandle<std::result::Result<(), anyhow::Error>> {
     let codec = MavlinkCodec::<true, true, false, false, false, false>::default();
     let (mut writer, _reader) = UdpFramed::new(socket.clone(), codec).split();
 
     tokio::spawn({
-        let context = context.clone();
+        let context = clients.client_context(client_addr, context);
         async move { udp_send_task(&mut writer, &client_addr, &context).await }
     })
 }
@@ -264,7 +279,10 @@ impl AccumulatedDriverStatsProvider for UdpServer {
     async fn reset_stats(&self) {
         let mut stats = self.stats.write().await;
         stats.stats.input = None;
-        stats.stats.output = None
+        stats.stats.output = None;
+        stats.stats.rejected = 0;
+
+        self.clients.reset_stats().await;
     }
 }
 
@@ -291,21 +309,106 @@ impl DriverInfo for UdpServerInfo {
         let first_schema = &self.valid_schemes()[0];
         let second_schema = &self.valid_schemes()[1];
         vec![
-            format!("{first_schema}://<IP>:<PORT>").to_string(),
-            url::Url::parse(&format!("{first_schema}://0.0.0.0:14550"))
-                .unwrap()
+            format!("{first_schema}://<IP>:<PORT>?allow=<NETWORKS?>&max_clients=<NUMBER?>")
                 .to_string(),
-            url::Url::parse(&format!("{second_schema}://127.0.0.1:14660"))
+            url::Url::parse(&format!("{first_schema}://0.0.0.0:14550"))
                 .unwrap()
                 .to_string(),
+            url::Url::parse(&format!(
+                "{second_schema}://127.0.0.1:14660?allow=192.168.2.0/24,10.0.0.5&max_clients=4"
+            ))
+            .unwrap()
+            .to_string(),
         ]
     }
 
-    fn create_endpoint_from_url(&self, url: &url::Url) -> Option<Arc<dyn Driver>> {
-        let host = url.host_str().unwrap();
-        let port = url.port().unwrap();
-        Some(Arc::new(
-            UdpServer::builder("UdpServer", &format!("{host}:{port}")).build(),
-        ))
+    fn create_endpoint_from_url(&self, url: &url::Url) -> Result<Arc<dyn Driver>> {
+        let address = host_port_from_url(url)?;
+        let mut builder = UdpServer::builder("UdpServer", &address);
+
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "max_clients" => {
+                    builder = builder.max_clients(
+                        value
+                            .parse()
+                            .context(format!("Invalid max_clients {value:?}"))?,
+                    )
+                }
+                "allow" => builder = builder.allow(parse_allowlist(&value)?),
+                _ => (),
+            }
+        }
+
+        let builder = with_script(builder, url)?;
+
+        Ok(Arc::new(builder.build()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mavlink::{
+        ardupilotmega::{MavMessage, HEARTBEAT_DATA},
+        MavHeader,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_idle_clients_free_their_slots() {
+        let (hub_sender, mut hub_receiver) = broadcast::channel(16);
+        let context = SendReceiveContext {
+            hub_sender,
+            on_message_output: Callbacks::default(),
+            on_message_input: Callbacks::default(),
+            stats: Arc::new(RwLock::new(AccumulatedDriverStats::new(
+                Arc::new("test".to_string()),
+                &UdpServerInfo,
+            ))),
+            driver: DriverUuid::nil(),
+        };
+
+        let packet = || {
+            let message = MavMessage::HEARTBEAT(HEARTBEAT_DATA::default());
+            (*Protocol::from_mavlink_raw(MavHeader::default(), &message, "")).clone()
+        };
+        // The second client arrives once the first one went idle
+        let first_addr = SocketAddr::from(([192, 168, 2, 2], 14550));
+        let second_addr = SocketAddr::from(([192, 168, 2, 3], 14550));
+        let reader = futures::stream::iter([first_addr, second_addr]).then(|address| async move {
+            if address == second_addr {
+                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+            }
+            Ok((Ok(packet()), address))
+        });
+        let mut reader = std::pin::pin!(reader);
+
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let local_addr = socket.local_addr().unwrap();
+        let clients = ClientRegistry::default().with_max_clients(1);
+        udp_receive_task(
+            &mut reader,
+            socket,
+            local_addr,
+            &clients,
+            Some(tokio::time::Duration::from_millis(50)),
+            &context,
+        )
+        .await
+        .unwrap();
+
+        let mut origins = Vec::new();
+        while let Ok(message) = hub_receiver.try_recv() {
+            origins.push(message.origin.clone());
+        }
+        assert_eq!(
+            origins,
+            vec![first_addr.to_string(), second_addr.to_string()]
+        );
+
+        let clients = clients.list().await;
+        assert_eq!(clients.len(), 1);
+        assert_eq!(clients[0].address, second_addr);
     }
 }
@@ -0,0 +1,27 @@
+use anyhow::{anyhow, Result};
+
+pub mod client;
+
+/// How MAVLink messages are carried over WebSocket messages
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WebsocketFormat {
+    /// Raw MAVLink packets, in binary messages
+    #[default]
+    Binary,
+    /// `MAVLinkJSON` messages, in text messages
+    Json,
+}
+
+impl std::str::FromStr for WebsocketFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format.to_ascii_lowercase().as_str() {
+            "binary" => Ok(Self::Binary),
+            "json" => Ok(Self::Json),
+            _ => Err(anyhow!(
+                "Invalid format {format:?}, expected \"binary\" or \"json\""
+            )),
+        }
+    }
+}
@@ -8,7 +8,10 @@ use zenoh;
 
 use crate::{
     callbacks::{Callbacks, MessageCallback},
-    drivers::{generic_tasks::SendReceiveContext, Driver, DriverInfo},
+    drivers::{
+        generic_tasks::{should_send, SendReceiveContext},
+        host_port_from_url, Driver, DriverInfo,
+    },
     mavlink_json::MAVLinkJSON,
     protocol::Protocol,
     stats::{
@@ -99,14 +102,16 @@ impl Zenoh {
 
             context.stats.write().await.stats.update_input(&bus_message);
 
-            for future in context.on_message_input.call_all(bus_message.clone()) {
-                if let Err(error) = future.await {
-                    debug!("Dropping message: on_message_input callback returned error: {error:?}");
-                    continue;
-                }
+            if let Err(error) = context
+                .on_message_input
+                .call_until_error(bus_message.clone())
+                .await
+            {
+                debug!("Dropping message: on_message_input callback returned error: {error:?}");
+                continue;
             }
 
-            if let Err(error) = context.hub_sender.send(bus_message) {
+            if let Err(error) = context.send_to_hub(bus_message) {
                 error!("Failed to send message to hub: {error:?}");
                 continue;
             }
@@ -136,19 +141,19 @@ impl Zenoh {
                 }
             };
 
-            if message.origin.eq("zenoh") {
-                continue; // Don't do loopback
+            if !should_send(&message, "zenoh").await {
+                continue;
             }
 
             context.stats.write().await.stats.update_output(&message);
 
-            for future in context.on_message_output.call_all(message.clone()) {
-                if let Err(error) = future.await {
-                    debug!(
-                        "Dropping message: on_message_output callback returned error: {error:?}"
-                    );
-                    continue;
-                }
+            if let Err(error) = context
+                .on_message_output
+                .call_until_error(message.clone())
+                .await
+            {
+                debug!("Dropping message: on_message_output callback returned error: {error:?}");
+                continue;
             }
 
             let Ok(mavlink_json) = message
@@ -202,6 +207,7 @@ impl Driver for Zenoh {
             on_message_output: self.on_message_output.clone(),
             on_message_input: self.on_message_input.clone(),
             stats: self.stats.clone(),
+            driver: self.uuid,
         };
 
         // Change this based on the endpoint configuration
@@ -285,10 +291,9 @@ impl DriverInfo for ZenohInfo {
         vec![format!("{first_schema}://<IP>:<PORT>").to_string()]
     }
 
-    fn create_endpoint_from_url(&self, url: &url::Url) -> Option<Arc<dyn Driver>> {
+    fn create_endpoint_from_url(&self, url: &url::Url) -> Result<Arc<dyn Driver>> {
         println!("{}", &url);
-        let _host = url.host_str().unwrap();
-        let _port = url.port().unwrap();
-        Some(Arc::new(Zenoh::builder("Zenoh").build()))
+        let _address = host_port_from_url(url)?;
+        Ok(Arc::new(Zenoh::builder("Zenoh").build()))
     }
 }
@@ -43,6 +43,9 @@ impl HubActor {
                     let result = self.remove_driver(uuid).await;
                     let _ = response.send(result);
                 }
+                HubCommand::GetDriver { uuid, response } => {
+                    let _ = response.send(self.drivers.get(&uuid).cloned());
+                }
                 HubCommand::GetDrivers { response } => {
                     let drivers = self.drivers().await;
                     let _ = response.send(drivers);
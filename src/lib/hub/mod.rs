@@ -74,6 +74,18 @@ pub async fn remove_driver(uuid: DriverUuid) -> Result<()> {
     response_rx.await?
 }
 
+pub async fn driver(uuid: DriverUuid) -> Result<Option<Arc<dyn Driver>>> {
+    let (response_tx, response_rx) = oneshot::channel();
+    HUB.sender
+        .send(HubCommand::GetDriver {
+            uuid,
+            response: response_tx,
+        })
+        .await?;
+    let res = response_rx.await?;
+    Ok(res)
+}
+
 pub async fn drivers() -> Result<IndexMap<DriverUuid, Box<dyn DriverInfo>>> {
     let (response_tx, response_rx) = oneshot::channel();
     HUB.sender
@@ -25,6 +25,10 @@ pub enum HubCommand {
         uuid: DriverUuid,
         response: oneshot::Sender<Result<()>>,
     },
+    GetDriver {
+        uuid: DriverUuid,
+        response: oneshot::Sender<Option<Arc<dyn Driver>>>,
+    },
     GetDrivers {
         response: oneshot::Sender<IndexMap<DriverUuid, Box<dyn DriverInfo>>>,
     },
@@ -5,5 +5,8 @@ pub mod hub;
 pub mod logger;
 pub mod mavlink_json;
 pub mod protocol;
+#[cfg(feature = "scripting")]
+pub mod scripting;
 pub mod stats;
+pub mod vehicles;
 pub mod web;
@@ -7,12 +7,22 @@ use serde::Serialize;
 use crate::{
     cli,
     mavlink_json::{MAVLinkJSON, MAVLinkJSONHeader},
+    stats::driver::DriverUuid,
 };
 
 #[derive(Debug, PartialEq, Serialize)]
 pub struct Protocol {
     pub origin: String,
     pub timestamp: u64,
+    /// Origin of the only link this message should be sent to, like for answers to a single client
+    #[serde(skip)]
+    pub destination: Option<String>,
+    /// Whether the server answers this message itself, decided once for every link and cache
+    #[serde(skip)]
+    pub(crate) answered_locally: tokio::sync::OnceCell<bool>,
+    /// The driver it came through, set when it enters the hub
+    #[serde(skip)]
+    driver: std::sync::OnceLock<DriverUuid>,
     #[serde(skip)]
     packet: Packet,
 }
@@ -22,6 +32,9 @@ impl Protocol {
         Self {
             origin: origin.to_string(),
             timestamp: chrono::Utc::now().timestamp_micros() as u64,
+            destination: None,
+            answered_locally: tokio::sync::OnceCell::new(),
+            driver: std::sync::OnceLock::new(),
             packet,
         }
     }
@@ -30,6 +43,9 @@ impl Protocol {
         Self {
             origin: origin.to_string(),
             timestamp,
+            destination: None,
+            answered_locally: tokio::sync::OnceCell::new(),
+            driver: std::sync::OnceLock::new(),
             packet,
         }
     }
@@ -55,10 +71,23 @@ impl Protocol {
         Self {
             origin: origin.to_string(),
             timestamp: chrono::Utc::now().timestamp_micros() as u64,
+            destination: None,
+            answered_locally: tokio::sync::OnceCell::new(),
+            driver: std::sync::OnceLock::new(),
             packet,
         }
     }
 
+    /// The driver this message came through, unknown for the ones created by the server itself
+    pub fn driver(&self) -> Option<DriverUuid> {
+        self.driver.get().copied()
+    }
+
+    /// Marks the driver this message came through, keeping the first one set
+    pub(crate) fn set_driver(&self, driver: DriverUuid) {
+        let _ = self.driver.set(driver);
+    }
+
     pub async fn to_mavlink_json<M>(&self) -> Result<MAVLinkJSON<M>>
     where
         M: mavlink::Message,
@@ -0,0 +1,411 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Context, Result};
+use rhai::{CallFnOptions, Dynamic, Engine, ImmutableString, NativeCallContext, Scope, AST};
+use tracing::*;
+
+use crate::{callbacks::MessageCallback, hub, mavlink_json::MAVLinkJSON, protocol::Protocol};
+
+/// Name of the function each script must define
+const ENTRY_POINT: &str = "on_message";
+
+/// Upper bound of operations a single script call can run, so a runaway script can't stall the drivers
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// The messages generated by a single script call with `send(message)`, given to the call as its tag
+type Outbox = Arc<Mutex<Vec<Dynamic>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Messages received by the driver, on their way to the hub
+    Input,
+    /// Messages from the hub, on their way out of the driver
+    Output,
+}
+
+impl std::fmt::Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Direction::Input => write!(f, "input"),
+            Direction::Output => write!(f, "output"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Action {
+    /// Let the message through untouched
+    Keep,
+    /// Drop the message
+    Drop,
+    /// Drop the message and send this one to the hub instead
+    Replace(Dynamic),
+}
+
+/// A user script that runs against every decoded `MAVLinkJSON` message of a driver.
+///
+/// The script must define `fn on_message(message, direction)`, where `message` is the
+/// `MAVLinkJSON` object and `direction` is either `"input"` or `"output"`. Its return value decides
+/// what happens with the message:
+/// - `()` or `true`: the message goes through;
+/// - `false`: the message is dropped;
+/// - an object: the message is replaced by it (input direction only).
+///
+/// Scripts can also call `send(message)` to generate new messages into the hub, which have the
+/// script as their origin, and `alert(text)` to raise a warning in the logs.
+///
+/// ```rhai
+/// fn on_message(message, direction) {
+///     if message.message.type == "PARAM_SET" && direction == "input" {
+///         alert("Blocked PARAM_SET from " + message.header.system_id);
+///         return false;
+///     }
+///     true
+/// }
+/// ```
+pub struct Script {
+    path: PathBuf,
+    /// Of the messages generated by the script
+    origin: String,
+    engine: Engine,
+    ast: AST,
+}
+
+impl std::fmt::Debug for Script {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Script").field("path", &self.path).finish()
+    }
+}
+
+impl Script {
+    #[instrument(level = "debug")]
+    pub fn load(path: PathBuf) -> Result<Arc<Self>> {
+        let source = path.display().to_string();
+
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.register_fn("send", |context: NativeCallContext, message: Dynamic| {
+            if let Some(outbox) = context
+                .tag()
+                .and_then(|tag| tag.clone().try_cast::<Outbox>())
+            {
+                outbox.lock().unwrap().push(message);
+            }
+        });
+        engine.register_fn("alert", {
+            let source = source.clone();
+            move |text: ImmutableString| warn!(script = %source, "Script alert: {text}")
+        });
+
+        let ast = engine
+            .compile_file(path.clone())
+            .map_err(|error| anyhow!("Failed to compile script {source:?}: {error}"))?;
+
+        if !ast
+            .iter_functions()
+            .any(|function| function.name == ENTRY_POINT)
+        {
+            return Err(anyhow!(
+                "Script {source:?} does not define the function {ENTRY_POINT:?}"
+            ));
+        }
+
+        Ok(Arc::new(Self {
+            path,
+            origin: format!("script:{source}"),
+            engine,
+            ast,
+        }))
+    }
+
+    /// Loads the script named by the `script` option of an endpoint URL, if any
+    pub fn from_url(url: &url::Url) -> Result<Option<Arc<Self>>> {
+        let Some(path) = url
+            .query_pairs()
+            .find_map(|(key, value)| (key == "script").then(|| value.to_string()))
+        else {
+            return Ok(None);
+        };
+
+        let path = shellexpand::full(&path)
+            .context("Failed to expand script path")?
+            .to_string();
+
+        Self::load(PathBuf::from(path)).map(Some)
+    }
+
+    pub fn on_message_input(self: &Arc<Self>) -> impl MessageCallback<Arc<Protocol>> {
+        let script = self.clone();
+        move |message: Arc<Protocol>| {
+            let script = script.clone();
+            async move { script.process(message, Direction::Input).await }
+        }
+    }
+
+    pub fn on_message_output(self: &Arc<Self>) -> impl MessageCallback<Arc<Protocol>> {
+        let script = self.clone();
+        move |message: Arc<Protocol>| {
+            let script = script.clone();
+            async move { script.process(message, Direction::Output).await }
+        }
+    }
+
+    /// Runs the script entry point for a single message
+    pub fn evaluate(&self, message: Dynamic, direction: Direction) -> Result<Action> {
+        self.evaluate_with_outbox(message, direction, &Outbox::default())
+    }
+
+    fn evaluate_with_outbox(
+        &self,
+        message: Dynamic,
+        direction: Direction,
+        outbox: &Outbox,
+    ) -> Result<Action> {
+        let mut scope = Scope::new();
+        let result = self
+            .engine
+            .call_fn_with_options::<Dynamic>(
+                CallFnOptions::new().with_tag(outbox.clone()),
+                &mut scope,
+                &self.ast,
+                ENTRY_POINT,
+                (message, direction.to_string()),
+            )
+            .map_err(|error| anyhow!("Script {:?} failed: {error}", self.path))?;
+
+        if result.is_unit() {
+            return Ok(Action::Keep);
+        }
+
+        if let Ok(keep) = result.as_bool() {
+            return Ok(if keep { Action::Keep } else { Action::Drop });
+        }
+
+        if result.is_map() {
+            return Ok(Action::Replace(result));
+        }
+
+        Err(anyhow!(
+            "Script {:?} returned an unexpected value: {result:?}",
+            self.path
+        ))
+    }
+
+    #[instrument(level = "trace", skip_all)]
+    async fn process(&self, message: Arc<Protocol>, direction: Direction) -> Result<()> {
+        let Ok(mavlink_json) = message
+            .to_mavlink_json::<mavlink::ardupilotmega::MavMessage>()
+            .await
+        else {
+            return Ok(()); // Scripts only see messages that can be decoded
+        };
+
+        let input = match rhai::serde::to_dynamic(&mavlink_json) {
+            Ok(input) => input,
+            Err(error) => {
+                warn!("Failed to convert message to script object: {error}");
+                return Ok(());
+            }
+        };
+
+        // Each call has its own outbox, so concurrent calls from other drivers don't take its messages
+        let outbox = Outbox::default();
+        let action = self.evaluate_with_outbox(input, direction, &outbox);
+
+        self.flush_outbox(&outbox).await;
+
+        match action {
+            Ok(Action::Keep) => Ok(()),
+            Ok(Action::Drop) => Err(anyhow!("Message dropped by script")),
+            Ok(Action::Replace(replacement)) => {
+                if direction == Direction::Output {
+                    warn!("Script {:?} tried to modify an output message, only input messages can be modified", self.path);
+                    return Ok(());
+                }
+
+                self.send_to_hub(&replacement, &message.origin).await?;
+
+                Err(anyhow!("Message replaced by script"))
+            }
+            Err(error) => {
+                // A broken script should not silence the link
+                warn!("{error:?}");
+                Ok(())
+            }
+        }
+    }
+
+    async fn flush_outbox(&self, outbox: &Outbox) {
+        let messages = std::mem::take(&mut *outbox.lock().unwrap());
+
+        for message in messages {
+            if let Err(error) = self.send_to_hub(&message, &self.origin).await {
+                warn!(
+                    "Failed to send message generated by script {:?}: {error:?}",
+                    self.path
+                );
+            }
+        }
+    }
+
+    async fn send_to_hub(&self, message: &Dynamic, origin: &str) -> Result<()> {
+        let content =
+            rhai::serde::from_dynamic::<MAVLinkJSON<mavlink::ardupilotmega::MavMessage>>(message)
+                .map_err(|error| anyhow!("Not a valid MAVLinkJSON message: {error}"))?;
+
+        let message = Arc::new(Protocol::from_mavlink_raw(
+            content.header.inner,
+            &content.message,
+            origin,
+        ));
+
+        hub::sender()
+            .await?
+            .send(message)
+            .map_err(|error| anyhow!("Failed to send message to hub: {error:?}"))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mavlink::{
+        ardupilotmega::{MavMessage, HEARTBEAT_DATA},
+        MavHeader,
+    };
+    use mavlink_codec::{error::DecoderError, Packet};
+    use tokio::sync::{broadcast, RwLock};
+
+    use super::*;
+    use crate::{
+        callbacks::Callbacks,
+        drivers::{
+            generic_tasks::{default_receive_task, SendReceiveContext},
+            tcp::client::TcpClientInfo,
+        },
+        stats::{accumulated::driver::AccumulatedDriverStats, driver::DriverUuid},
+    };
+
+    fn load_source(source: &str) -> Result<Arc<Script>> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("script.rhai");
+        std::fs::write(&path, source).unwrap();
+        Script::load(path)
+    }
+
+    fn heartbeat(system_id: i64) -> Dynamic {
+        let mut header = rhai::Map::new();
+        header.insert("system_id".into(), system_id.into());
+        header.insert("component_id".into(), Dynamic::from_int(1));
+        header.insert("sequence".into(), Dynamic::from_int(0));
+
+        let mut message = rhai::Map::new();
+        message.insert("type".into(), "HEARTBEAT".into());
+
+        let mut mavlink_json = rhai::Map::new();
+        mavlink_json.insert("header".into(), header.into());
+        mavlink_json.insert("message".into(), message.into());
+        mavlink_json.into()
+    }
+
+    #[test]
+    fn test_script_actions() {
+        let script = load_source(
+            r#"
+            fn on_message(message, direction) {
+                if message.header.system_id == 2 {
+                    return false;
+                }
+                if message.header.system_id == 3 && direction == "input" {
+                    message.header.system_id = 4;
+                    return message;
+                }
+            }
+            "#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            script.evaluate(heartbeat(1), Direction::Input).unwrap(),
+            Action::Keep
+        ));
+        assert!(matches!(
+            script.evaluate(heartbeat(2), Direction::Output).unwrap(),
+            Action::Drop
+        ));
+        assert!(matches!(
+            script.evaluate(heartbeat(3), Direction::Output).unwrap(),
+            Action::Keep
+        ));
+
+        let Action::Replace(replacement) = script.evaluate(heartbeat(3), Direction::Input).unwrap()
+        else {
+            panic!("Expected the message to be replaced");
+        };
+        let header = replacement
+            .cast::<rhai::Map>()
+            .get("header")
+            .unwrap()
+            .clone()
+            .cast::<rhai::Map>();
+        assert_eq!(header.get("system_id").unwrap().as_int().unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_script_drops_driver_messages() {
+        let script = load_source(
+            r#"
+            fn on_message(message, direction) {
+                message.header.system_id != 2
+            }
+            "#,
+        )
+        .unwrap();
+
+        let (hub_sender, mut hub_receiver) = broadcast::channel(16);
+        let on_message_input = Callbacks::default();
+        on_message_input.add_callback(script.on_message_input());
+        let context = SendReceiveContext {
+            hub_sender,
+            on_message_output: Callbacks::default(),
+            on_message_input,
+            stats: Arc::new(RwLock::new(AccumulatedDriverStats::new(
+                Arc::new("test".to_string()),
+                &TcpClientInfo,
+            ))),
+            driver: DriverUuid::nil(),
+        };
+
+        let packet = |system_id| -> std::io::Result<Result<Packet, DecoderError>> {
+            let header = MavHeader {
+                system_id,
+                component_id: 1,
+                sequence: 0,
+            };
+            let message = MavMessage::HEARTBEAT(HEARTBEAT_DATA::default());
+            Ok(Ok(
+                (*Protocol::from_mavlink_raw(header, &message, "")).clone()
+            ))
+        };
+        let mut reader = futures::stream::iter(vec![packet(1), packet(2), packet(3)]);
+
+        default_receive_task(&mut reader, "test", &context)
+            .await
+            .unwrap();
+
+        let mut system_ids = Vec::new();
+        while let Ok(message) = hub_receiver.try_recv() {
+            system_ids.push(*message.system_id());
+        }
+        assert_eq!(system_ids, vec![1, 3]);
+    }
+
+    #[test]
+    fn test_script_without_entry_point() {
+        assert!(load_source("fn something_else() { true }").is_err());
+    }
+}
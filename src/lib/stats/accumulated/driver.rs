@@ -36,6 +36,8 @@ impl AccumulatedDriverStats {
 pub struct AccumulatedDriverStatsInner {
     pub input: Option<AccumulatedStatsInner>,
     pub output: Option<AccumulatedStatsInner>,
+    /// Packets or connections refused by the driver, e.g. from hosts outside its allowlist
+    pub rejected: u64,
 }
 
 impl AccumulatedDriverStatsInner {
@@ -54,4 +56,8 @@ impl AccumulatedDriverStatsInner {
             self.output.replace(AccumulatedStatsInner::default());
         }
     }
+
+    pub fn update_rejected(&mut self) {
+        self.rejected = self.rejected.wrapping_add(1);
+    }
 }
@@ -431,6 +431,7 @@ async fn update_driver_stats(
                     stats: DriverStatsInner {
                         input: new_input_stats,
                         output: new_output_stats,
+                        rejected: current_stats.stats.rejected,
                     },
                 },
             );
@@ -20,4 +20,5 @@ pub struct DriverStats {
 pub struct DriverStatsInner {
     pub input: Option<StatsInner>,
     pub output: Option<StatsInner>,
+    pub rejected: u64,
 }
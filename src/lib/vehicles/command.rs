@@ -0,0 +1,265 @@
+use std::sync::Arc;
+
+use mavlink::{
+    ardupilotmega::{
+        MavCmd, MavFrame, MavMessage, MavResult, COMMAND_ACK_DATA, COMMAND_INT_DATA,
+        COMMAND_LONG_DATA,
+    },
+    MessageData,
+};
+use num_traits::FromPrimitive;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::broadcast,
+    time::{Duration, Instant},
+};
+use tracing::*;
+
+use crate::{
+    protocol::Protocol,
+    vehicles::{enum_from_name, hub_sender, is_own_target, send, wait_message, VehicleError},
+};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(1500);
+const DEFAULT_RETRIES: u8 = 3;
+/// How long to wait for the next update once the vehicle reported the command as in progress
+const IN_PROGRESS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A `MAV_CMD`, by its name or its number
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum CommandId {
+    Id(u32),
+    Name(String),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CommandRequest {
+    pub command: CommandId,
+    /// From `param1` to `param7`, the missing ones are sent as zero.
+    /// For `COMMAND_INT`, `param5` and `param6` are the already scaled `x` and `y` integers.
+    #[serde(default)]
+    pub params: Vec<f64>,
+    /// Sends a `COMMAND_INT` with this `MAV_FRAME` instead of a `COMMAND_LONG`
+    pub frame: Option<String>,
+    /// How long to wait for the `COMMAND_ACK` of each attempt, in milliseconds
+    pub timeout_ms: Option<u64>,
+    /// How many times the command is sent again when it is not acknowledged
+    pub retries: Option<u8>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CommandResult {
+    /// The `MAV_RESULT` name, like `MAV_RESULT_ACCEPTED`
+    pub result: String,
+    pub progress: u8,
+    pub result_param2: i32,
+    pub attempts: u16,
+}
+
+impl CommandRequest {
+    fn command(&self) -> Result<MavCmd, VehicleError> {
+        match &self.command {
+            CommandId::Id(id) => MavCmd::from_u32(*id)
+                .ok_or_else(|| VehicleError::Invalid(format!("Unknown MAV_CMD {id}"))),
+            CommandId::Name(name) => enum_from_name("MAV_CMD_", name),
+        }
+    }
+
+    fn frame(&self) -> Result<Option<MavFrame>, VehicleError> {
+        self.frame
+            .as_deref()
+            .map(|frame| enum_from_name("MAV_FRAME_", frame))
+            .transpose()
+    }
+
+    fn message(
+        &self,
+        system_id: u8,
+        component_id: u8,
+        confirmation: u8,
+    ) -> Result<MavMessage, VehicleError> {
+        if self.params.len() > 7 {
+            return Err(VehicleError::Invalid(format!(
+                "Commands have 7 params, got {}",
+                self.params.len()
+            )));
+        }
+
+        let command = self.command()?;
+        let param = |index: usize| self.params.get(index).copied().unwrap_or_default();
+
+        let message = match self.frame()? {
+            None => MavMessage::COMMAND_LONG(COMMAND_LONG_DATA {
+                param1: param(0) as f32,
+                param2: param(1) as f32,
+                param3: param(2) as f32,
+                param4: param(3) as f32,
+                param5: param(4) as f32,
+                param6: param(5) as f32,
+                param7: param(6) as f32,
+                command,
+                target_system: system_id,
+                target_component: component_id,
+                confirmation,
+            }),
+            Some(frame) => MavMessage::COMMAND_INT(COMMAND_INT_DATA {
+                param1: param(0) as f32,
+                param2: param(1) as f32,
+                param3: param(2) as f32,
+                param4: param(3) as f32,
+                x: param(4) as i32,
+                y: param(5) as i32,
+                z: param(6) as f32,
+                command,
+                target_system: system_id,
+                target_component: component_id,
+                frame,
+                current: 0,
+                autocontinue: 0,
+            }),
+        };
+
+        Ok(message)
+    }
+}
+
+/// Sends a command and waits for its final `COMMAND_ACK`, sending it again while it is not acknowledged.
+/// `COMMAND_LONG` retries increment the `confirmation` field.
+#[instrument(level = "debug")]
+pub async fn send_command(
+    system_id: u8,
+    component_id: u8,
+    request: CommandRequest,
+) -> Result<CommandResult, VehicleError> {
+    let command = request.command()?;
+    // Validates the request before anything is sent
+    request.message(system_id, component_id, 0)?;
+
+    let timeout = request
+        .timeout_ms
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_TIMEOUT);
+    let retries = request.retries.unwrap_or(DEFAULT_RETRIES);
+
+    let hub_sender = hub_sender().await?;
+    // Subscribed before sending, so the answer can't be missed
+    let mut hub_receiver = hub_sender.subscribe();
+
+    // Wider than the retries, so the last attempt doesn't overflow it
+    let mut attempts: u16 = 0;
+    let mut in_progress = false;
+    loop {
+        if !in_progress {
+            if attempts > u16::from(retries) {
+                return Err(VehicleError::Timeout(format!(
+                    "No COMMAND_ACK for {command:?} after {attempts} attempts"
+                )));
+            }
+
+            // The confirmation field stops counting at its maximum
+            let confirmation = u8::try_from(attempts).unwrap_or(u8::MAX);
+            let message = request.message(system_id, component_id, confirmation)?;
+            send(&hub_sender, &message)?;
+            attempts += 1;
+        }
+
+        let deadline = Instant::now()
+            + if in_progress {
+                IN_PROGRESS_TIMEOUT
+            } else {
+                timeout
+            };
+
+        let Some(ack) = wait_ack(
+            &mut hub_receiver,
+            system_id,
+            component_id,
+            command,
+            deadline,
+        )
+        .await
+        else {
+            if in_progress {
+                return Err(VehicleError::Timeout(format!(
+                    "No final COMMAND_ACK for {command:?} after it was reported in progress"
+                )));
+            }
+            continue;
+        };
+
+        if ack.result == MavResult::MAV_RESULT_IN_PROGRESS {
+            debug!("{command:?} in progress: {}%", ack.progress);
+            in_progress = true;
+            continue;
+        }
+
+        return Ok(CommandResult {
+            result: format!("{:?}", ack.result),
+            progress: ack.progress,
+            result_param2: ack.result_param2,
+            attempts,
+        });
+    }
+}
+
+async fn wait_ack(
+    hub_receiver: &mut broadcast::Receiver<Arc<Protocol>>,
+    system_id: u8,
+    component_id: u8,
+    command: MavCmd,
+    deadline: Instant,
+) -> Option<COMMAND_ACK_DATA> {
+    wait_message(
+        hub_receiver,
+        (system_id, component_id),
+        &[COMMAND_ACK_DATA::ID],
+        deadline,
+        |message| match message {
+            MavMessage::COMMAND_ACK(ack)
+                if ack.command == command && is_own_target(ack.target_system) =>
+            {
+                Some(ack)
+            }
+            _ => None,
+        },
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_message() {
+        let request: CommandRequest =
+            serde_json::from_str(r#"{"command": "COMPONENT_ARM_DISARM", "params": [1]}"#).unwrap();
+
+        let MavMessage::COMMAND_LONG(command_long) = request.message(1, 1, 2).unwrap() else {
+            panic!("Expected a COMMAND_LONG");
+        };
+        assert_eq!(command_long.command, MavCmd::MAV_CMD_COMPONENT_ARM_DISARM);
+        assert_eq!(command_long.param1, 1.0);
+        assert_eq!(command_long.param2, 0.0);
+        assert_eq!(command_long.confirmation, 2);
+
+        let request: CommandRequest = serde_json::from_str(
+            r#"{"command": 192, "params": [-1, 1, 0, 0, 473977418, 85455939, 10], "frame": "GLOBAL_RELATIVE_ALT"}"#,
+        )
+        .unwrap();
+
+        let MavMessage::COMMAND_INT(command_int) = request.message(1, 1, 0).unwrap() else {
+            panic!("Expected a COMMAND_INT");
+        };
+        assert_eq!(command_int.command, MavCmd::MAV_CMD_DO_REPOSITION);
+        assert_eq!(command_int.frame, MavFrame::MAV_FRAME_GLOBAL_RELATIVE_ALT);
+        assert_eq!(command_int.x, 473977418);
+        assert_eq!(command_int.z, 10.0);
+
+        let request: CommandRequest =
+            serde_json::from_str(r#"{"command": 400, "params": [0, 0, 0, 0, 0, 0, 0, 0]}"#)
+                .unwrap();
+        assert!(request.message(1, 1, 0).is_err());
+    }
+}
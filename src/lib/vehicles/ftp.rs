@@ -0,0 +1,714 @@
+use std::{collections::HashMap, sync::Arc};
+
+use lazy_static::lazy_static;
+use mavlink::{
+    ardupilotmega::{MavMessage, FILE_TRANSFER_PROTOCOL_DATA},
+    MessageData,
+};
+use serde::Serialize;
+use tokio::{
+    sync::{broadcast, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::*;
+
+use crate::{
+    protocol::Protocol,
+    vehicles::{hub_sender, is_own_target, send, wait_message, Received, VehicleError},
+};
+
+const TIMEOUT: Duration = Duration::from_millis(1000);
+const RETRIES: u8 = 5;
+const PAYLOAD_SIZE: usize = 251;
+const HEADER_SIZE: usize = 12;
+/// File data carried by each message
+const MAX_DATA: usize = PAYLOAD_SIZE - HEADER_SIZE;
+/// Gaps larger than this are read with a new burst, the smaller ones with a read per chunk
+const BURST_GAP: u32 = 16 * MAX_DATA as u32;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+/// Files are announced by the vehicle, so larger ones are refused instead of trusted
+const MAX_FILE_SIZE: u32 = 16 * 1024 * 1024;
+
+lazy_static! {
+    /// FTP servers only handle a few sessions, so each component runs a single transfer at a time
+    static ref TRANSFERS: Mutex<HashMap<(u8, u8), Arc<Mutex<()>>>> = Mutex::new(HashMap::new());
+    static ref PROGRESS: broadcast::Sender<FtpProgress> = broadcast::channel(100).0;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+enum Opcode {
+    TerminateSession = 1,
+    ListDirectory = 3,
+    OpenFileRO = 4,
+    ReadFile = 5,
+    CreateFile = 6,
+    WriteFile = 7,
+    BurstReadFile = 15,
+    Ack = 128,
+    Nak = 129,
+}
+
+/// The `FILE_TRANSFER_PROTOCOL` payload
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Payload {
+    seq: u16,
+    session: u8,
+    opcode: u8,
+    /// Length of the data, or of the data to read
+    size: u8,
+    /// The opcode of the request, in answers
+    req_opcode: u8,
+    burst_complete: bool,
+    offset: u32,
+    data: Vec<u8>,
+}
+
+impl Payload {
+    fn new(opcode: Opcode, session: u8, offset: u32, data: &[u8]) -> Self {
+        Self {
+            session,
+            opcode: opcode as u8,
+            size: data.len() as u8,
+            offset,
+            data: data.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    fn read(opcode: Opcode, session: u8, offset: u32, size: usize) -> Self {
+        Self {
+            size: size.min(MAX_DATA) as u8,
+            ..Self::new(opcode, session, offset, &[])
+        }
+    }
+
+    fn encode(&self) -> [u8; PAYLOAD_SIZE] {
+        let mut payload = [0; PAYLOAD_SIZE];
+
+        payload[0..2].copy_from_slice(&self.seq.to_le_bytes());
+        payload[2] = self.session;
+        payload[3] = self.opcode;
+        payload[4] = self.size;
+        payload[5] = self.req_opcode;
+        payload[6] = self.burst_complete as u8;
+        payload[8..12].copy_from_slice(&self.offset.to_le_bytes());
+
+        let length = self.data.len().min(MAX_DATA);
+        payload[HEADER_SIZE..HEADER_SIZE + length].copy_from_slice(&self.data[..length]);
+
+        payload
+    }
+
+    fn decode(payload: &[u8]) -> Option<Self> {
+        if payload.len() < HEADER_SIZE {
+            return None;
+        }
+
+        // Trailing zeros are truncated by MAVLink 2, so the data can be shorter than its size
+        let size = payload[4];
+        let end = (HEADER_SIZE + size as usize).min(payload.len());
+
+        Some(Self {
+            seq: u16::from_le_bytes([payload[0], payload[1]]),
+            session: payload[2],
+            opcode: payload[3],
+            size,
+            req_opcode: payload[5],
+            burst_complete: payload[6] != 0,
+            offset: u32::from_le_bytes([payload[8], payload[9], payload[10], payload[11]]),
+            data: payload[HEADER_SIZE..end].to_vec(),
+        })
+    }
+}
+
+/// A negative acknowledgment, with its error code and the `errno` of the failures that have it
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Nak {
+    error: u8,
+    errno: u8,
+}
+
+impl Nak {
+    const FAIL_ERRNO: u8 = 2;
+    const EOF: u8 = 6;
+    const FILE_NOT_FOUND: u8 = 10;
+
+    fn is_eof(&self) -> bool {
+        self.error == Self::EOF
+    }
+
+    fn into_error(self, path: &str) -> VehicleError {
+        let reason = match self.error {
+            1 => "failed".to_string(),
+            Self::FAIL_ERRNO => format!("failed with errno {}", self.errno),
+            3 => "invalid data size".to_string(),
+            4 => "invalid session".to_string(),
+            5 => "no sessions available".to_string(),
+            Self::EOF => "end of file".to_string(),
+            7 => "unknown command".to_string(),
+            8 => "file exists".to_string(),
+            9 => "file protected".to_string(),
+            Self::FILE_NOT_FOUND => return VehicleError::NotFound(format!("File {path:?}")),
+            error => format!("error {error}"),
+        };
+
+        VehicleError::Rejected(format!("FTP on {path:?} {reason}"))
+    }
+}
+
+enum Reply {
+    Ack(Payload),
+    Nak(Nak),
+}
+
+impl Reply {
+    fn ack(self, path: &str) -> Result<Payload, VehicleError> {
+        match self {
+            Reply::Ack(payload) => Ok(payload),
+            Reply::Nak(nak) => Err(nak.into_error(path)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    File,
+    Directory,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FtpEntry {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: EntryKind,
+    /// In bytes, for files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u32>,
+}
+
+/// Parses the entries of a `ListDirectory` answer, returning how many the vehicle sent,
+/// which is the offset of the next request, and the files and directories among them
+fn parse_entries(data: &[u8]) -> (u32, Vec<FtpEntry>) {
+    let raw_entries = data
+        .split(|byte| *byte == 0)
+        .filter(|entry| !entry.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect::<Vec<_>>();
+
+    let entries = raw_entries
+        .iter()
+        .filter_map(|entry| {
+            let (kind, entry) = if let Some(entry) = entry.strip_prefix('F') {
+                (EntryKind::File, entry)
+            } else if let Some(entry) = entry.strip_prefix('D') {
+                (EntryKind::Directory, entry)
+            } else {
+                // Skipped entries
+                return None;
+            };
+            let (name, size) = match entry.split_once('\t') {
+                Some((name, size)) => (name, size.trim().parse().ok()),
+                None => (entry, None),
+            };
+
+            if name == "." || name == ".." {
+                return None;
+            }
+
+            Some(FtpEntry {
+                name: name.to_string(),
+                kind,
+                size,
+            })
+        })
+        .collect();
+
+    (raw_entries.len() as u32, entries)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FtpOperation {
+    Download,
+    Upload,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FtpProgress {
+    pub system_id: u8,
+    pub component_id: u8,
+    pub path: String,
+    pub operation: FtpOperation,
+    pub bytes: u32,
+    pub total: u32,
+    pub done: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The progress of every transfer
+pub fn progress() -> broadcast::Receiver<FtpProgress> {
+    PROGRESS.subscribe()
+}
+
+/// Sends the progress of a transfer, at most once per `PROGRESS_INTERVAL`
+struct ProgressReporter {
+    progress: FtpProgress,
+    last_sent: Option<Instant>,
+}
+
+impl ProgressReporter {
+    fn new(target: (u8, u8), path: &str, operation: FtpOperation, total: u32) -> Self {
+        Self {
+            progress: FtpProgress {
+                system_id: target.0,
+                component_id: target.1,
+                path: path.to_string(),
+                operation,
+                bytes: 0,
+                total,
+                done: false,
+                error: None,
+            },
+            last_sent: None,
+        }
+    }
+
+    fn update(&mut self, bytes: u32) {
+        self.progress.bytes = bytes;
+
+        if self
+            .last_sent
+            .is_some_and(|last_sent| last_sent.elapsed() < PROGRESS_INTERVAL)
+        {
+            return;
+        }
+
+        self.last_sent = Some(Instant::now());
+        let _ = PROGRESS.send(self.progress.clone());
+    }
+
+    fn finish<T>(mut self, result: &Result<T, VehicleError>) {
+        self.progress.done = true;
+        self.progress.error = result.as_ref().err().map(ToString::to_string);
+
+        let _ = PROGRESS.send(self.progress);
+    }
+}
+
+struct FtpClient {
+    target: (u8, u8),
+    hub_sender: broadcast::Sender<Arc<Protocol>>,
+    hub_receiver: broadcast::Receiver<Arc<Protocol>>,
+    seq: u16,
+}
+
+impl FtpClient {
+    async fn new(system_id: u8, component_id: u8) -> Result<Self, VehicleError> {
+        let hub_sender = hub_sender().await?;
+        let hub_receiver = hub_sender.subscribe();
+
+        Ok(Self {
+            target: (system_id, component_id),
+            hub_sender,
+            hub_receiver,
+            seq: 0,
+        })
+    }
+
+    fn send(&self, payload: &Payload) -> Result<(), VehicleError> {
+        let message = MavMessage::FILE_TRANSFER_PROTOCOL(FILE_TRANSFER_PROTOCOL_DATA {
+            target_network: 0,
+            target_system: self.target.0,
+            target_component: self.target.1,
+            payload: payload.encode(),
+        });
+
+        send(&self.hub_sender, &message)
+    }
+
+    async fn receive(
+        &mut self,
+        deadline: Instant,
+        mut filter: impl FnMut(&Payload) -> bool,
+    ) -> Option<Payload> {
+        wait_message(
+            &mut self.hub_receiver,
+            self.target,
+            &[FILE_TRANSFER_PROTOCOL_DATA::ID],
+            deadline,
+            |message| match message {
+                MavMessage::FILE_TRANSFER_PROTOCOL(ftp) if is_own_target(ftp.target_system) => {
+                    Payload::decode(&ftp.payload).filter(|payload| filter(payload))
+                }
+                _ => None,
+            },
+        )
+        .await
+    }
+
+    /// Sends a request until it is answered, retrying with the same sequence number,
+    /// so the vehicle can tell the retries apart and send its last answer again
+    async fn request(&mut self, mut payload: Payload) -> Result<Reply, VehicleError> {
+        self.seq = self.seq.wrapping_add(1);
+        payload.seq = self.seq;
+        let answer_seq = payload.seq.wrapping_add(1);
+
+        for _ in 0..=RETRIES {
+            self.send(&payload)?;
+
+            let Some(answer) = self
+                .receive(Instant::now() + TIMEOUT, |answer| {
+                    answer.seq == answer_seq && answer.req_opcode == payload.opcode
+                })
+                .await
+            else {
+                continue;
+            };
+
+            self.seq = answer.seq;
+            if answer.opcode == Opcode::Nak as u8 {
+                return Ok(Reply::Nak(Nak {
+                    error: answer.data.first().copied().unwrap_or_default(),
+                    errno: answer.data.get(1).copied().unwrap_or_default(),
+                }));
+            }
+            return Ok(Reply::Ack(answer));
+        }
+
+        Err(VehicleError::Timeout(format!(
+            "No FTP answer from {}:{}",
+            self.target.0, self.target.1
+        )))
+    }
+
+    async fn terminate(&mut self, session: u8) {
+        let payload = Payload::new(Opcode::TerminateSession, session, 0, &[]);
+        if let Err(error) = self.request(payload).await {
+            debug!("Failed terminating FTP session {session}: {error}");
+        }
+    }
+
+    /// Reads a whole file, with bursts, filling the gaps they leave with single reads
+    async fn read_file(
+        &mut self,
+        session: u8,
+        size: u32,
+        progress: &mut ProgressReporter,
+    ) -> Result<Vec<u8>, VehicleError> {
+        // Grows with the chunks, so a wrong size doesn't allocate more than what was sent
+        let mut data = Vec::new();
+        let mut received = Received::default();
+        let mut attempts = 0;
+
+        while let Some((start, end)) = received.first_gap(size) {
+            let received_before = received.bytes();
+
+            if end - start > BURST_GAP {
+                self.burst_read(session, start, size, &mut data, &mut received, progress)
+                    .await?;
+            } else {
+                let payload =
+                    Payload::read(Opcode::ReadFile, session, start, (end - start) as usize);
+                match self.request(payload).await? {
+                    Reply::Ack(answer) => {
+                        store(&mut data, size, &mut received, &answer);
+                        progress.update(received.bytes());
+                    }
+                    Reply::Nak(nak) => return Err(nak.into_error(&progress.progress.path)),
+                }
+            }
+
+            if received.bytes() > received_before {
+                attempts = 0;
+            } else if attempts < RETRIES {
+                attempts += 1;
+            } else {
+                return Err(VehicleError::Timeout(format!(
+                    "Stalled reading {:?} at {start} of {size} bytes",
+                    progress.progress.path
+                )));
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Reads from the offset until the vehicle completes the burst or goes quiet
+    async fn burst_read(
+        &mut self,
+        session: u8,
+        offset: u32,
+        size: u32,
+        data: &mut Vec<u8>,
+        received: &mut Received,
+        progress: &mut ProgressReporter,
+    ) -> Result<(), VehicleError> {
+        self.seq = self.seq.wrapping_add(1);
+        let payload = Payload {
+            seq: self.seq,
+            ..Payload::read(Opcode::BurstReadFile, session, offset, MAX_DATA)
+        };
+        self.send(&payload)?;
+
+        while let Some(answer) = self
+            .receive(Instant::now() + TIMEOUT, |answer| {
+                answer.session == session && answer.req_opcode == Opcode::BurstReadFile as u8
+            })
+            .await
+        {
+            self.seq = answer.seq;
+
+            // Usually the end of the file, the other failures show up when reading the gaps
+            if answer.opcode == Opcode::Nak as u8 {
+                break;
+            }
+
+            store(data, size, received, &answer);
+            progress.update(received.bytes());
+
+            if answer.burst_complete {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Copies the data of an answer, ignoring what goes past the file size
+fn store(data: &mut Vec<u8>, size: u32, received: &mut Received, answer: &Payload) {
+    if answer.opcode != Opcode::Ack as u8 {
+        return;
+    }
+
+    let start = answer.offset.min(size) as usize;
+    let end = (start + answer.data.len()).min(size as usize);
+    if start >= end {
+        return;
+    }
+    if data.len() < end {
+        data.resize(end, 0);
+    }
+    data[start..end].copy_from_slice(&answer.data[..end - start]);
+    received.insert(start as u32, end as u32);
+}
+
+async fn transfer_lock(target: (u8, u8)) -> Arc<Mutex<()>> {
+    TRANSFERS.lock().await.entry(target).or_default().clone()
+}
+
+fn path_data(path: &str) -> Result<&[u8], VehicleError> {
+    if path.is_empty() || path.len() > MAX_DATA {
+        return Err(VehicleError::Invalid(format!("Invalid FTP path {path:?}")));
+    }
+
+    Ok(path.as_bytes())
+}
+
+/// The files and directories in a directory
+#[instrument(level = "debug")]
+pub async fn list(
+    system_id: u8,
+    component_id: u8,
+    path: &str,
+) -> Result<Vec<FtpEntry>, VehicleError> {
+    let path_data = path_data(path)?;
+    let lock = transfer_lock((system_id, component_id)).await;
+    let _transfer = lock.lock().await;
+
+    let mut client = FtpClient::new(system_id, component_id).await?;
+    let mut entries = vec![];
+    let mut offset = 0;
+
+    loop {
+        let payload = Payload::new(Opcode::ListDirectory, 0, offset, path_data);
+        match client.request(payload).await? {
+            Reply::Ack(answer) => {
+                let (count, listed) = parse_entries(&answer.data);
+                if count == 0 {
+                    break;
+                }
+                offset += count;
+                entries.extend(listed);
+            }
+            Reply::Nak(nak) if nak.is_eof() => break,
+            Reply::Nak(nak) => return Err(nak.into_error(path)),
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Downloads a file, reporting the progress through [`progress`]
+#[instrument(level = "debug")]
+pub async fn download(
+    system_id: u8,
+    component_id: u8,
+    path: &str,
+) -> Result<Vec<u8>, VehicleError> {
+    let path_data = path_data(path)?;
+    let target = (system_id, component_id);
+    let lock = transfer_lock(target).await;
+    let _transfer = lock.lock().await;
+
+    let mut client = FtpClient::new(system_id, component_id).await?;
+    let answer = client
+        .request(Payload::new(Opcode::OpenFileRO, 0, 0, path_data))
+        .await?
+        .ack(path)?;
+    let session = answer.session;
+
+    let mut progress = ProgressReporter::new(target, path, FtpOperation::Download, 0);
+    let result = match answer.data.get(..4) {
+        Some(size) => {
+            let size = u32::from_le_bytes([size[0], size[1], size[2], size[3]]);
+            progress.progress.total = size;
+            if size > MAX_FILE_SIZE {
+                Err(VehicleError::Invalid(format!(
+                    "{path:?} has {size} bytes, more than the {MAX_FILE_SIZE} bytes allowed"
+                )))
+            } else {
+                client.read_file(session, size, &mut progress).await
+            }
+        }
+        None => Err(VehicleError::Rejected(format!(
+            "No size when opening {path:?}"
+        ))),
+    };
+
+    client.terminate(session).await;
+    progress.finish(&result);
+
+    if let Ok(data) = &result {
+        debug!("Downloaded {} bytes from {path:?}", data.len());
+    }
+
+    result
+}
+
+/// Creates or replaces a file, reporting the progress through [`progress`]
+#[instrument(level = "debug", skip(data))]
+pub async fn upload(
+    system_id: u8,
+    component_id: u8,
+    path: &str,
+    data: &[u8],
+) -> Result<(), VehicleError> {
+    let path_data = path_data(path)?;
+    let size = u32::try_from(data.len())
+        .map_err(|_| VehicleError::Invalid(format!("File too large: {} bytes", data.len())))?;
+    let target = (system_id, component_id);
+    let lock = transfer_lock(target).await;
+    let _transfer = lock.lock().await;
+
+    let mut client = FtpClient::new(system_id, component_id).await?;
+    let session = client
+        .request(Payload::new(Opcode::CreateFile, 0, 0, path_data))
+        .await?
+        .ack(path)?
+        .session;
+
+    let mut progress = ProgressReporter::new(target, path, FtpOperation::Upload, size);
+    let mut result = Ok(());
+    for (index, chunk) in data.chunks(MAX_DATA).enumerate() {
+        let offset = (index * MAX_DATA) as u32;
+        let payload = Payload::new(Opcode::WriteFile, session, offset, chunk);
+
+        if let Err(error) = client
+            .request(payload)
+            .await
+            .and_then(|reply| reply.ack(path))
+        {
+            result = Err(error);
+            break;
+        }
+        progress.update(offset + chunk.len() as u32);
+    }
+
+    client.terminate(session).await;
+    progress.finish(&result);
+
+    if result.is_ok() {
+        debug!("Uploaded {size} bytes to {path:?}");
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload() {
+        let payload = Payload {
+            seq: 513,
+            burst_complete: true,
+            ..Payload::new(Opcode::WriteFile, 2, 70000, b"hello")
+        };
+
+        let encoded = payload.encode();
+        assert_eq!(&encoded[..12], &[1, 2, 2, 7, 5, 0, 1, 0, 112, 17, 1, 0]);
+        assert_eq!(Payload::decode(&encoded).unwrap(), payload);
+
+        // Truncated by MAVLink 2
+        let answer = Payload::decode(&encoded[..14]).unwrap();
+        assert_eq!(answer.size, 5);
+        assert_eq!(answer.data, b"he");
+
+        assert!(Payload::decode(&encoded[..8]).is_none());
+    }
+
+    #[test]
+    fn test_store() {
+        let answer = |offset, data: &[u8]| Payload::new(Opcode::Ack, 1, offset, data);
+        let mut data = Vec::new();
+        let mut received = Received::default();
+
+        // Out of order, with the data past the size ignored
+        store(&mut data, 5, &mut received, &answer(3, b"lo!"));
+        assert_eq!(data, b"\0\0\0lo");
+        store(&mut data, 5, &mut received, &answer(0, b"hel"));
+        assert_eq!(data, b"hello");
+        assert_eq!(received.first_gap(5), None);
+
+        store(&mut data, 5, &mut received, &answer(9, b"far"));
+        store(
+            &mut data,
+            5,
+            &mut received,
+            &Payload::new(Opcode::Nak, 1, 0, b"xx"),
+        );
+        assert_eq!(data, b"hello");
+    }
+
+    #[test]
+    fn test_parse_entries() {
+        let (count, entries) =
+            parse_entries(b"D.\0D..\0FLASTLOG.TXT\t3\0DLOGS\0S\0F00000001.BIN\t123456\0");
+        assert_eq!(count, 6);
+        assert_eq!(
+            entries,
+            vec![
+                FtpEntry {
+                    name: "LASTLOG.TXT".to_string(),
+                    kind: EntryKind::File,
+                    size: Some(3),
+                },
+                FtpEntry {
+                    name: "LOGS".to_string(),
+                    kind: EntryKind::Directory,
+                    size: None,
+                },
+                FtpEntry {
+                    name: "00000001.BIN".to_string(),
+                    kind: EntryKind::File,
+                    size: Some(123456),
+                },
+            ]
+        );
+    }
+}
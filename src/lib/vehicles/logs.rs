@@ -0,0 +1,402 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use lazy_static::lazy_static;
+use mavlink::{
+    ardupilotmega::{
+        MavMessage, LOG_DATA_DATA, LOG_ENTRY_DATA, LOG_REQUEST_DATA_DATA, LOG_REQUEST_END_DATA,
+        LOG_REQUEST_LIST_DATA,
+    },
+    MessageData,
+};
+use serde::Serialize;
+use tokio::{
+    io::{AsyncSeekExt, AsyncWriteExt},
+    sync::{Mutex, RwLock},
+    time::{Duration, Instant},
+};
+use tracing::*;
+
+use crate::{
+    cli,
+    vehicles::{hub_sender, send, wait_message, Received, VehicleError},
+};
+
+const TIMEOUT: Duration = Duration::from_millis(1500);
+const RETRIES: u8 = 5;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+/// Inside the log path of the server
+const DIRECTORY: &str = "vehicles";
+/// Autopilots close the log of a flight a moment after they disarm
+const DISARM_DELAY: Duration = Duration::from_secs(3);
+
+lazy_static! {
+    /// The log protocol streams a single log at a time from each vehicle
+    static ref TRANSFERS: Mutex<HashMap<u8, Arc<Mutex<()>>>> = Mutex::new(HashMap::new());
+    /// The last list of each vehicle, so downloads can start while another one is running
+    static ref ENTRIES: RwLock<HashMap<u8, Vec<LogEntry>>> = RwLock::new(HashMap::new());
+    static ref DOWNLOADS: RwLock<HashMap<(u8, u16), LogDownload>> = RwLock::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LogEntry {
+    pub id: u16,
+    /// Seconds since the UNIX epoch, zero when unknown
+    pub time_utc: u32,
+    /// In bytes
+    pub size: u32,
+}
+
+impl From<&LOG_ENTRY_DATA> for LogEntry {
+    fn from(entry: &LOG_ENTRY_DATA) -> Self {
+        Self {
+            id: entry.id,
+            time_utc: entry.time_utc,
+            size: entry.size,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DownloadState {
+    /// Waiting for another download from the same vehicle
+    Queued,
+    Downloading,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogDownload {
+    pub system_id: u8,
+    pub component_id: u8,
+    pub id: u16,
+    pub size: u32,
+    pub bytes: u32,
+    pub state: DownloadState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Where the log is saved
+    pub path: PathBuf,
+}
+
+/// The file name of a log, with its date when the vehicle knows it
+fn file_name(entry: &LogEntry) -> String {
+    match chrono::DateTime::from_timestamp(entry.time_utc as i64, 0) {
+        Some(time) if entry.time_utc > 0 => {
+            format!("log_{}_{}.bin", entry.id, time.format("%Y-%m-%d_%H-%M-%S"))
+        }
+        _ => format!("log_{}.bin", entry.id),
+    }
+}
+
+fn log_directory(system_id: u8) -> PathBuf {
+    PathBuf::from(cli::log_path())
+        .join(DIRECTORY)
+        .join(system_id.to_string())
+}
+
+async fn transfer_lock(system_id: u8) -> Arc<Mutex<()>> {
+    TRANSFERS.lock().await.entry(system_id).or_default().clone()
+}
+
+/// The logs stored by a vehicle
+#[instrument(level = "debug")]
+pub async fn list(system_id: u8, component_id: u8) -> Result<Vec<LogEntry>, VehicleError> {
+    let lock = transfer_lock(system_id).await;
+    let _transfer = lock.lock().await;
+
+    let hub_sender = hub_sender().await?;
+    let mut hub_receiver = hub_sender.subscribe();
+
+    let mut entries = HashMap::new();
+    let mut count = None;
+    for _ in 0..=RETRIES {
+        let request = MavMessage::LOG_REQUEST_LIST(LOG_REQUEST_LIST_DATA {
+            start: 0,
+            end: u16::MAX,
+            target_system: system_id,
+            target_component: component_id,
+        });
+        send(&hub_sender, &request)?;
+
+        while let Some(entry) = wait_message(
+            &mut hub_receiver,
+            (system_id, component_id),
+            &[LOG_ENTRY_DATA::ID],
+            Instant::now() + TIMEOUT,
+            |message| match message {
+                MavMessage::LOG_ENTRY(entry) => Some(entry),
+                _ => None,
+            },
+        )
+        .await
+        {
+            count = Some(entry.num_logs as usize);
+            // Vehicles without logs send a single entry, with no logs
+            if entry.num_logs > 0 {
+                entries.insert(entry.id, LogEntry::from(&entry));
+            }
+
+            if entries.len() >= entry.num_logs as usize {
+                break;
+            }
+        }
+
+        if count.is_some_and(|count| entries.len() >= count) {
+            break;
+        }
+    }
+
+    let Some(count) = count else {
+        return Err(VehicleError::Timeout(format!(
+            "No LOG_ENTRY from {system_id}:{component_id}"
+        )));
+    };
+    if entries.len() < count {
+        warn!(
+            "Only {} of {count} logs listed by {system_id}:{component_id}",
+            entries.len()
+        );
+    }
+
+    let mut entries = entries.into_values().collect::<Vec<_>>();
+    entries.sort_by_key(|entry| entry.id);
+    ENTRIES.write().await.insert(system_id, entries.clone());
+
+    Ok(entries)
+}
+
+/// Starts downloading a log in the background, or returns the download already running
+#[instrument(level = "debug")]
+pub async fn start(system_id: u8, component_id: u8, id: u16) -> Result<LogDownload, VehicleError> {
+    let cached = ENTRIES
+        .read()
+        .await
+        .get(&system_id)
+        .and_then(|entries| entries.iter().find(|entry| entry.id == id).cloned());
+    let entry = match cached {
+        Some(entry) => entry,
+        None => list(system_id, component_id)
+            .await?
+            .into_iter()
+            .find(|entry| entry.id == id)
+            .ok_or_else(|| {
+                VehicleError::NotFound(format!("Log {id} on {system_id}:{component_id}"))
+            })?,
+    };
+
+    let download = LogDownload {
+        system_id,
+        component_id,
+        id,
+        size: entry.size,
+        bytes: 0,
+        state: DownloadState::Queued,
+        error: None,
+        path: log_directory(system_id).join(file_name(&entry)),
+    };
+    let key = (system_id, id);
+    {
+        let mut downloads = DOWNLOADS.write().await;
+        if let Some(running) = downloads.get(&key).filter(|running| {
+            matches!(
+                running.state,
+                DownloadState::Queued | DownloadState::Downloading
+            )
+        }) {
+            return Ok(running.clone());
+        }
+        downloads.insert(key, download.clone());
+    }
+
+    let path = download.path.clone();
+    tokio::spawn(async move {
+        let result = download_log(system_id, component_id, &entry, &path).await;
+
+        if let Some(download) = DOWNLOADS.write().await.get_mut(&key) {
+            match result {
+                Ok(()) => {
+                    info!("Log {id} of {system_id} saved to {path:?}");
+                    download.state = DownloadState::Done;
+                }
+                Err(error) => {
+                    warn!("Failed downloading log {id} of {system_id}: {error}");
+                    download.state = DownloadState::Failed;
+                    download.error = Some(error.to_string());
+                }
+            }
+        }
+    });
+
+    Ok(download)
+}
+
+/// Starts downloading the newest log of a vehicle that just disarmed
+#[instrument(level = "debug")]
+pub(crate) async fn start_latest(
+    system_id: u8,
+    component_id: u8,
+) -> Result<LogDownload, VehicleError> {
+    tokio::time::sleep(DISARM_DELAY).await;
+
+    let Some(entry) = list(system_id, component_id).await?.pop() else {
+        return Err(VehicleError::NotFound(format!(
+            "Logs on {system_id}:{component_id}"
+        )));
+    };
+
+    start(system_id, component_id, entry.id).await
+}
+
+/// The progress of a download, which is kept after it finishes
+pub async fn download(system_id: u8, id: u16) -> Option<LogDownload> {
+    DOWNLOADS.read().await.get(&(system_id, id)).cloned()
+}
+
+async fn update_download(key: (u8, u16), state: DownloadState, bytes: u32) {
+    if let Some(download) = DOWNLOADS.write().await.get_mut(&key) {
+        download.state = state;
+        download.bytes = bytes;
+    }
+}
+
+async fn download_log(
+    system_id: u8,
+    component_id: u8,
+    entry: &LogEntry,
+    path: &std::path::Path,
+) -> Result<(), VehicleError> {
+    let lock = transfer_lock(system_id).await;
+    let _transfer = lock.lock().await;
+
+    let key = (system_id, entry.id);
+    update_download(key, DownloadState::Downloading, 0).await;
+
+    let file_error =
+        |error: std::io::Error| VehicleError::Unreachable(format!("{path:?}: {error}"));
+    if let Some(directory) = path.parent() {
+        tokio::fs::create_dir_all(directory)
+            .await
+            .map_err(file_error)?;
+    }
+    let hub_sender = hub_sender().await?;
+    let mut hub_receiver = hub_sender.subscribe();
+
+    let mut file = tokio::fs::File::create(path).await.map_err(file_error)?;
+
+    let result = async {
+        file.set_len(entry.size as u64).await.map_err(file_error)?;
+
+        let mut received = Received::default();
+        let mut attempts = 0;
+        let mut last_update = Instant::now();
+
+        // The vehicle streams the requested range, and the gaps it leaves are requested again
+        while let Some((start, end)) = received.first_gap(entry.size) {
+            let received_before = received.bytes();
+
+            let request = MavMessage::LOG_REQUEST_DATA(LOG_REQUEST_DATA_DATA {
+                ofs: start,
+                count: end - start,
+                id: entry.id,
+                target_system: system_id,
+                target_component: component_id,
+            });
+            send(&hub_sender, &request)?;
+
+            while let Some(data) = wait_message(
+                &mut hub_receiver,
+                (system_id, component_id),
+                &[LOG_DATA_DATA::ID],
+                Instant::now() + TIMEOUT,
+                |message| match message {
+                    MavMessage::LOG_DATA(data) if data.id == entry.id => Some(data),
+                    _ => None,
+                },
+            )
+            .await
+            {
+                // The count is not trusted beyond the data the message can carry
+                let count = (data.count as usize).min(data.data.len()) as u32;
+                let data_start = data.ofs.min(entry.size);
+                let data_end = data_start.saturating_add(count).min(entry.size);
+
+                if data_end > data_start {
+                    file.seek(std::io::SeekFrom::Start(data_start as u64))
+                        .await
+                        .map_err(file_error)?;
+                    file.write_all(&data.data[..(data_end - data_start) as usize])
+                        .await
+                        .map_err(file_error)?;
+                    received.insert(data_start, data_end);
+                }
+
+                if last_update.elapsed() > PROGRESS_INTERVAL {
+                    last_update = Instant::now();
+                    update_download(key, DownloadState::Downloading, received.bytes()).await;
+                }
+
+                // Empty at the end of the log
+                if data.count == 0 || data_end >= end {
+                    break;
+                }
+            }
+
+            if received.bytes() > received_before {
+                attempts = 0;
+            } else if attempts < RETRIES {
+                attempts += 1;
+            } else {
+                return Err(VehicleError::Timeout(format!(
+                    "Stalled downloading log {} at {start} of {} bytes",
+                    entry.id, entry.size
+                )));
+            }
+        }
+
+        update_download(key, DownloadState::Downloading, received.bytes()).await;
+        file.flush().await.map_err(file_error)
+    }
+    .await;
+
+    // Stops the stream, also when the download failed
+    let end = MavMessage::LOG_REQUEST_END(LOG_REQUEST_END_DATA {
+        target_system: system_id,
+        target_component: component_id,
+    });
+    if let Err(error) = send(&hub_sender, &end) {
+        debug!("Failed sending LOG_REQUEST_END: {error}");
+    }
+
+    // A partial log would look like a complete one, as the file is created with its final size
+    if result.is_err() {
+        drop(file);
+        if let Err(error) = tokio::fs::remove_file(path).await {
+            warn!("Failed removing partial log {path:?}: {error}");
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_name() {
+        let entry = LogEntry {
+            id: 12,
+            time_utc: 1700000000,
+            size: 1000,
+        };
+        assert_eq!(file_name(&entry), "log_12_2023-11-14_22-13-20.bin");
+
+        let entry = LogEntry {
+            time_utc: 0,
+            ..entry
+        };
+        assert_eq!(file_name(&entry), "log_12.bin");
+    }
+}
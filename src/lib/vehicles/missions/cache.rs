@@ -0,0 +1,697 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use lazy_static::lazy_static;
+use mavlink::{
+    ardupilotmega::{
+        MavMessage, MavMissionResult, MavMissionType, MISSION_ACK_DATA, MISSION_CLEAR_ALL_DATA,
+        MISSION_COUNT_DATA, MISSION_CURRENT_DATA, MISSION_ITEM_DATA, MISSION_ITEM_INT_DATA,
+        MISSION_REQUEST_DATA, MISSION_REQUEST_INT_DATA, MISSION_REQUEST_LIST_DATA,
+    },
+    MavHeader, MessageData,
+};
+use num_traits::ToPrimitive;
+use tokio::{
+    sync::{broadcast, RwLock},
+    time::{Duration, Instant},
+};
+use tracing::*;
+
+use crate::{
+    protocol::Protocol,
+    vehicles::{self, decode, hub_sender, missions::MissionItem, send_to},
+};
+
+/// Transfers and downloads from the cache are forgotten after being quiet for this long
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Requests from the clients that can be answered from the cache
+const REQUEST_IDS: [u32; 4] = [
+    MISSION_REQUEST_LIST_DATA::ID,
+    MISSION_REQUEST_INT_DATA::ID,
+    MISSION_REQUEST_DATA::ID,
+    MISSION_ACK_DATA::ID,
+];
+
+lazy_static! {
+    static ref MISSIONS: MissionCache = MissionCache::new();
+}
+
+/// System and component IDs
+type Component = (u8, u8);
+
+struct MissionCache {
+    state: Arc<RwLock<CacheState>>,
+    _task: Arc<Mutex<tokio::task::JoinHandle<()>>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct CachedMission {
+    items: Vec<MissionItem>,
+    /// Identifies the mission on the vehicles that support it, zero otherwise
+    opaque_id: u32,
+}
+
+/// A transfer between two other systems, followed to cache its result
+#[derive(Debug)]
+struct Transfer {
+    opaque_id: u32,
+    items: Vec<Option<MissionItem>>,
+    /// Whether the receiver requested the list, so the items come from the vehicle
+    download: bool,
+    last_message: Instant,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    /// By vehicle component and mission type
+    missions: HashMap<(Component, u32), CachedMission>,
+    /// By sender, receiver and mission type
+    transfers: HashMap<(Component, Component, u32), Transfer>,
+    /// By requester, target system and mission type, as the mission protocol is the same both ways
+    list_requests: HashMap<(Component, u8, u32), Instant>,
+    /// Clients downloading from the cache, by client, vehicle component and mission type
+    sessions: HashMap<(Component, Component, u32), Instant>,
+}
+
+/// A mission request that can be answered from the cache
+#[derive(Debug, PartialEq)]
+enum CachedRequest {
+    /// Starts a download from the cache
+    List(Component, MavMissionType),
+    Item {
+        vehicle: Component,
+        mission_type: MavMissionType,
+        seq: u16,
+        /// Whether the client asked for a `MISSION_ITEM_INT` or the deprecated `MISSION_ITEM`
+        int: bool,
+    },
+    /// Ends a download from the cache, nothing is sent back
+    Ack(Component, MavMissionType),
+}
+
+fn type_key(mission_type: MavMissionType) -> u32 {
+    mission_type.to_u32().unwrap_or_default()
+}
+
+fn is_target(component: Component, target_system: u8, target_component: u8) -> bool {
+    component.0 == target_system && (target_component == 0 || component.1 == target_component)
+}
+
+impl CacheState {
+    fn cached_vehicle(
+        &self,
+        target_system: u8,
+        target_component: u8,
+        mission_type: MavMissionType,
+    ) -> Option<Component> {
+        self.missions
+            .keys()
+            .filter(|(vehicle, key)| {
+                *key == type_key(mission_type)
+                    && is_target(*vehicle, target_system, target_component)
+            })
+            .map(|(vehicle, _)| *vehicle)
+            .min()
+    }
+
+    fn session_vehicle(
+        &self,
+        client: Component,
+        target_system: u8,
+        target_component: u8,
+        mission_type: MavMissionType,
+    ) -> Option<Component> {
+        self.sessions
+            .keys()
+            .filter(|(session_client, vehicle, key)| {
+                *session_client == client
+                    && *key == type_key(mission_type)
+                    && is_target(*vehicle, target_system, target_component)
+            })
+            .map(|(_, vehicle, _)| *vehicle)
+            .min()
+    }
+
+    /// Lists are answered from the cache, and so is the rest of the download of the clients that got them
+    fn cached_request(&self, client: Component, request: &MavMessage) -> Option<CachedRequest> {
+        match request {
+            MavMessage::MISSION_REQUEST_LIST(request) => {
+                let vehicle = self.cached_vehicle(
+                    request.target_system,
+                    request.target_component,
+                    request.mission_type,
+                )?;
+                Some(CachedRequest::List(vehicle, request.mission_type))
+            }
+            MavMessage::MISSION_REQUEST_INT(request) => Some(CachedRequest::Item {
+                vehicle: self.session_vehicle(
+                    client,
+                    request.target_system,
+                    request.target_component,
+                    request.mission_type,
+                )?,
+                mission_type: request.mission_type,
+                seq: request.seq,
+                int: true,
+            }),
+            MavMessage::MISSION_REQUEST(request) => Some(CachedRequest::Item {
+                vehicle: self.session_vehicle(
+                    client,
+                    request.target_system,
+                    request.target_component,
+                    request.mission_type,
+                )?,
+                mission_type: request.mission_type,
+                seq: request.seq,
+                int: false,
+            }),
+            MavMessage::MISSION_ACK(ack) => {
+                let vehicle = self.session_vehicle(
+                    client,
+                    ack.target_system,
+                    ack.target_component,
+                    ack.mission_type,
+                )?;
+                Some(CachedRequest::Ack(vehicle, ack.mission_type))
+            }
+            _ => None,
+        }
+    }
+
+    /// Keeps track of the client download and builds the answer, as sent by the vehicle
+    fn answer(&mut self, client: Component, request: &CachedRequest) -> Option<MavMessage> {
+        let (vehicle, mission_type) = match request {
+            CachedRequest::List(vehicle, mission_type) => (*vehicle, *mission_type),
+            CachedRequest::Item {
+                vehicle,
+                mission_type,
+                ..
+            } => (*vehicle, *mission_type),
+            CachedRequest::Ack(vehicle, mission_type) => {
+                self.sessions
+                    .remove(&(client, *vehicle, type_key(*mission_type)));
+                return None;
+            }
+        };
+
+        let key = (client, vehicle, type_key(mission_type));
+        let Some(mission) = self.missions.get(&(vehicle, type_key(mission_type))) else {
+            self.sessions.remove(&key);
+            return None;
+        };
+        self.sessions.insert(key, Instant::now());
+
+        let answer = match request {
+            CachedRequest::List(..) => MavMessage::MISSION_COUNT(MISSION_COUNT_DATA {
+                count: mission.items.len() as u16,
+                target_system: client.0,
+                target_component: client.1,
+                mission_type,
+                opaque_id: mission.opaque_id,
+            }),
+            CachedRequest::Item { seq, int, .. } => match mission.items.get(*seq as usize) {
+                Some(item) if *int => MavMessage::MISSION_ITEM_INT(item.to_mavlink(
+                    client.0,
+                    client.1,
+                    *seq,
+                    mission_type,
+                )),
+                Some(item) => {
+                    MavMessage::MISSION_ITEM(legacy_item(item, client, *seq, mission_type))
+                }
+                None => {
+                    self.sessions.remove(&key);
+                    MavMessage::MISSION_ACK(MISSION_ACK_DATA {
+                        target_system: client.0,
+                        target_component: client.1,
+                        mavtype: MavMissionResult::MAV_MISSION_INVALID_SEQUENCE,
+                        mission_type,
+                        ..Default::default()
+                    })
+                }
+            },
+            CachedRequest::Ack(..) => return None,
+        };
+
+        Some(answer)
+    }
+
+    /// Follows the transfers between other systems, caching their result and forgetting the missions that
+    /// may have changed
+    fn observe(&mut self, sender: Component, message: &MavMessage) {
+        match message {
+            MavMessage::MISSION_REQUEST_LIST(request) => {
+                self.list_requests.insert(
+                    (
+                        sender,
+                        request.target_system,
+                        type_key(request.mission_type),
+                    ),
+                    Instant::now(),
+                );
+            }
+            MavMessage::MISSION_COUNT(count) => {
+                let receiver = (count.target_system, count.target_component);
+                let key = type_key(count.mission_type);
+                let download = self
+                    .list_requests
+                    .remove(&(receiver, sender.0, key))
+                    .is_some_and(|requested| requested.elapsed() < TRANSFER_TIMEOUT);
+
+                self.transfers.insert(
+                    (sender, receiver, key),
+                    Transfer {
+                        opaque_id: count.opaque_id,
+                        items: vec![None; count.count as usize],
+                        download,
+                        last_message: Instant::now(),
+                    },
+                );
+            }
+            MavMessage::MISSION_ITEM_INT(item) => {
+                let Some(transfer) = self.transfers.iter_mut().find_map(|(key, transfer)| {
+                    (key.0 == sender
+                        && is_target(key.1, item.target_system, item.target_component)
+                        && key.2 == type_key(item.mission_type))
+                    .then_some(transfer)
+                }) else {
+                    return;
+                };
+
+                if let Some(slot) = transfer.items.get_mut(item.seq as usize) {
+                    *slot = Some(MissionItem::from_mavlink(item));
+                }
+                transfer.last_message = Instant::now();
+            }
+            MavMessage::MISSION_ACK(ack) => {
+                let key = type_key(ack.mission_type);
+                let transfer_key = self.transfers.keys().copied().find(|transfer_key| {
+                    is_target(transfer_key.0, ack.target_system, ack.target_component)
+                        && transfer_key.1 == sender
+                        && transfer_key.2 == key
+                });
+                let transfer = transfer_key.and_then(|transfer_key| {
+                    Some((transfer_key.0, self.transfers.remove(&transfer_key)?))
+                });
+
+                match transfer {
+                    Some((transfer_sender, transfer))
+                        if ack.mavtype == MavMissionResult::MAV_MISSION_ACCEPTED
+                            && transfer.items.iter().all(Option::is_some) =>
+                    {
+                        let (vehicle, opaque_id) = if transfer.download {
+                            (transfer_sender, transfer.opaque_id)
+                        } else {
+                            (sender, ack.opaque_id)
+                        };
+                        let items = transfer.items.into_iter().flatten().collect::<Vec<_>>();
+
+                        debug!("Cached {} mission items of {vehicle:?}", items.len());
+                        self.missions
+                            .insert((vehicle, key), CachedMission { items, opaque_id });
+                    }
+                    _ => {
+                        // Anything else acknowledged by a vehicle, like a partial write, may change its mission
+                        if self.missions.remove(&(sender, key)).is_some() {
+                            debug!("Mission of {sender:?} invalidated by {:?}", ack.mavtype);
+                        }
+                    }
+                }
+            }
+            MavMessage::MISSION_CLEAR_ALL(clear) => {
+                // Forgotten right away, the next download caches the result
+                let key = type_key(clear.mission_type);
+                let all = clear.mission_type == MavMissionType::MAV_MISSION_TYPE_ALL;
+                self.missions.retain(|(vehicle, mission_type), _| {
+                    !(is_target(*vehicle, clear.target_system, clear.target_component)
+                        && (all || *mission_type == key))
+                });
+            }
+            MavMessage::MISSION_CURRENT(current) => self.check_current(sender, current),
+            _ => {}
+        }
+    }
+
+    /// Forgets the missions whose `MISSION_CURRENT` total or opaque IDs don't match the cached ones
+    fn check_current(&mut self, vehicle: Component, current: &MISSION_CURRENT_DATA) {
+        let opaque_ids = [
+            (MavMissionType::MAV_MISSION_TYPE_MISSION, current.mission_id),
+            (MavMissionType::MAV_MISSION_TYPE_FENCE, current.fence_id),
+            (
+                MavMissionType::MAV_MISSION_TYPE_RALLY,
+                current.rally_points_id,
+            ),
+        ];
+
+        for (mission_type, opaque_id) in opaque_ids {
+            let key = (vehicle, type_key(mission_type));
+            let changed = self.missions.get(&key).is_some_and(|mission| {
+                // Zero when not supported
+                let total_changed = mission_type == MavMissionType::MAV_MISSION_TYPE_MISSION
+                    && match current.total {
+                        0 => false,
+                        // No mission, apart from the home position some autopilots keep as the first item
+                        u16::MAX => mission.items.len() > 1,
+                        // Without that home position
+                        total => {
+                            ![total as usize, total as usize + 1].contains(&mission.items.len())
+                        }
+                    };
+                let id_changed =
+                    opaque_id != 0 && mission.opaque_id != 0 && opaque_id != mission.opaque_id;
+
+                total_changed || id_changed
+            });
+
+            if changed {
+                debug!("{mission_type:?} of {vehicle:?} invalidated by MISSION_CURRENT");
+                self.missions.remove(&key);
+            }
+        }
+    }
+
+    fn forget_stale(&mut self) {
+        self.transfers
+            .retain(|_, transfer| transfer.last_message.elapsed() < TRANSFER_TIMEOUT);
+        self.list_requests
+            .retain(|_, requested| requested.elapsed() < TRANSFER_TIMEOUT);
+        self.sessions
+            .retain(|_, last_request| last_request.elapsed() < TRANSFER_TIMEOUT);
+    }
+}
+
+fn legacy_item(
+    item: &MissionItem,
+    (system_id, component_id): Component,
+    seq: u16,
+    mission_type: MavMissionType,
+) -> MISSION_ITEM_DATA {
+    let scale = item.position_scale();
+
+    MISSION_ITEM_DATA {
+        param1: item.params[0],
+        param2: item.params[1],
+        param3: item.params[2],
+        param4: item.params[3],
+        x: (item.x as f64 / scale) as f32,
+        y: (item.y as f64 / scale) as f32,
+        z: item.z,
+        seq,
+        command: item.command,
+        target_system: system_id,
+        target_component: component_id,
+        frame: item.frame,
+        current: item.current as u8,
+        autocontinue: item.autocontinue as u8,
+        mission_type,
+    }
+}
+
+impl MissionCache {
+    fn new() -> Self {
+        let state = Arc::new(RwLock::new(CacheState::default()));
+
+        let _task = Arc::new(Mutex::new(tokio::spawn(Self::observe_task(state.clone()))));
+
+        Self { state, _task }
+    }
+
+    /// Caches the missions transferred through the hub, and answers the clients asking for them
+    async fn observe_task(state: Arc<RwLock<CacheState>>) {
+        let hub_sender = match hub_sender().await {
+            Ok(hub_sender) => hub_sender,
+            Err(error) => {
+                error!("Missions won't be cached: {error}");
+                return;
+            }
+        };
+        let mut hub_receiver = hub_sender.subscribe();
+        let mut interval = tokio::time::interval(TRANSFER_TIMEOUT);
+
+        loop {
+            tokio::select! {
+                result = hub_receiver.recv() => match result {
+                    Ok(message) => Self::handle_message(&hub_sender, &state, &message).await,
+                    Err(broadcast::error::RecvError::Lagged(samples)) => {
+                        warn!("Channel lagged by {samples} messages.");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = interval.tick() => state.write().await.forget_stale(),
+            }
+        }
+
+        debug!("Missions observer task stopped!");
+    }
+
+    async fn handle_message(
+        hub_sender: &broadcast::Sender<Arc<Protocol>>,
+        state: &RwLock<CacheState>,
+        message: &Protocol,
+    ) {
+        // The answers from the cache, the transfers of the server itself are followed like any other
+        if message.destination.is_some() {
+            return;
+        }
+
+        if ![
+            MISSION_COUNT_DATA::ID,
+            MISSION_ITEM_INT_DATA::ID,
+            MISSION_CLEAR_ALL_DATA::ID,
+            MISSION_CURRENT_DATA::ID,
+        ]
+        .contains(&message.message_id())
+            && !REQUEST_IDS.contains(&message.message_id())
+        {
+            return;
+        }
+
+        let Some((header, decoded)) = decode(message).await else {
+            return;
+        };
+        let sender = (header.system_id, header.component_id);
+
+        // Decided before the state changes, and followed, as the links do
+        let answered_locally = vehicles::answers_locally(message).await;
+
+        let mut state = state.write().await;
+
+        let request = if answered_locally {
+            state.cached_request(sender, &decoded)
+        } else {
+            None
+        };
+        let Some(request) = request else {
+            state.observe(sender, &decoded);
+            return;
+        };
+
+        trace!(
+            "Answering {decoded:?} from {} with the cache",
+            message.origin
+        );
+        let Some(answer) = state.answer(sender, &request) else {
+            return;
+        };
+
+        let (system_id, component_id) = match request {
+            CachedRequest::List(vehicle, _)
+            | CachedRequest::Item { vehicle, .. }
+            | CachedRequest::Ack(vehicle, _) => vehicle,
+        };
+        let header = MavHeader {
+            system_id,
+            component_id,
+            sequence: header.sequence,
+        };
+        if let Err(error) = send_to(hub_sender, header, &answer, &message.origin) {
+            warn!("Failed answering mission request: {error}");
+        }
+    }
+}
+
+/// Whether a mission request from a client is answered from the cache
+pub(crate) async fn answers_locally(message: &Protocol) -> bool {
+    if !REQUEST_IDS.contains(&message.message_id()) {
+        return false;
+    }
+
+    let Some((header, request)) = decode(message).await else {
+        return false;
+    };
+
+    MISSIONS
+        .state
+        .read()
+        .await
+        .cached_request((header.system_id, header.component_id), &request)
+        .is_some()
+}
+
+/// Starts caching the missions transferred through the hub
+pub(crate) fn init() {
+    lazy_static::initialize(&MISSIONS);
+}
+
+#[cfg(test)]
+mod tests {
+    use mavlink::ardupilotmega::{MavCmd, MavFrame};
+
+    use super::*;
+
+    const VEHICLE: Component = (1, 1);
+    const GCS: Component = (255, 190);
+    const OTHER_GCS: Component = (254, 190);
+    const MISSION: MavMissionType = MavMissionType::MAV_MISSION_TYPE_MISSION;
+
+    fn item(seq: u16, target: Component) -> MavMessage {
+        MavMessage::MISSION_ITEM_INT(MISSION_ITEM_INT_DATA {
+            seq,
+            x: 473977418 + seq as i32,
+            y: 85455939,
+            z: 20.0,
+            command: MavCmd::MAV_CMD_NAV_WAYPOINT,
+            frame: MavFrame::MAV_FRAME_GLOBAL_RELATIVE_ALT_INT,
+            target_system: target.0,
+            target_component: target.1,
+            mission_type: MISSION,
+            ..Default::default()
+        })
+    }
+
+    fn count(count: u16, target: Component, opaque_id: u32) -> MavMessage {
+        MavMessage::MISSION_COUNT(MISSION_COUNT_DATA {
+            count,
+            target_system: target.0,
+            target_component: target.1,
+            mission_type: MISSION,
+            opaque_id,
+        })
+    }
+
+    fn ack(target: Component, opaque_id: u32) -> MavMessage {
+        MavMessage::MISSION_ACK(MISSION_ACK_DATA {
+            target_system: target.0,
+            target_component: target.1,
+            mavtype: MavMissionResult::MAV_MISSION_ACCEPTED,
+            mission_type: MISSION,
+            opaque_id,
+        })
+    }
+
+    fn cached_count(state: &CacheState) -> Option<usize> {
+        Some(
+            state
+                .missions
+                .get(&(VEHICLE, type_key(MISSION)))?
+                .items
+                .len(),
+        )
+    }
+
+    #[test]
+    fn test_observe_transfers() {
+        let mut state = CacheState::default();
+
+        // A download by a client
+        state.observe(
+            GCS,
+            &MavMessage::MISSION_REQUEST_LIST(MISSION_REQUEST_LIST_DATA {
+                target_system: VEHICLE.0,
+                target_component: 0,
+                mission_type: MISSION,
+            }),
+        );
+        state.observe(VEHICLE, &count(2, GCS, 7));
+        state.observe(VEHICLE, &item(0, GCS));
+        state.observe(VEHICLE, &item(1, GCS));
+        assert_eq!(cached_count(&state), None);
+        state.observe(GCS, &ack(VEHICLE, 0));
+        assert_eq!(cached_count(&state), Some(2));
+        assert_eq!(state.missions[&(VEHICLE, type_key(MISSION))].opaque_id, 7);
+
+        // An incomplete upload by a client is not cached, and the refusal invalidates the cache
+        state.observe(GCS, &count(3, VEHICLE, 0));
+        state.observe(GCS, &item(0, VEHICLE));
+        state.observe(VEHICLE, &ack(GCS, 0));
+        assert_eq!(cached_count(&state), None);
+
+        // An upload by a client, with the new opaque ID in the vehicle acknowledgment
+        state.observe(GCS, &count(3, VEHICLE, 0));
+        for seq in 0..3 {
+            state.observe(GCS, &item(seq, VEHICLE));
+        }
+        state.observe(VEHICLE, &ack(GCS, 8));
+        assert_eq!(cached_count(&state), Some(3));
+        assert_eq!(state.missions[&(VEHICLE, type_key(MISSION))].opaque_id, 8);
+
+        // The home position is not part of the total
+        let current = |total: u16, mission_id: u32| {
+            MavMessage::MISSION_CURRENT(MISSION_CURRENT_DATA {
+                total,
+                mission_id,
+                ..Default::default()
+            })
+        };
+        state.observe(VEHICLE, &current(2, 8));
+        assert_eq!(cached_count(&state), Some(3));
+        state.observe(VEHICLE, &current(3, 9));
+        assert_eq!(cached_count(&state), None);
+    }
+
+    #[test]
+    fn test_cached_answers() {
+        let mut state = CacheState::default();
+        state.observe(GCS, &count(2, VEHICLE, 0));
+        state.observe(GCS, &item(0, VEHICLE));
+        state.observe(GCS, &item(1, VEHICLE));
+        state.observe(VEHICLE, &ack(GCS, 0));
+
+        let request_item = MavMessage::MISSION_REQUEST_INT(MISSION_REQUEST_INT_DATA {
+            seq: 1,
+            target_system: VEHICLE.0,
+            target_component: VEHICLE.1,
+            mission_type: MISSION,
+        });
+        // Only the clients that got the list from the cache get the items from it
+        assert_eq!(state.cached_request(OTHER_GCS, &request_item), None);
+
+        let request_list = MavMessage::MISSION_REQUEST_LIST(MISSION_REQUEST_LIST_DATA {
+            target_system: VEHICLE.0,
+            target_component: 0,
+            mission_type: MISSION,
+        });
+        let request = state.cached_request(OTHER_GCS, &request_list).unwrap();
+        assert_eq!(request, CachedRequest::List(VEHICLE, MISSION));
+        let Some(MavMessage::MISSION_COUNT(answer)) = state.answer(OTHER_GCS, &request) else {
+            panic!("Expected a MISSION_COUNT");
+        };
+        assert_eq!(answer.count, 2);
+        assert_eq!(answer.target_system, OTHER_GCS.0);
+
+        let request = state.cached_request(OTHER_GCS, &request_item).unwrap();
+        let Some(MavMessage::MISSION_ITEM_INT(answer)) = state.answer(OTHER_GCS, &request) else {
+            panic!("Expected a MISSION_ITEM_INT");
+        };
+        assert_eq!(answer.seq, 1);
+        assert_eq!(answer.x, 473977419);
+
+        let request = state.cached_request(OTHER_GCS, &ack(VEHICLE, 0)).unwrap();
+        assert_eq!(state.answer(OTHER_GCS, &request), None);
+        assert_eq!(state.cached_request(OTHER_GCS, &request_item), None);
+
+        // Forgotten when cleared
+        state.observe(
+            OTHER_GCS,
+            &MavMessage::MISSION_CLEAR_ALL(MISSION_CLEAR_ALL_DATA {
+                target_system: VEHICLE.0,
+                target_component: VEHICLE.1,
+                mission_type: MavMissionType::MAV_MISSION_TYPE_ALL,
+            }),
+        );
+        assert_eq!(state.cached_request(OTHER_GCS, &request_list), None);
+    }
+}
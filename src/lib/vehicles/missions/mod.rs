@@ -0,0 +1,518 @@
+pub mod cache;
+
+use std::{collections::HashMap, sync::Arc};
+
+use lazy_static::lazy_static;
+use mavlink::{
+    ardupilotmega::{
+        MavCmd, MavFrame, MavMessage, MavMissionResult, MavMissionType, MISSION_ACK_DATA,
+        MISSION_CLEAR_ALL_DATA, MISSION_COUNT_DATA, MISSION_ITEM_INT_DATA, MISSION_REQUEST_DATA,
+        MISSION_REQUEST_INT_DATA, MISSION_REQUEST_LIST_DATA,
+    },
+    MessageData,
+};
+use num_traits::{FromPrimitive, ToPrimitive};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{broadcast, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::*;
+
+use crate::{
+    protocol::Protocol,
+    vehicles::{
+        deserialize_enum_name, hub_sender, is_own_target, send, serialize_enum_name, wait_message,
+        VehicleError,
+    },
+};
+
+const TIMEOUT: Duration = Duration::from_millis(1500);
+const RETRIES: u8 = 5;
+/// Header of the plain-text mission files used by QGroundControl and Mission Planner
+const WPL_HEADER: &str = "QGC WPL 110";
+
+lazy_static! {
+    /// The mission protocol handles a single transfer at a time with each vehicle
+    static ref TRANSFERS: Mutex<HashMap<u8, Arc<Mutex<()>>>> = Mutex::new(HashMap::new());
+}
+
+/// A mission item, as in `MISSION_ITEM_INT`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MissionItem {
+    #[serde(
+        serialize_with = "serialize_enum_name",
+        deserialize_with = "deserialize_enum_name"
+    )]
+    pub command: MavCmd,
+    #[serde(
+        serialize_with = "serialize_enum_name",
+        deserialize_with = "deserialize_enum_name"
+    )]
+    pub frame: MavFrame,
+    #[serde(default)]
+    pub params: [f32; 4],
+    /// Latitude in degrees * 1e7 for global frames, or meters * 1e4 for local ones
+    #[serde(default)]
+    pub x: i32,
+    /// Longitude in degrees * 1e7 for global frames, or meters * 1e4 for local ones
+    #[serde(default)]
+    pub y: i32,
+    #[serde(default)]
+    pub z: f32,
+    #[serde(default)]
+    pub current: bool,
+    #[serde(default = "default_autocontinue")]
+    pub autocontinue: bool,
+}
+
+fn default_autocontinue() -> bool {
+    true
+}
+
+impl MissionItem {
+    pub fn from_mavlink(item: &MISSION_ITEM_INT_DATA) -> Self {
+        Self {
+            command: item.command,
+            frame: item.frame,
+            params: [item.param1, item.param2, item.param3, item.param4],
+            x: item.x,
+            y: item.y,
+            z: item.z,
+            current: item.current != 0,
+            autocontinue: item.autocontinue != 0,
+        }
+    }
+
+    pub fn to_mavlink(
+        &self,
+        system_id: u8,
+        component_id: u8,
+        seq: u16,
+        mission_type: MavMissionType,
+    ) -> MISSION_ITEM_INT_DATA {
+        MISSION_ITEM_INT_DATA {
+            param1: self.params[0],
+            param2: self.params[1],
+            param3: self.params[2],
+            param4: self.params[3],
+            x: self.x,
+            y: self.y,
+            z: self.z,
+            seq,
+            command: self.command,
+            target_system: system_id,
+            target_component: component_id,
+            frame: self.frame,
+            current: self.current as u8,
+            autocontinue: self.autocontinue as u8,
+            mission_type,
+        }
+    }
+
+    /// How `x` and `y` are scaled from the `MISSION_ITEM` floats
+    fn position_scale(&self) -> f64 {
+        match self.frame {
+            MavFrame::MAV_FRAME_MISSION => 1.0,
+            MavFrame::MAV_FRAME_GLOBAL
+            | MavFrame::MAV_FRAME_GLOBAL_RELATIVE_ALT
+            | MavFrame::MAV_FRAME_GLOBAL_INT
+            | MavFrame::MAV_FRAME_GLOBAL_RELATIVE_ALT_INT
+            | MavFrame::MAV_FRAME_GLOBAL_TERRAIN_ALT
+            | MavFrame::MAV_FRAME_GLOBAL_TERRAIN_ALT_INT => 1e7,
+            _ => 1e4,
+        }
+    }
+}
+
+pub fn mission_type_from_name(name: &str) -> Result<MavMissionType, VehicleError> {
+    match name {
+        "mission" => Ok(MavMissionType::MAV_MISSION_TYPE_MISSION),
+        "fence" => Ok(MavMissionType::MAV_MISSION_TYPE_FENCE),
+        "rally" => Ok(MavMissionType::MAV_MISSION_TYPE_RALLY),
+        _ => Err(VehicleError::Invalid(format!(
+            "Invalid mission type {name:?}, expected \"mission\", \"fence\" or \"rally\""
+        ))),
+    }
+}
+
+/// Exports the items as a QGC WPL 110 file
+pub fn to_wpl(items: &[MissionItem]) -> String {
+    let mut wpl = format!("{WPL_HEADER}\n");
+
+    for (seq, item) in items.iter().enumerate() {
+        let scale = item.position_scale();
+        let fields = [
+            seq.to_string(),
+            (item.current as u8).to_string(),
+            item.frame.to_u32().unwrap_or_default().to_string(),
+            item.command.to_u32().unwrap_or_default().to_string(),
+            item.params[0].to_string(),
+            item.params[1].to_string(),
+            item.params[2].to_string(),
+            item.params[3].to_string(),
+            format!("{:.8}", item.x as f64 / scale),
+            format!("{:.8}", item.y as f64 / scale),
+            item.z.to_string(),
+            (item.autocontinue as u8).to_string(),
+        ];
+
+        wpl.push_str(&fields.join("\t"));
+        wpl.push('\n');
+    }
+
+    wpl
+}
+
+/// Imports the items of a QGC WPL 110 file
+pub fn from_wpl(wpl: &str) -> Result<Vec<MissionItem>, VehicleError> {
+    let mut lines = wpl.lines().map(str::trim).filter(|line| !line.is_empty());
+
+    if lines.next() != Some(WPL_HEADER) {
+        return Err(VehicleError::Invalid(format!(
+            "Missing the {WPL_HEADER:?} header"
+        )));
+    }
+
+    lines
+        .enumerate()
+        .map(|(line_number, line)| {
+            let invalid =
+                |reason: &str| VehicleError::Invalid(format!("Line {}: {reason}", line_number + 2));
+
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            if fields.len() != 12 {
+                return Err(invalid("expected 12 fields"));
+            }
+
+            let integer = |index: usize| {
+                fields[index]
+                    .parse::<u32>()
+                    .map_err(|_| invalid(&format!("invalid integer {:?}", fields[index])))
+            };
+            let number = |index: usize| {
+                fields[index]
+                    .parse::<f64>()
+                    .map_err(|_| invalid(&format!("invalid number {:?}", fields[index])))
+            };
+
+            let frame = MavFrame::from_u32(integer(2)?).ok_or_else(|| invalid("unknown frame"))?;
+            let command =
+                MavCmd::from_u32(integer(3)?).ok_or_else(|| invalid("unknown command"))?;
+
+            let mut item = MissionItem {
+                command,
+                frame,
+                params: [
+                    number(4)? as f32,
+                    number(5)? as f32,
+                    number(6)? as f32,
+                    number(7)? as f32,
+                ],
+                x: 0,
+                y: 0,
+                z: number(10)? as f32,
+                current: integer(1)? != 0,
+                autocontinue: integer(11)? != 0,
+            };
+            let scale = item.position_scale();
+            item.x = (number(8)? * scale).round() as i32;
+            item.y = (number(9)? * scale).round() as i32;
+
+            Ok(item)
+        })
+        .collect()
+}
+
+async fn transfer_lock(system_id: u8) -> Arc<Mutex<()>> {
+    TRANSFERS.lock().await.entry(system_id).or_default().clone()
+}
+
+/// What a vehicle answered during a transfer
+enum Answer {
+    Count(u16),
+    Item(MISSION_ITEM_INT_DATA),
+    Request(u16),
+    Ack(MavMissionResult),
+}
+
+async fn wait_answer(
+    hub_receiver: &mut broadcast::Receiver<Arc<Protocol>>,
+    target: (u8, u8),
+    mission_type: MavMissionType,
+) -> Option<Answer> {
+    wait_message(
+        hub_receiver,
+        target,
+        &[
+            MISSION_COUNT_DATA::ID,
+            MISSION_ITEM_INT_DATA::ID,
+            MISSION_REQUEST_INT_DATA::ID,
+            MISSION_REQUEST_DATA::ID,
+            MISSION_ACK_DATA::ID,
+        ],
+        Instant::now() + TIMEOUT,
+        |message| match message {
+            MavMessage::MISSION_COUNT(count)
+                if count.mission_type == mission_type && is_own_target(count.target_system) =>
+            {
+                Some(Answer::Count(count.count))
+            }
+            MavMessage::MISSION_ITEM_INT(item)
+                if item.mission_type == mission_type && is_own_target(item.target_system) =>
+            {
+                Some(Answer::Item(item))
+            }
+            MavMessage::MISSION_REQUEST_INT(request)
+                if request.mission_type == mission_type && is_own_target(request.target_system) =>
+            {
+                Some(Answer::Request(request.seq))
+            }
+            MavMessage::MISSION_REQUEST(request)
+                if request.mission_type == mission_type && is_own_target(request.target_system) =>
+            {
+                Some(Answer::Request(request.seq))
+            }
+            MavMessage::MISSION_ACK(ack)
+                if ack.mission_type == mission_type && is_own_target(ack.target_system) =>
+            {
+                Some(Answer::Ack(ack.mavtype))
+            }
+            _ => None,
+        },
+    )
+    .await
+}
+
+fn rejected(result: MavMissionResult) -> VehicleError {
+    VehicleError::Rejected(format!("{result:?}"))
+}
+
+/// Downloads the items of a mission, fence or rally points
+#[instrument(level = "debug")]
+pub async fn download(
+    system_id: u8,
+    component_id: u8,
+    mission_type: MavMissionType,
+) -> Result<Vec<MissionItem>, VehicleError> {
+    let lock = transfer_lock(system_id).await;
+    let _transfer = lock.lock().await;
+
+    let target = (system_id, component_id);
+    let hub_sender = hub_sender().await?;
+    let mut hub_receiver = hub_sender.subscribe();
+
+    let mut count = None;
+    for _ in 0..RETRIES {
+        let request = MavMessage::MISSION_REQUEST_LIST(MISSION_REQUEST_LIST_DATA {
+            target_system: system_id,
+            target_component: component_id,
+            mission_type,
+        });
+        send(&hub_sender, &request)?;
+
+        match wait_answer(&mut hub_receiver, target, mission_type).await {
+            Some(Answer::Count(received)) => {
+                count = Some(received);
+                break;
+            }
+            Some(Answer::Ack(result)) => return Err(rejected(result)),
+            _ => continue,
+        }
+    }
+    let count = count.ok_or_else(|| {
+        VehicleError::Timeout(format!("No MISSION_COUNT from {system_id}:{component_id}"))
+    })?;
+
+    let mut items = Vec::with_capacity(count as usize);
+    for seq in 0..count {
+        let mut item = None;
+        for _ in 0..RETRIES {
+            let request = MavMessage::MISSION_REQUEST_INT(MISSION_REQUEST_INT_DATA {
+                seq,
+                target_system: system_id,
+                target_component: component_id,
+                mission_type,
+            });
+            send(&hub_sender, &request)?;
+
+            match wait_answer(&mut hub_receiver, target, mission_type).await {
+                Some(Answer::Item(received)) if received.seq == seq => {
+                    item = Some(MissionItem::from_mavlink(&received));
+                    break;
+                }
+                Some(Answer::Ack(result)) => return Err(rejected(result)),
+                _ => continue,
+            }
+        }
+
+        items.push(
+            item.ok_or_else(|| VehicleError::Timeout(format!("Mission item {seq} not received")))?,
+        );
+    }
+
+    let ack = MavMessage::MISSION_ACK(MISSION_ACK_DATA {
+        target_system: system_id,
+        target_component: component_id,
+        mavtype: MavMissionResult::MAV_MISSION_ACCEPTED,
+        mission_type,
+        ..Default::default()
+    });
+    send(&hub_sender, &ack)?;
+
+    debug!("Downloaded {count} items from {system_id}:{component_id}");
+
+    Ok(items)
+}
+
+/// Uploads the items of a mission, fence or rally points, replacing the current ones
+#[instrument(level = "debug", skip(items))]
+pub async fn upload(
+    system_id: u8,
+    component_id: u8,
+    mission_type: MavMissionType,
+    items: &[MissionItem],
+) -> Result<(), VehicleError> {
+    let count = u16::try_from(items.len())
+        .map_err(|_| VehicleError::Invalid(format!("Too many items: {}", items.len())))?;
+
+    let lock = transfer_lock(system_id).await;
+    let _transfer = lock.lock().await;
+
+    let target = (system_id, component_id);
+    let hub_sender = hub_sender().await?;
+    let mut hub_receiver = hub_sender.subscribe();
+
+    let count_message = MavMessage::MISSION_COUNT(MISSION_COUNT_DATA {
+        count,
+        target_system: system_id,
+        target_component: component_id,
+        mission_type,
+        ..Default::default()
+    });
+    send(&hub_sender, &count_message)?;
+
+    // The vehicle drives the transfer, and the last message is sent again when it goes quiet
+    let mut last_sent = count_message;
+    let mut retries = 0;
+    loop {
+        match wait_answer(&mut hub_receiver, target, mission_type).await {
+            Some(Answer::Request(seq)) => {
+                let item = items.get(seq as usize).ok_or_else(|| {
+                    VehicleError::Rejected(format!("Requested item {seq} of {count}"))
+                })?;
+
+                last_sent = MavMessage::MISSION_ITEM_INT(item.to_mavlink(
+                    system_id,
+                    component_id,
+                    seq,
+                    mission_type,
+                ));
+                send(&hub_sender, &last_sent)?;
+                retries = 0;
+            }
+            Some(Answer::Ack(MavMissionResult::MAV_MISSION_ACCEPTED)) => break,
+            Some(Answer::Ack(result)) => return Err(rejected(result)),
+            Some(_) => continue,
+            None if retries < RETRIES => {
+                send(&hub_sender, &last_sent)?;
+                retries += 1;
+            }
+            None => {
+                return Err(VehicleError::Timeout(format!(
+                    "Upload to {system_id}:{component_id} stalled"
+                )))
+            }
+        }
+    }
+
+    debug!("Uploaded {count} items to {system_id}:{component_id}");
+
+    Ok(())
+}
+
+/// Removes the mission, fence or rally points
+#[instrument(level = "debug")]
+pub async fn clear(
+    system_id: u8,
+    component_id: u8,
+    mission_type: MavMissionType,
+) -> Result<(), VehicleError> {
+    let lock = transfer_lock(system_id).await;
+    let _transfer = lock.lock().await;
+
+    let target = (system_id, component_id);
+    let hub_sender = hub_sender().await?;
+    let mut hub_receiver = hub_sender.subscribe();
+
+    for _ in 0..RETRIES {
+        let message = MavMessage::MISSION_CLEAR_ALL(MISSION_CLEAR_ALL_DATA {
+            target_system: system_id,
+            target_component: component_id,
+            mission_type,
+        });
+        send(&hub_sender, &message)?;
+
+        match wait_answer(&mut hub_receiver, target, mission_type).await {
+            Some(Answer::Ack(MavMissionResult::MAV_MISSION_ACCEPTED)) => return Ok(()),
+            Some(Answer::Ack(result)) => return Err(rejected(result)),
+            _ => continue,
+        }
+    }
+
+    Err(VehicleError::Timeout(format!(
+        "No MISSION_ACK from {system_id}:{component_id}"
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WPL: &str = "QGC WPL 110
+0\t1\t0\t16\t0\t0\t0\t0\t47.39774180\t8.54559390\t488.0\t1
+1\t0\t3\t22\t15\t0\t0\t0\t0.00000000\t0.00000000\t10\t1
+2\t0\t3\t16\t0\t0\t0\t0\t47.39800000\t8.54600000\t20\t1
+3\t0\t2\t178\t1\t5\t-1\t0\t0.00000000\t0.00000000\t0\t1
+";
+
+    #[test]
+    fn test_wpl() {
+        let items = from_wpl(WPL).unwrap();
+
+        assert_eq!(items.len(), 4);
+        assert!(items[0].current);
+        assert_eq!(items[1].command, MavCmd::MAV_CMD_NAV_TAKEOFF);
+        assert_eq!(items[1].params[0], 15.0);
+        assert_eq!(items[2].frame, MavFrame::MAV_FRAME_GLOBAL_RELATIVE_ALT);
+        assert_eq!(items[2].x, 473980000);
+        assert_eq!(items[2].y, 85460000);
+        assert_eq!(items[3].command, MavCmd::MAV_CMD_DO_CHANGE_SPEED);
+
+        assert_eq!(from_wpl(&to_wpl(&items)).unwrap(), items);
+
+        assert!(from_wpl("1\t0\t3\t16").is_err());
+        assert!(from_wpl("QGC WPL 110\n0\t1\t0\t16\t0\t0\t0").is_err());
+    }
+
+    #[test]
+    fn test_json_items() {
+        let items: Vec<MissionItem> = serde_json::from_str(
+            r#"[{"command": "MAV_CMD_NAV_WAYPOINT", "frame": "MAV_FRAME_GLOBAL_RELATIVE_ALT", "x": 473977418, "y": 85455939, "z": 20}]"#,
+        )
+        .unwrap();
+        assert!(items[0].autocontinue);
+        assert_eq!(items[0].params, [0.0; 4]);
+
+        let json = serde_json::to_value(&items).unwrap();
+        assert_eq!(json[0]["command"], "MAV_CMD_NAV_WAYPOINT");
+
+        let item = items[0].to_mavlink(1, 1, 0, MavMissionType::MAV_MISSION_TYPE_MISSION);
+        assert_eq!(MissionItem::from_mavlink(&item), items[0]);
+
+        assert!(serde_json::from_str::<Vec<MissionItem>>(
+            r#"[{"command": "MAV_CMD_FLY_TO_THE_MOON", "frame": "MAV_FRAME_MISSION"}]"#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,297 @@
+pub mod command;
+pub mod ftp;
+pub mod logs;
+pub mod missions;
+pub mod modes;
+pub mod parameters;
+pub mod registry;
+pub mod shell;
+pub mod state;
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc,
+    },
+};
+
+use mavlink::{ardupilotmega::MavMessage, MavHeader};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serializer};
+use tokio::{sync::broadcast, time::Instant};
+use tracing::*;
+
+use crate::{cli, hub, protocol::Protocol};
+
+/// Origin of the messages sent by the server itself to the vehicles, which every driver forwards
+pub const ORIGIN: &str = "vehicles";
+
+static SEQUENCE: AtomicU8 = AtomicU8::new(0);
+
+/// Why a request to a vehicle failed
+#[derive(Debug)]
+pub enum VehicleError {
+    /// The request itself is wrong
+    Invalid(String),
+    /// The vehicle, component or resource is not known
+    NotFound(String),
+    /// The vehicle didn't answer in time
+    Timeout(String),
+    /// The vehicle answered, but didn't do what was asked
+    Rejected(String),
+    /// The hub couldn't take the messages
+    Unreachable(String),
+}
+
+impl std::fmt::Display for VehicleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VehicleError::Invalid(reason) => write!(f, "invalid request: {reason}"),
+            VehicleError::NotFound(reason) => write!(f, "not found: {reason}"),
+            VehicleError::Timeout(reason) => write!(f, "timeout: {reason}"),
+            VehicleError::Rejected(reason) => write!(f, "rejected: {reason}"),
+            VehicleError::Unreachable(reason) => write!(f, "hub unreachable: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for VehicleError {}
+
+/// The hub sender, used to both talk to the vehicles and receive their answers
+pub(crate) async fn hub_sender() -> Result<broadcast::Sender<Arc<Protocol>>, VehicleError> {
+    hub::sender()
+        .await
+        .map_err(|error| VehicleError::Unreachable(error.to_string()))
+}
+
+/// Sends a message to the hub, with the server's own system and component IDs
+pub(crate) fn send(
+    hub_sender: &broadcast::Sender<Arc<Protocol>>,
+    message: &MavMessage,
+) -> Result<(), VehicleError> {
+    let header = MavHeader {
+        system_id: cli::mavlink_system_id(),
+        component_id: cli::mavlink_component_id(),
+        sequence: SEQUENCE.fetch_add(1, Ordering::Relaxed),
+    };
+
+    hub_sender
+        .send(Arc::new(Protocol::from_mavlink_raw(
+            header, message, ORIGIN,
+        )))
+        .map(|_| ())
+        .map_err(|error| VehicleError::Unreachable(error.to_string()))
+}
+
+/// Sends a message on behalf of a vehicle component, only to the link of the given origin
+pub(crate) fn send_to(
+    hub_sender: &broadcast::Sender<Arc<Protocol>>,
+    header: MavHeader,
+    message: &MavMessage,
+    destination: &str,
+) -> Result<(), VehicleError> {
+    let mut protocol = Protocol::from_mavlink_raw(header, message, ORIGIN);
+    protocol.destination = Some(destination.to_string());
+
+    hub_sender
+        .send(Arc::new(protocol))
+        .map(|_| ())
+        .map_err(|error| VehicleError::Unreachable(error.to_string()))
+}
+
+/// Whether the server answers a request from a client itself, so it is not forwarded to the vehicles.
+/// It is decided by the first task that asks, so the links and the caches agree on it even when the
+/// caches change meanwhile.
+pub async fn answers_locally(message: &Protocol) -> bool {
+    if message.origin == ORIGIN {
+        return false;
+    }
+
+    *message
+        .answered_locally
+        .get_or_init(|| async {
+            parameters::answers_locally(message).await
+                || missions::cache::answers_locally(message).await
+        })
+        .await
+}
+
+/// Decodes a message from the hub, for the messages in the `ardupilotmega` dialect
+pub(crate) async fn decode(message: &Protocol) -> Option<(MavHeader, MavMessage)> {
+    let content = message.to_mavlink_json::<MavMessage>().await.ok()?;
+
+    Some((content.header.inner, content.message))
+}
+
+/// Waits until the deadline for a message from a component, returning what the filter makes of it.
+/// Only the messages with the given IDs are decoded, and the ones sent by the server are ignored.
+pub(crate) async fn wait_message<T>(
+    hub_receiver: &mut broadcast::Receiver<Arc<Protocol>>,
+    (system_id, component_id): (u8, u8),
+    message_ids: &[u32],
+    deadline: Instant,
+    mut filter: impl FnMut(MavMessage) -> Option<T>,
+) -> Option<T> {
+    tokio::time::timeout_at(deadline, async {
+        loop {
+            let message = match hub_receiver.recv().await {
+                Ok(message) => message,
+                Err(broadcast::error::RecvError::Lagged(samples)) => {
+                    warn!("Channel lagged by {samples} messages.");
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            };
+
+            if message.origin == ORIGIN
+                || *message.system_id() != system_id
+                || *message.component_id() != component_id
+                || !message_ids.contains(&message.message_id())
+            {
+                continue;
+            }
+
+            let Some((_, decoded)) = decode(&message).await else {
+                continue;
+            };
+
+            if let Some(result) = filter(decoded) {
+                return Some(result);
+            }
+        }
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+/// Whether a message targets the server, as some autopilots don't fill the target of their answers
+pub(crate) fn is_own_target(target_system: u8) -> bool {
+    target_system == 0 || target_system == cli::mavlink_system_id()
+}
+
+/// Parses a MAVLink enum from its name, with or without its prefix, like `MAV_CMD_NAV_LAND` or `nav_land`
+pub(crate) fn enum_from_name<T: DeserializeOwned>(
+    prefix: &str,
+    name: &str,
+) -> Result<T, VehicleError> {
+    let name = name.trim().to_ascii_uppercase();
+    let name = if name.starts_with(prefix) {
+        name
+    } else {
+        format!("{prefix}{name}")
+    };
+
+    serde_json::from_value(serde_json::json!({ "type": name }))
+        .map_err(|_| VehicleError::Invalid(format!("Unknown {prefix}* value {name:?}")))
+}
+
+/// Serializes a MAVLink enum as its name, instead of the `{"type": name}` used by `MAVLinkJSON`
+pub(crate) fn serialize_enum_name<T: std::fmt::Debug, S: Serializer>(
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{value:?}"))
+}
+
+/// Deserializes a MAVLink enum from its full name, like `MAV_CMD_NAV_WAYPOINT`
+pub(crate) fn deserialize_enum_name<'de, T: DeserializeOwned, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<T, D::Error> {
+    let name = String::deserialize(deserializer)?;
+
+    serde_json::from_value(serde_json::json!({ "type": name }))
+        .map_err(|_| serde::de::Error::custom(format!("Unknown value {name:?}")))
+}
+
+/// The byte ranges of a file already received, which can come in any order and more than once
+#[derive(Debug, Default)]
+pub(crate) struct Received {
+    ranges: BTreeMap<u32, u32>,
+}
+
+impl Received {
+    pub(crate) fn insert(&mut self, start: u32, end: u32) {
+        if end > start {
+            let range_end = self.ranges.entry(start).or_insert(end);
+            *range_end = (*range_end).max(end);
+        }
+    }
+
+    pub(crate) fn first_gap(&self, size: u32) -> Option<(u32, u32)> {
+        let mut covered = 0;
+        for (&start, &end) in &self.ranges {
+            if start > covered {
+                return Some((covered, start.min(size)));
+            }
+            covered = covered.max(end);
+        }
+
+        (covered < size).then_some((covered, size))
+    }
+
+    pub(crate) fn bytes(&self) -> u32 {
+        let mut bytes = 0;
+        let mut covered = 0;
+        for (&start, &end) in &self.ranges {
+            let start = start.max(covered);
+            if end > start {
+                bytes += end - start;
+                covered = end;
+            }
+        }
+
+        bytes
+    }
+}
+
+/// Starts the services that observe the hub traffic, so they know the vehicles before being asked
+pub fn init() {
+    registry::init();
+    state::init();
+    parameters::init();
+    missions::cache::init();
+}
+
+#[cfg(test)]
+mod tests {
+    use mavlink::ardupilotmega::{MavCmd, MavFrame};
+
+    use super::*;
+
+    #[test]
+    fn test_enum_from_name() {
+        assert_eq!(
+            enum_from_name::<MavCmd>("MAV_CMD_", "MAV_CMD_COMPONENT_ARM_DISARM").unwrap(),
+            MavCmd::MAV_CMD_COMPONENT_ARM_DISARM
+        );
+        assert_eq!(
+            enum_from_name::<MavCmd>("MAV_CMD_", "nav_land").unwrap(),
+            MavCmd::MAV_CMD_NAV_LAND
+        );
+        assert_eq!(
+            enum_from_name::<MavFrame>("MAV_FRAME_", "GLOBAL_RELATIVE_ALT_INT").unwrap(),
+            MavFrame::MAV_FRAME_GLOBAL_RELATIVE_ALT_INT
+        );
+        assert!(enum_from_name::<MavCmd>("MAV_CMD_", "FLY_TO_THE_MOON").is_err());
+    }
+
+    #[test]
+    fn test_received() {
+        let mut received = Received::default();
+        assert_eq!(received.first_gap(1000), Some((0, 1000)));
+
+        received.insert(0, 239);
+        received.insert(478, 717);
+        received.insert(478, 600);
+        assert_eq!(received.bytes(), 478);
+        assert_eq!(received.first_gap(1000), Some((239, 478)));
+
+        received.insert(239, 478);
+        assert_eq!(received.first_gap(1000), Some((717, 1000)));
+
+        received.insert(717, 1000);
+        assert_eq!(received.bytes(), 1000);
+        assert_eq!(received.first_gap(1000), None);
+    }
+}
@@ -0,0 +1,242 @@
+use mavlink::ardupilotmega::{MavAutopilot, MavModeFlag, MavType};
+
+/// ArduPilot modes by `custom_mode`, which depend on the firmware of the vehicle type
+type ModeTable = &'static [(u32, &'static str)];
+
+const ARDUCOPTER: ModeTable = &[
+    (0, "STABILIZE"),
+    (1, "ACRO"),
+    (2, "ALT_HOLD"),
+    (3, "AUTO"),
+    (4, "GUIDED"),
+    (5, "LOITER"),
+    (6, "RTL"),
+    (7, "CIRCLE"),
+    (9, "LAND"),
+    (11, "DRIFT"),
+    (13, "SPORT"),
+    (14, "FLIP"),
+    (15, "AUTOTUNE"),
+    (16, "POSHOLD"),
+    (17, "BRAKE"),
+    (18, "THROW"),
+    (19, "AVOID_ADSB"),
+    (20, "GUIDED_NOGPS"),
+    (21, "SMART_RTL"),
+    (22, "FLOWHOLD"),
+    (23, "FOLLOW"),
+    (24, "ZIGZAG"),
+    (25, "SYSTEMID"),
+    (26, "AUTOROTATE"),
+    (27, "AUTO_RTL"),
+    (28, "TURTLE"),
+];
+
+const ARDUPLANE: ModeTable = &[
+    (0, "MANUAL"),
+    (1, "CIRCLE"),
+    (2, "STABILIZE"),
+    (3, "TRAINING"),
+    (4, "ACRO"),
+    (5, "FBWA"),
+    (6, "FBWB"),
+    (7, "CRUISE"),
+    (8, "AUTOTUNE"),
+    (10, "AUTO"),
+    (11, "RTL"),
+    (12, "LOITER"),
+    (13, "TAKEOFF"),
+    (14, "AVOID_ADSB"),
+    (15, "GUIDED"),
+    (17, "QSTABILIZE"),
+    (18, "QHOVER"),
+    (19, "QLOITER"),
+    (20, "QLAND"),
+    (21, "QRTL"),
+    (22, "QAUTOTUNE"),
+    (23, "QACRO"),
+    (24, "THERMAL"),
+    (25, "LOITER_ALT_QLAND"),
+];
+
+const ARDUROVER: ModeTable = &[
+    (0, "MANUAL"),
+    (1, "ACRO"),
+    (3, "STEERING"),
+    (4, "HOLD"),
+    (5, "LOITER"),
+    (6, "FOLLOW"),
+    (7, "SIMPLE"),
+    (8, "DOCK"),
+    (9, "CIRCLE"),
+    (10, "AUTO"),
+    (11, "RTL"),
+    (12, "SMART_RTL"),
+    (15, "GUIDED"),
+    (16, "INITIALISING"),
+];
+
+const ARDUSUB: ModeTable = &[
+    (0, "STABILIZE"),
+    (1, "ACRO"),
+    (2, "ALT_HOLD"),
+    (3, "AUTO"),
+    (4, "GUIDED"),
+    (7, "CIRCLE"),
+    (9, "SURFACE"),
+    (16, "POSHOLD"),
+    (19, "MANUAL"),
+    (20, "MOTOR_DETECT"),
+    (21, "SURFTRAK"),
+];
+
+const ANTENNA_TRACKER: ModeTable = &[
+    (0, "MANUAL"),
+    (1, "STOP"),
+    (2, "SCAN"),
+    (3, "SERVO_TEST"),
+    (10, "AUTO"),
+    (16, "INITIALISING"),
+];
+
+const BLIMP: ModeTable = &[
+    (0, "LAND"),
+    (1, "MANUAL"),
+    (2, "VELOCITY"),
+    (3, "LOITER"),
+    (4, "RTL"),
+];
+
+fn ardupilot_modes(mav_type: MavType) -> Option<ModeTable> {
+    match mav_type {
+        MavType::MAV_TYPE_QUADROTOR
+        | MavType::MAV_TYPE_HEXAROTOR
+        | MavType::MAV_TYPE_OCTOROTOR
+        | MavType::MAV_TYPE_TRICOPTER
+        | MavType::MAV_TYPE_COAXIAL
+        | MavType::MAV_TYPE_HELICOPTER
+        | MavType::MAV_TYPE_DODECAROTOR
+        | MavType::MAV_TYPE_DECAROTOR => Some(ARDUCOPTER),
+        MavType::MAV_TYPE_FIXED_WING
+        | MavType::MAV_TYPE_VTOL_TAILSITTER_DUOROTOR
+        | MavType::MAV_TYPE_VTOL_TAILSITTER_QUADROTOR
+        | MavType::MAV_TYPE_VTOL_TILTROTOR
+        | MavType::MAV_TYPE_VTOL_FIXEDROTOR
+        | MavType::MAV_TYPE_VTOL_TAILSITTER
+        | MavType::MAV_TYPE_VTOL_TILTWING
+        | MavType::MAV_TYPE_VTOL_RESERVED5 => Some(ARDUPLANE),
+        MavType::MAV_TYPE_GROUND_ROVER | MavType::MAV_TYPE_SURFACE_BOAT => Some(ARDUROVER),
+        MavType::MAV_TYPE_SUBMARINE => Some(ARDUSUB),
+        MavType::MAV_TYPE_ANTENNA_TRACKER => Some(ANTENNA_TRACKER),
+        MavType::MAV_TYPE_AIRSHIP => Some(BLIMP),
+        _ => None,
+    }
+}
+
+/// PX4 packs its main mode in the third byte of `custom_mode`, and its sub mode in the fourth one
+fn px4_mode(custom_mode: u32) -> Option<&'static str> {
+    let main_mode = (custom_mode >> 16) & 0xff;
+    let sub_mode = (custom_mode >> 24) & 0xff;
+
+    let mode = match (main_mode, sub_mode) {
+        (1, _) => "MANUAL",
+        (2, _) => "ALTCTL",
+        (3, 1) => "POSCTL_ORBIT",
+        (3, 2) => "POSCTL_SLOW",
+        (3, _) => "POSCTL",
+        (4, 1) => "AUTO_READY",
+        (4, 2) => "AUTO_TAKEOFF",
+        (4, 3) => "AUTO_LOITER",
+        (4, 4) => "AUTO_MISSION",
+        (4, 5) => "AUTO_RTL",
+        (4, 6) => "AUTO_LAND",
+        (4, 8) => "AUTO_FOLLOW_TARGET",
+        (4, 9) => "AUTO_PRECLAND",
+        (4, 10) => "AUTO_VTOL_TAKEOFF",
+        (5, _) => "ACRO",
+        (6, _) => "OFFBOARD",
+        (7, _) => "STABILIZED",
+        (8, _) => "RATTITUDE",
+        (9, _) => "SIMPLE",
+        (10, _) => "TERMINATION",
+        _ => return None,
+    };
+
+    Some(mode)
+}
+
+/// The name of the flight mode of a `HEARTBEAT`, for the autopilots that use custom modes
+pub fn flight_mode(
+    autopilot: MavAutopilot,
+    mav_type: MavType,
+    base_mode: MavModeFlag,
+    custom_mode: u32,
+) -> Option<String> {
+    if !base_mode.contains(MavModeFlag::MAV_MODE_FLAG_CUSTOM_MODE_ENABLED) {
+        return None;
+    }
+
+    let mode = match autopilot {
+        MavAutopilot::MAV_AUTOPILOT_ARDUPILOTMEGA => ardupilot_modes(mav_type)?
+            .iter()
+            .find(|(number, _)| *number == custom_mode)
+            .map(|(_, name)| *name),
+        MavAutopilot::MAV_AUTOPILOT_PX4 => px4_mode(custom_mode),
+        _ => None,
+    };
+
+    mode.map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flight_mode() {
+        let custom = MavModeFlag::MAV_MODE_FLAG_CUSTOM_MODE_ENABLED;
+        let ardupilot = MavAutopilot::MAV_AUTOPILOT_ARDUPILOTMEGA;
+
+        let mode = |autopilot, mav_type, custom_mode| {
+            flight_mode(autopilot, mav_type, custom, custom_mode)
+        };
+
+        assert_eq!(
+            mode(ardupilot, MavType::MAV_TYPE_QUADROTOR, 4).as_deref(),
+            Some("GUIDED")
+        );
+        assert_eq!(
+            mode(ardupilot, MavType::MAV_TYPE_FIXED_WING, 4).as_deref(),
+            Some("ACRO")
+        );
+        assert_eq!(
+            mode(ardupilot, MavType::MAV_TYPE_SUBMARINE, 19).as_deref(),
+            Some("MANUAL")
+        );
+        assert_eq!(
+            mode(ardupilot, MavType::MAV_TYPE_GROUND_ROVER, 10).as_deref(),
+            Some("AUTO")
+        );
+        assert_eq!(mode(ardupilot, MavType::MAV_TYPE_QUADROTOR, 8), None);
+
+        let px4 = MavAutopilot::MAV_AUTOPILOT_PX4;
+        assert_eq!(
+            mode(px4, MavType::MAV_TYPE_QUADROTOR, (4 << 24) | (4 << 16)).as_deref(),
+            Some("AUTO_MISSION")
+        );
+        assert_eq!(
+            mode(px4, MavType::MAV_TYPE_FIXED_WING, 3 << 16).as_deref(),
+            Some("POSCTL")
+        );
+
+        assert_eq!(
+            flight_mode(
+                ardupilot,
+                MavType::MAV_TYPE_QUADROTOR,
+                MavModeFlag::empty(),
+                4
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,831 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use lazy_static::lazy_static;
+use mavlink::{
+    ardupilotmega::{
+        MavMessage, MavParamType, HEARTBEAT_DATA, PARAM_REQUEST_LIST_DATA, PARAM_REQUEST_READ_DATA,
+        PARAM_SET_DATA, PARAM_VALUE_DATA, SYSTEM_TIME_DATA,
+    },
+    MavHeader, MessageData,
+};
+use serde::Serialize;
+use tokio::{
+    sync::{broadcast, RwLock},
+    time::{Duration, Instant},
+};
+use tracing::*;
+
+use crate::{
+    protocol::Protocol,
+    vehicles::{
+        self, decode, hub_sender, send, send_to, serialize_enum_name, VehicleError, ORIGIN,
+    },
+};
+
+/// How long a parameter list transfer can be quiet before the missing parameters are requested
+const TRANSFER_IDLE: Duration = Duration::from_secs(1);
+const MISSING_REQUESTS_PER_ROUND: usize = 10;
+/// Rounds of missing parameter requests without any answer before giving up on a component
+const MAX_MISSING_ROUNDS: u8 = 10;
+const LIST_TIMEOUT: Duration = Duration::from_secs(30);
+const READ_TIMEOUT: Duration = Duration::from_millis(1500);
+const RETRIES: u8 = 3;
+/// Components that stop sending heartbeats for longer than this may have rebooted with other parameters
+const HEARTBEAT_LOSS: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    static ref PARAMETERS: Parameters = Parameters::new();
+}
+
+struct Parameters {
+    components: Arc<RwLock<HashMap<(u8, u8), ComponentParameters>>>,
+    /// Every parameter received, for the requests waiting on them
+    updates: broadcast::Sender<ParameterUpdate>,
+    _task: Arc<Mutex<tokio::task::JoinHandle<()>>>,
+}
+
+#[derive(Debug, Clone)]
+struct ParameterUpdate {
+    system_id: u8,
+    component_id: u8,
+    parameter: Parameter,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Parameter {
+    pub name: String,
+    /// The value as sent by the component, which for integer parameters depends on its encoding
+    pub value: f32,
+    #[serde(rename = "type", serialize_with = "serialize_enum_name")]
+    pub param_type: MavParamType,
+    pub index: u16,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ParameterSet {
+    /// Whether every parameter of the component is known
+    pub complete: bool,
+    pub count: u16,
+    /// Sorted by index
+    pub parameters: Vec<Parameter>,
+}
+
+impl From<&PARAM_VALUE_DATA> for Parameter {
+    fn from(value: &PARAM_VALUE_DATA) -> Self {
+        Self {
+            name: param_name(&value.param_id),
+            value: value.param_value,
+            param_type: value.param_type,
+            index: value.param_index,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ComponentParameters {
+    count: u16,
+    /// Names by index, to know which ones are missing
+    names: BTreeMap<u16, String>,
+    parameters: HashMap<String, Parameter>,
+    last_received: Instant,
+    missing_rounds: u8,
+    last_heartbeat: Option<Instant>,
+    /// From `SYSTEM_TIME`, to detect reboots
+    time_boot_ms: Option<u32>,
+    /// Of the answers sent from the cache, continued between requests
+    sequence: u8,
+}
+
+impl ComponentParameters {
+    fn new() -> Self {
+        Self {
+            count: 0,
+            names: BTreeMap::new(),
+            parameters: HashMap::new(),
+            last_received: Instant::now(),
+            missing_rounds: 0,
+            last_heartbeat: None,
+            time_boot_ms: None,
+            sequence: 0,
+        }
+    }
+
+    fn update(&mut self, parameter: Parameter, count: u16) {
+        // Indexes are shifted when parameters are added or removed, like after enabling a feature
+        if count != self.count {
+            self.count = count;
+            self.names.clear();
+            self.parameters.clear();
+        }
+
+        // Parameters that are not part of the list are sent with an invalid index
+        if parameter.index < self.count {
+            if let Some(name) = self.names.insert(parameter.index, parameter.name.clone()) {
+                if name != parameter.name {
+                    self.parameters.remove(&name);
+                }
+            }
+        }
+
+        self.parameters.insert(parameter.name.clone(), parameter);
+        self.last_received = Instant::now();
+        self.missing_rounds = 0;
+    }
+
+    fn forget(&mut self, name: &str) {
+        if let Some(parameter) = self.parameters.remove(name) {
+            self.names.remove(&parameter.index);
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.count > 0 && self.names.len() == self.count as usize
+    }
+
+    fn missing(&self) -> impl Iterator<Item = u16> + '_ {
+        (0..self.count).filter(|index| !self.names.contains_key(index))
+    }
+
+    fn set(&self) -> ParameterSet {
+        let mut parameters = self.parameters.values().cloned().collect::<Vec<_>>();
+        parameters.sort_by_key(|parameter| parameter.index);
+
+        ParameterSet {
+            complete: self.is_complete(),
+            count: self.count,
+            parameters,
+        }
+    }
+}
+
+impl Parameters {
+    fn new() -> Self {
+        let components = Arc::new(RwLock::new(HashMap::new()));
+        let (updates, _) = broadcast::channel(1000);
+
+        let _task = Arc::new(Mutex::new(tokio::spawn(Self::observe_task(
+            components.clone(),
+            updates.clone(),
+        ))));
+
+        Self {
+            components,
+            updates,
+            _task,
+        }
+    }
+
+    /// Caches every `PARAM_VALUE` in the hub, and requests the ones missing from incomplete lists
+    async fn observe_task(
+        components: Arc<RwLock<HashMap<(u8, u8), ComponentParameters>>>,
+        updates: broadcast::Sender<ParameterUpdate>,
+    ) {
+        let hub_sender = match hub_sender().await {
+            Ok(hub_sender) => hub_sender,
+            Err(error) => {
+                error!("Parameters won't be cached: {error}");
+                return;
+            }
+        };
+        let mut hub_receiver = hub_sender.subscribe();
+        let mut interval = tokio::time::interval(TRANSFER_IDLE);
+
+        loop {
+            tokio::select! {
+                result = hub_receiver.recv() => match result {
+                    Ok(message) => {
+                        Self::handle_message(&hub_sender, &components, &updates, &message).await
+                    }
+                    Err(broadcast::error::RecvError::Lagged(samples)) => {
+                        warn!("Channel lagged by {samples} messages.");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = interval.tick() => Self::request_missing(&hub_sender, &components).await,
+            }
+        }
+
+        debug!("Parameters observer task stopped!");
+    }
+
+    async fn handle_message(
+        hub_sender: &broadcast::Sender<Arc<Protocol>>,
+        components: &RwLock<HashMap<(u8, u8), ComponentParameters>>,
+        updates: &broadcast::Sender<ParameterUpdate>,
+        message: &Protocol,
+    ) {
+        if message.origin == ORIGIN {
+            return;
+        }
+
+        let key = (*message.system_id(), *message.component_id());
+        let message_id = message.message_id();
+
+        if message_id == HEARTBEAT_DATA::ID {
+            let mut components = components.write().await;
+            if let Some(component) = components.get_mut(&key) {
+                if component
+                    .last_heartbeat
+                    .is_some_and(|last_heartbeat| last_heartbeat.elapsed() > HEARTBEAT_LOSS)
+                {
+                    debug!("Parameters of {key:?} invalidated, its heartbeat was lost");
+                    components.remove(&key);
+                } else {
+                    component.last_heartbeat = Some(Instant::now());
+                }
+            }
+            return;
+        }
+
+        if ![
+            PARAM_VALUE_DATA::ID,
+            PARAM_SET_DATA::ID,
+            PARAM_REQUEST_LIST_DATA::ID,
+            PARAM_REQUEST_READ_DATA::ID,
+            SYSTEM_TIME_DATA::ID,
+        ]
+        .contains(&message_id)
+        {
+            return;
+        }
+
+        let Some((_, decoded)) = decode(message).await else {
+            return;
+        };
+
+        match decoded {
+            MavMessage::PARAM_VALUE(value) => {
+                let parameter = Parameter::from(&value);
+                trace!("Parameter from {key:?}: {parameter:?}");
+
+                components
+                    .write()
+                    .await
+                    .entry(key)
+                    .or_insert_with(ComponentParameters::new)
+                    .update(parameter.clone(), value.param_count);
+
+                let _ = updates.send(ParameterUpdate {
+                    system_id: key.0,
+                    component_id: key.1,
+                    parameter,
+                });
+            }
+            MavMessage::SYSTEM_TIME(system_time) => {
+                let mut components = components.write().await;
+                if let Some(component) = components.get_mut(&key) {
+                    if component
+                        .time_boot_ms
+                        .is_some_and(|time_boot_ms| system_time.time_boot_ms < time_boot_ms)
+                    {
+                        debug!("Parameters of {key:?} invalidated, it rebooted");
+                        components.remove(&key);
+                    } else {
+                        component.time_boot_ms = Some(system_time.time_boot_ms);
+                    }
+                }
+            }
+            MavMessage::PARAM_SET(set) => {
+                // Not trusted until the component answers with the new value
+                let target = (set.target_system, set.target_component);
+                if let Some(component) = components.write().await.get_mut(&target) {
+                    component.forget(&param_name(&set.param_id));
+                }
+            }
+            request => {
+                // Follows the decision the links took, so it is neither answered twice nor dropped
+                if !vehicles::answers_locally(message).await {
+                    return;
+                }
+
+                let mut components = components.write().await;
+                let Some(cached) = cached_request(&components, &request) else {
+                    return;
+                };
+
+                debug!(
+                    "Answering {request:?} from {} with the cache",
+                    message.origin
+                );
+                if let Err(error) = answer(hub_sender, &mut components, cached, &message.origin) {
+                    warn!("Failed answering parameters request: {error}");
+                }
+            }
+        }
+    }
+
+    async fn request_missing(
+        hub_sender: &broadcast::Sender<Arc<Protocol>>,
+        components: &RwLock<HashMap<(u8, u8), ComponentParameters>>,
+    ) {
+        let mut requests = vec![];
+
+        for (&(system_id, component_id), component) in components.write().await.iter_mut() {
+            if component.is_complete()
+                || component.missing_rounds >= MAX_MISSING_ROUNDS
+                || component.last_received.elapsed() < TRANSFER_IDLE
+            {
+                continue;
+            }
+
+            component.missing_rounds += 1;
+            requests.extend(
+                component
+                    .missing()
+                    .take(MISSING_REQUESTS_PER_ROUND)
+                    .map(|index| {
+                        MavMessage::PARAM_REQUEST_READ(PARAM_REQUEST_READ_DATA {
+                            param_index: index as i16,
+                            target_system: system_id,
+                            target_component: component_id,
+                            param_id: [0; 16],
+                        })
+                    }),
+            );
+        }
+
+        for message in requests {
+            if let Err(error) = send(hub_sender, &message) {
+                warn!("Failed requesting missing parameter: {error}");
+                break;
+            }
+        }
+    }
+}
+
+/// A parameter request that can be answered from the cache
+#[derive(Debug, PartialEq)]
+enum CachedRequest {
+    /// Every parameter of these components
+    List(Vec<(u8, u8)>),
+    Read((u8, u8), String),
+}
+
+/// Requests are only answered from complete caches, as the clients expect consistent counts and indexes.
+/// Lists for every component of a system are answered when all of its known components are cached.
+fn cached_request(
+    components: &HashMap<(u8, u8), ComponentParameters>,
+    request: &MavMessage,
+) -> Option<CachedRequest> {
+    match request {
+        MavMessage::PARAM_REQUEST_LIST(request) => {
+            let keys = if request.target_component == 0 {
+                let mut keys = components
+                    .keys()
+                    .filter(|(system_id, _)| *system_id == request.target_system)
+                    .copied()
+                    .collect::<Vec<_>>();
+                keys.sort();
+                keys
+            } else {
+                vec![(request.target_system, request.target_component)]
+            };
+
+            let complete = !keys.is_empty()
+                && keys.iter().all(|key| {
+                    components
+                        .get(key)
+                        .is_some_and(|component| component.is_complete())
+                });
+
+            complete.then_some(CachedRequest::List(keys))
+        }
+        MavMessage::PARAM_REQUEST_READ(request) => {
+            let key = (request.target_system, request.target_component);
+            let component = components.get(&key)?;
+            if !component.is_complete() {
+                return None;
+            }
+
+            let name = if request.param_index < 0 {
+                param_name(&request.param_id)
+            } else {
+                component.names.get(&(request.param_index as u16))?.clone()
+            };
+
+            component
+                .parameters
+                .contains_key(&name)
+                .then_some(CachedRequest::Read(key, name))
+        }
+        _ => None,
+    }
+}
+
+/// Sends the cached parameters to the client that requested them, as if the components did
+fn answer(
+    hub_sender: &broadcast::Sender<Arc<Protocol>>,
+    components: &mut HashMap<(u8, u8), ComponentParameters>,
+    request: CachedRequest,
+    destination: &str,
+) -> Result<(), VehicleError> {
+    let parameters = match &request {
+        CachedRequest::List(keys) => keys
+            .iter()
+            .filter_map(|key| Some((*key, components.get(key)?)))
+            .flat_map(|(key, component)| {
+                component
+                    .set()
+                    .parameters
+                    .into_iter()
+                    .map(move |parameter| (key, component.count, parameter))
+            })
+            .collect::<Vec<_>>(),
+        CachedRequest::Read(key, name) => components
+            .get(key)
+            .and_then(|component| {
+                let parameter = component.parameters.get(name)?.clone();
+                Some(vec![(*key, component.count, parameter)])
+            })
+            .unwrap_or_default(),
+    };
+
+    for ((system_id, component_id), count, parameter) in parameters {
+        let Some(component) = components.get_mut(&(system_id, component_id)) else {
+            continue;
+        };
+        let header = MavHeader {
+            system_id,
+            component_id,
+            sequence: component.sequence,
+        };
+        component.sequence = component.sequence.wrapping_add(1);
+
+        let message = MavMessage::PARAM_VALUE(PARAM_VALUE_DATA {
+            param_value: parameter.value,
+            param_count: count,
+            param_index: parameter.index,
+            param_id: param_id(&parameter.name)?,
+            param_type: parameter.param_type,
+        });
+
+        send_to(hub_sender, header, &message, destination)?;
+    }
+
+    Ok(())
+}
+
+/// Whether a parameter request from a client is answered from the cache
+pub(crate) async fn answers_locally(message: &Protocol) -> bool {
+    if ![PARAM_REQUEST_LIST_DATA::ID, PARAM_REQUEST_READ_DATA::ID].contains(&message.message_id()) {
+        return false;
+    }
+
+    let Some((_, request)) = decode(message).await else {
+        return false;
+    };
+
+    cached_request(&PARAMETERS.components.read().await, &request).is_some()
+}
+
+/// Starts caching the parameters of every component in the hub
+pub(crate) fn init() {
+    lazy_static::initialize(&PARAMETERS);
+}
+
+/// Every parameter of a component, downloading them when they are not cached yet.
+/// A partial set is returned when the download doesn't complete in time.
+#[instrument(level = "debug")]
+pub async fn parameters(system_id: u8, component_id: u8) -> Result<ParameterSet, VehicleError> {
+    let key = (system_id, component_id);
+    let mut updates = PARAMETERS.updates.subscribe();
+
+    let mut received = match PARAMETERS.components.write().await.get_mut(&key) {
+        Some(component) if component.is_complete() => return Ok(component.set()),
+        Some(component) => {
+            // Lets the observer task request the missing ones again
+            component.missing_rounds = 0;
+            true
+        }
+        None => false,
+    };
+
+    let hub_sender = hub_sender().await?;
+    let deadline = Instant::now() + LIST_TIMEOUT;
+    let mut attempts = 0;
+
+    loop {
+        if !received {
+            if attempts > RETRIES {
+                break;
+            }
+
+            let message = MavMessage::PARAM_REQUEST_LIST(PARAM_REQUEST_LIST_DATA {
+                target_system: system_id,
+                target_component: component_id,
+            });
+            send(&hub_sender, &message)?;
+            attempts += 1;
+        }
+
+        let update_deadline = deadline.min(Instant::now() + READ_TIMEOUT);
+        match wait_update(&mut updates, system_id, component_id, update_deadline).await {
+            Some(_) => {
+                received = true;
+
+                if let Some(component) = PARAMETERS.components.read().await.get(&key) {
+                    if component.is_complete() {
+                        return Ok(component.set());
+                    }
+                }
+            }
+            None if Instant::now() < deadline => continue,
+            None => break,
+        }
+    }
+
+    match PARAMETERS.components.read().await.get(&key) {
+        Some(component) => Ok(component.set()),
+        None => Err(VehicleError::Timeout(format!(
+            "No parameters from {system_id}:{component_id}"
+        ))),
+    }
+}
+
+/// A single parameter, read from the component when it is not cached
+#[instrument(level = "debug")]
+pub async fn parameter(
+    system_id: u8,
+    component_id: u8,
+    name: &str,
+) -> Result<Parameter, VehicleError> {
+    let param_id = param_id(name)?;
+
+    if let Some(component) = PARAMETERS
+        .components
+        .read()
+        .await
+        .get(&(system_id, component_id))
+    {
+        if let Some(parameter) = component.parameters.get(name) {
+            return Ok(parameter.clone());
+        }
+
+        if component.is_complete() {
+            return Err(VehicleError::NotFound(format!(
+                "Parameter {name:?} on {system_id}:{component_id}"
+            )));
+        }
+    }
+
+    let hub_sender = hub_sender().await?;
+    let mut updates = PARAMETERS.updates.subscribe();
+
+    for _ in 0..=RETRIES {
+        let message = MavMessage::PARAM_REQUEST_READ(PARAM_REQUEST_READ_DATA {
+            param_index: -1,
+            target_system: system_id,
+            target_component: component_id,
+            param_id,
+        });
+        send(&hub_sender, &message)?;
+
+        let deadline = Instant::now() + READ_TIMEOUT;
+        if let Some(parameter) =
+            wait_parameter(&mut updates, system_id, component_id, name, deadline).await
+        {
+            return Ok(parameter);
+        }
+    }
+
+    Err(VehicleError::Timeout(format!(
+        "Parameter {name:?} not received from {system_id}:{component_id}"
+    )))
+}
+
+/// Sets a parameter and checks that the component answers with the new value
+#[instrument(level = "debug")]
+pub async fn set_parameter(
+    system_id: u8,
+    component_id: u8,
+    name: &str,
+    value: f32,
+) -> Result<Parameter, VehicleError> {
+    // The type is needed by the component to interpret the value
+    let current = parameter(system_id, component_id, name).await?;
+    let param_id = param_id(name)?;
+
+    let hub_sender = hub_sender().await?;
+    let mut updates = PARAMETERS.updates.subscribe();
+
+    for _ in 0..=RETRIES {
+        let message = MavMessage::PARAM_SET(PARAM_SET_DATA {
+            param_value: value,
+            target_system: system_id,
+            target_component: component_id,
+            param_id,
+            param_type: current.param_type,
+        });
+        send(&hub_sender, &message)?;
+
+        let deadline = Instant::now() + READ_TIMEOUT;
+        let Some(parameter) =
+            wait_parameter(&mut updates, system_id, component_id, name, deadline).await
+        else {
+            continue;
+        };
+
+        if parameter.value != value {
+            return Err(VehicleError::Rejected(format!(
+                "Parameter {name:?} is {} instead of {value}",
+                parameter.value
+            )));
+        }
+
+        return Ok(parameter);
+    }
+
+    Err(VehicleError::Timeout(format!(
+        "Parameter {name:?} not confirmed by {system_id}:{component_id}"
+    )))
+}
+
+async fn wait_update(
+    updates: &mut broadcast::Receiver<ParameterUpdate>,
+    system_id: u8,
+    component_id: u8,
+    deadline: Instant,
+) -> Option<Parameter> {
+    tokio::time::timeout_at(deadline, async {
+        loop {
+            match updates.recv().await {
+                Ok(update)
+                    if update.system_id == system_id && update.component_id == component_id =>
+                {
+                    return Some(update.parameter)
+                }
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+async fn wait_parameter(
+    updates: &mut broadcast::Receiver<ParameterUpdate>,
+    system_id: u8,
+    component_id: u8,
+    name: &str,
+    deadline: Instant,
+) -> Option<Parameter> {
+    loop {
+        let parameter = wait_update(updates, system_id, component_id, deadline).await?;
+        if parameter.name == name {
+            return Some(parameter);
+        }
+    }
+}
+
+fn param_id(name: &str) -> Result<[u8; 16], VehicleError> {
+    let bytes = name
+        .chars()
+        .map(u8::try_from)
+        .collect::<Result<Vec<u8>, _>>()
+        .ok()
+        .filter(|bytes| !bytes.is_empty() && bytes.len() <= 16)
+        .ok_or_else(|| VehicleError::Invalid(format!("Invalid parameter name {name:?}")))?;
+
+    let mut param_id = [0; 16];
+    param_id[..bytes.len()].copy_from_slice(&bytes);
+
+    Ok(param_id)
+}
+
+fn param_name(param_id: &[u8; 16]) -> String {
+    param_id
+        .iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| byte as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parameter(name: &str, index: u16, value: f32) -> Parameter {
+        Parameter {
+            name: name.to_string(),
+            value,
+            param_type: MavParamType::MAV_PARAM_TYPE_REAL32,
+            index,
+        }
+    }
+
+    #[test]
+    fn test_param_id() {
+        let param_id = param_id("SYSID_THISMAV").unwrap();
+        assert_eq!(param_name(&param_id), "SYSID_THISMAV");
+        assert_eq!(
+            param_name(&super::param_id("BATT_MONITOR_ABC").unwrap()),
+            "BATT_MONITOR_ABC"
+        );
+        assert!(super::param_id("A_VERY_LONG_PARAMETER").is_err());
+        assert!(super::param_id("").is_err());
+    }
+
+    #[test]
+    fn test_component_parameters() {
+        let mut component = ComponentParameters::new();
+        component.update(parameter("A", 0, 1.0), 3);
+        component.update(parameter("C", 2, 3.0), 3);
+
+        assert!(!component.is_complete());
+        assert_eq!(component.missing().collect::<Vec<_>>(), vec![1]);
+
+        component.update(parameter("B", 1, 2.0), 3);
+        assert!(component.is_complete());
+
+        let set = component.set();
+        assert_eq!(
+            set.parameters
+                .iter()
+                .map(|parameter| parameter.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["A", "B", "C"]
+        );
+
+        // Not part of the list, but still cached
+        component.update(parameter("STAT_RUNTIME", u16::MAX, 10.0), 3);
+        assert!(component.is_complete());
+        assert!(component.parameters.contains_key("STAT_RUNTIME"));
+
+        component.forget("B");
+        assert_eq!(component.missing().collect::<Vec<_>>(), vec![1]);
+        component.update(parameter("B", 1, 2.5), 3);
+
+        // A new parameter shifts the indexes, so the list is downloaded again
+        component.update(parameter("A", 0, 1.0), 4);
+        assert!(!component.is_complete());
+        assert_eq!(component.missing().collect::<Vec<_>>(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_cached_request() {
+        let mut autopilot = ComponentParameters::new();
+        autopilot.update(parameter("A", 0, 1.0), 2);
+        autopilot.update(parameter("B", 1, 2.0), 2);
+        let mut gimbal = ComponentParameters::new();
+        gimbal.update(parameter("C", 0, 3.0), 2);
+
+        let mut components = HashMap::new();
+        components.insert((1, 1), autopilot);
+        components.insert((1, 154), gimbal);
+
+        let list = |target_system, target_component| {
+            MavMessage::PARAM_REQUEST_LIST(PARAM_REQUEST_LIST_DATA {
+                target_system,
+                target_component,
+            })
+        };
+        let read = |target_component, param_index, name: &str| {
+            MavMessage::PARAM_REQUEST_READ(PARAM_REQUEST_READ_DATA {
+                param_index,
+                target_system: 1,
+                target_component,
+                param_id: param_id(name).unwrap(),
+            })
+        };
+
+        assert_eq!(
+            cached_request(&components, &list(1, 1)),
+            Some(CachedRequest::List(vec![(1, 1)]))
+        );
+        assert_eq!(
+            cached_request(&components, &list(1, 154)),
+            None,
+            "Incomplete"
+        );
+        assert_eq!(
+            cached_request(&components, &list(1, 0)),
+            None,
+            "Not every component is complete"
+        );
+        assert_eq!(cached_request(&components, &list(2, 1)), None, "Unknown");
+
+        assert_eq!(
+            cached_request(&components, &read(1, -1, "B")),
+            Some(CachedRequest::Read((1, 1), "B".to_string()))
+        );
+        assert_eq!(
+            cached_request(&components, &read(1, 0, "")),
+            Some(CachedRequest::Read((1, 1), "A".to_string()))
+        );
+        assert_eq!(cached_request(&components, &read(1, -1, "Z")), None);
+
+        components
+            .get_mut(&(1, 154))
+            .unwrap()
+            .update(parameter("D", 1, 4.0), 2);
+        assert_eq!(
+            cached_request(&components, &list(1, 0)),
+            Some(CachedRequest::List(vec![(1, 1), (1, 154)]))
+        );
+    }
+}
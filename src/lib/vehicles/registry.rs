@@ -0,0 +1,532 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use lazy_static::lazy_static;
+use mavlink::{
+    ardupilotmega::{MavAutopilot, MavMessage, MavType, HEARTBEAT_DATA},
+    MessageData,
+};
+use serde::Serialize;
+use tokio::{
+    sync::{broadcast, RwLock},
+    time::{Duration, Instant},
+};
+use tracing::*;
+
+use crate::{
+    protocol::Protocol,
+    stats::driver::DriverUuid,
+    vehicles::{decode, hub_sender, serialize_enum_name, ORIGIN},
+};
+
+/// Components are offline after this long without a heartbeat
+const OFFLINE_TIMEOUT: Duration = Duration::from_secs(5);
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Sequences going back this many times within the offline timeout come from more than one sender
+const INTERLEAVED_JUMPS: usize = 3;
+
+lazy_static! {
+    static ref REGISTRY: Registry = Registry::new();
+    static ref EVENTS: broadcast::Sender<RegistryEvent> = broadcast::channel(100).0;
+}
+
+struct Registry {
+    state: Arc<RwLock<RegistryState>>,
+    _task: Arc<Mutex<tokio::task::JoinHandle<()>>>,
+}
+
+/// A system known from the heartbeats of its components
+#[derive(Debug, Clone, Serialize)]
+pub struct VehicleSystem {
+    pub system_id: u8,
+    /// Whether any of its components is online
+    pub online: bool,
+    /// Whether any of its components has its IDs shared with another one
+    pub conflict: bool,
+    pub components: Vec<VehicleComponent>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VehicleComponent {
+    pub component_id: u8,
+    #[serde(serialize_with = "serialize_enum_name")]
+    pub autopilot: MavAutopilot,
+    #[serde(rename = "type", serialize_with = "serialize_enum_name")]
+    pub mav_type: MavType,
+    /// Origins its messages recently came through, which identify the driver links
+    pub origins: BTreeSet<String>,
+    /// Drivers its heartbeats came through
+    pub drivers: BTreeSet<DriverUuid>,
+    pub first_seen_us: u64,
+    pub last_seen_us: u64,
+    pub online: bool,
+    /// Whether another component sends with the same IDs, like a vehicle with the same system ID.
+    /// It is found from heartbeats of another autopilot or vehicle type, or from sequences
+    /// interleaved on the same origin.
+    pub conflict: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistryEventKind {
+    /// First heartbeat of the component
+    Added,
+    /// Heartbeats received again after being offline
+    Online,
+    Offline,
+    /// Its autopilot, type, origins or drivers changed, or its conflict is over
+    Changed,
+    Conflict,
+}
+
+/// A change in the registry, with the component as it is after the change
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RegistryEvent {
+    pub event: RegistryEventKind,
+    pub system_id: u8,
+    pub component: VehicleComponent,
+}
+
+/// Where a message with the IDs of a component came from
+#[derive(Debug, Clone, Copy)]
+struct Source<'a> {
+    origin: &'a str,
+    driver: Option<DriverUuid>,
+    sequence: u8,
+}
+
+/// The recent messages of a component on one origin
+#[derive(Debug)]
+struct OriginActivity {
+    last_seen: Instant,
+    last_sequence: u8,
+    /// When the sequence went back, which a single sender only does when it restarts
+    backward_jumps: Vec<Instant>,
+}
+
+impl OriginActivity {
+    fn new(sequence: u8, now: Instant) -> Self {
+        Self {
+            last_seen: now,
+            last_sequence: sequence,
+            backward_jumps: Vec::new(),
+        }
+    }
+
+    fn update(&mut self, sequence: u8, now: Instant) {
+        self.backward_jumps
+            .retain(|instant| now.duration_since(*instant) < OFFLINE_TIMEOUT);
+
+        // Lost messages make it skip forward, but only another sender makes it go back
+        if sequence.wrapping_sub(self.last_sequence) > u8::MAX / 2 {
+            self.backward_jumps.push(now);
+        }
+
+        self.last_sequence = sequence;
+        self.last_seen = now;
+    }
+
+    fn is_interleaved(&self) -> bool {
+        self.backward_jumps.len() >= INTERLEAVED_JUMPS
+    }
+}
+
+#[derive(Debug)]
+struct ComponentEntry {
+    component: VehicleComponent,
+    last_heartbeat: Instant,
+    /// The autopilot and vehicle types recently heartbeating with the IDs of this component
+    identities: Vec<(MavAutopilot, MavType, Instant)>,
+    /// By origin
+    activity: HashMap<String, OriginActivity>,
+}
+
+impl ComponentEntry {
+    fn update(&mut self, source: Source, now: Instant) {
+        self.activity
+            .retain(|_, activity| now.duration_since(activity.last_seen) < OFFLINE_TIMEOUT);
+
+        self.activity
+            .entry(source.origin.to_string())
+            .and_modify(|activity| activity.update(source.sequence, now))
+            .or_insert_with(|| OriginActivity::new(source.sequence, now));
+    }
+
+    /// Whether more than one component is sending with these IDs.
+    /// Links can drop or delay messages, so only the sequences within the same origin are compared.
+    fn is_shared(&self) -> bool {
+        self.identities.len() > 1 || self.activity.values().any(OriginActivity::is_interleaved)
+    }
+
+    /// The origins that are still active
+    fn origins(&self) -> BTreeSet<String> {
+        self.activity.keys().cloned().collect()
+    }
+}
+
+#[derive(Debug, Default)]
+struct RegistryState {
+    /// By system and component IDs
+    components: BTreeMap<(u8, u8), ComponentEntry>,
+}
+
+impl RegistryState {
+    fn heartbeat(
+        &mut self,
+        (system_id, component_id): (u8, u8),
+        heartbeat: &HEARTBEAT_DATA,
+        source: Source,
+        timestamp_us: u64,
+        now: Instant,
+    ) -> Vec<RegistryEvent> {
+        let origin = source.origin;
+        let identity = (heartbeat.autopilot, heartbeat.mavtype);
+        let event = |kind: RegistryEventKind, component: &VehicleComponent| RegistryEvent {
+            event: kind,
+            system_id,
+            component: component.clone(),
+        };
+
+        let Some(entry) = self.components.get_mut(&(system_id, component_id)) else {
+            let component = VehicleComponent {
+                component_id,
+                autopilot: identity.0,
+                mav_type: identity.1,
+                origins: BTreeSet::from([origin.to_string()]),
+                drivers: source.driver.into_iter().collect(),
+                first_seen_us: timestamp_us,
+                last_seen_us: timestamp_us,
+                online: true,
+                conflict: false,
+            };
+            info!(
+                "Component {system_id}:{component_id} found, {:?} {:?} on {origin}",
+                identity.0, identity.1
+            );
+            let added = event(RegistryEventKind::Added, &component);
+            let mut entry = ComponentEntry {
+                component,
+                last_heartbeat: now,
+                identities: vec![(identity.0, identity.1, now)],
+                activity: HashMap::new(),
+            };
+            entry.update(source, now);
+            self.components.insert((system_id, component_id), entry);
+            return vec![added];
+        };
+
+        let mut events = Vec::new();
+        entry.update(source, now);
+        entry.identities.retain(|(autopilot, mav_type, last_seen)| {
+            now.duration_since(*last_seen) < OFFLINE_TIMEOUT && (*autopilot, *mav_type) != identity
+        });
+        entry.identities.push((identity.0, identity.1, now));
+        let shared = entry.is_shared();
+        // Only the active origins are kept, so links that come and go don't pile up
+        let origins = entry.origins();
+
+        let component = &mut entry.component;
+        entry.last_heartbeat = now;
+        component.last_seen_us = timestamp_us;
+
+        if !component.online {
+            component.online = true;
+            info!("Component {system_id}:{component_id} is online");
+            events.push(event(RegistryEventKind::Online, component));
+        }
+
+        let mut changed = component.origins != origins;
+        component.origins = origins;
+        if let Some(driver) = source.driver {
+            changed |= component.drivers.insert(driver);
+        }
+        if shared {
+            if !component.conflict {
+                component.conflict = true;
+                warn!(
+                    "IDs {system_id}:{component_id} are shared by more than one component: {:?} on {:?}",
+                    entry.identities,
+                    entry.activity.keys()
+                );
+                events.push(event(RegistryEventKind::Conflict, component));
+            }
+        } else {
+            // The identity is only changed once the other one is gone, so conflicts don't flip it
+            changed |= component.conflict || (component.autopilot, component.mav_type) != identity;
+            component.conflict = false;
+            component.autopilot = identity.0;
+            component.mav_type = identity.1;
+        }
+
+        if changed {
+            events.push(event(RegistryEventKind::Changed, component));
+        }
+
+        events
+    }
+
+    /// Follows the sequence of the other messages of a known component
+    fn message(&mut self, key: (u8, u8), source: Source, now: Instant) {
+        if let Some(entry) = self.components.get_mut(&key) {
+            entry.update(source, now);
+        }
+    }
+
+    fn check_offline(&mut self, now: Instant) -> Vec<RegistryEvent> {
+        self.components
+            .iter_mut()
+            .filter(|(_, entry)| {
+                entry.component.online && now.duration_since(entry.last_heartbeat) > OFFLINE_TIMEOUT
+            })
+            .map(|(&(system_id, component_id), entry)| {
+                info!("Component {system_id}:{component_id} is offline");
+                entry.component.online = false;
+                RegistryEvent {
+                    event: RegistryEventKind::Offline,
+                    system_id,
+                    component: entry.component.clone(),
+                }
+            })
+            .collect()
+    }
+
+    fn systems(&self) -> Vec<VehicleSystem> {
+        let mut systems: Vec<VehicleSystem> = Vec::new();
+
+        // Sorted by system and component IDs
+        for (&(system_id, _), entry) in &self.components {
+            let component = entry.component.clone();
+            match systems.last_mut() {
+                Some(system) if system.system_id == system_id => {
+                    system.online |= component.online;
+                    system.conflict |= component.conflict;
+                    system.components.push(component);
+                }
+                _ => systems.push(VehicleSystem {
+                    system_id,
+                    online: component.online,
+                    conflict: component.conflict,
+                    components: vec![component],
+                }),
+            }
+        }
+
+        systems
+    }
+}
+
+impl Registry {
+    fn new() -> Self {
+        let state = Arc::new(RwLock::new(RegistryState::default()));
+
+        let _task = Arc::new(Mutex::new(tokio::spawn(Self::observe_task(state.clone()))));
+
+        Self { state, _task }
+    }
+
+    /// Registers the components from their heartbeats, and marks the silent ones as offline
+    async fn observe_task(state: Arc<RwLock<RegistryState>>) {
+        let hub_sender = match hub_sender().await {
+            Ok(hub_sender) => hub_sender,
+            Err(error) => {
+                error!("Vehicles won't be registered: {error}");
+                return;
+            }
+        };
+        let mut hub_receiver = hub_sender.subscribe();
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+
+        loop {
+            let events = tokio::select! {
+                result = hub_receiver.recv() => match result {
+                    Ok(message) => Self::handle_message(&state, &message).await,
+                    Err(broadcast::error::RecvError::Lagged(samples)) => {
+                        warn!("Channel lagged by {samples} messages.");
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = interval.tick() => state.write().await.check_offline(Instant::now()),
+            };
+
+            for event in events {
+                let _ = EVENTS.send(event);
+            }
+        }
+
+        debug!("Registry observer task stopped!");
+    }
+
+    async fn handle_message(
+        state: &RwLock<RegistryState>,
+        message: &Protocol,
+    ) -> Vec<RegistryEvent> {
+        // Messages created by the server itself, which has no origin for its heartbeats
+        if message.origin.is_empty() || message.origin == ORIGIN {
+            return Vec::new();
+        }
+
+        let source = Source {
+            origin: &message.origin,
+            driver: message.driver(),
+            sequence: *message.sequence(),
+        };
+
+        if message.message_id() != HEARTBEAT_DATA::ID {
+            let key = (*message.system_id(), *message.component_id());
+            state.write().await.message(key, source, Instant::now());
+            return Vec::new();
+        }
+
+        let Some((header, MavMessage::HEARTBEAT(heartbeat))) = decode(message).await else {
+            return Vec::new();
+        };
+
+        state.write().await.heartbeat(
+            (header.system_id, header.component_id),
+            &heartbeat,
+            source,
+            message.timestamp,
+            Instant::now(),
+        )
+    }
+}
+
+/// Every system seen, sorted by ID
+pub async fn systems() -> Vec<VehicleSystem> {
+    REGISTRY.state.read().await.systems()
+}
+
+/// The changes in the registry, as they happen
+pub fn events() -> broadcast::Receiver<RegistryEvent> {
+    EVENTS.subscribe()
+}
+
+/// Starts registering the vehicles from their heartbeats
+pub(crate) fn init() {
+    lazy_static::initialize(&REGISTRY);
+}
+
+#[cfg(test)]
+mod tests {
+    use mavlink::ardupilotmega::{MavModeFlag, MavState};
+
+    use super::*;
+
+    fn heartbeat(autopilot: MavAutopilot, mavtype: MavType) -> HEARTBEAT_DATA {
+        HEARTBEAT_DATA {
+            custom_mode: 0,
+            mavtype,
+            autopilot,
+            base_mode: MavModeFlag::empty(),
+            system_status: MavState::MAV_STATE_ACTIVE,
+            mavlink_version: 3,
+        }
+    }
+
+    fn source(origin: &str, sequence: u8) -> Source<'_> {
+        Source {
+            origin,
+            driver: Some(DriverUuid::nil()),
+            sequence,
+        }
+    }
+
+    fn kinds(events: Vec<RegistryEvent>) -> Vec<RegistryEventKind> {
+        events.into_iter().map(|event| event.event).collect()
+    }
+
+    #[test]
+    fn test_heartbeats() {
+        let mut state = RegistryState::default();
+        let start = Instant::now();
+        let ardupilot = heartbeat(
+            MavAutopilot::MAV_AUTOPILOT_ARDUPILOTMEGA,
+            MavType::MAV_TYPE_QUADROTOR,
+        );
+        let px4 = heartbeat(
+            MavAutopilot::MAV_AUTOPILOT_PX4,
+            MavType::MAV_TYPE_FIXED_WING,
+        );
+
+        let events = state.heartbeat((1, 1), &ardupilot, source("/dev/ttyACM0", 10), 1, start);
+        assert_eq!(kinds(events), vec![RegistryEventKind::Added]);
+
+        let events = state.heartbeat((1, 1), &ardupilot, source("/dev/ttyACM0", 11), 2, start);
+        assert!(events.is_empty());
+
+        let events = state.heartbeat(
+            (1, 1),
+            &ardupilot,
+            source("192.168.2.1:14550", 11),
+            3,
+            start,
+        );
+        assert_eq!(kinds(events), vec![RegistryEventKind::Changed]);
+
+        let events = state.heartbeat((1, 1), &px4, source("192.168.2.1:14550", 12), 4, start);
+        assert_eq!(kinds(events), vec![RegistryEventKind::Conflict]);
+
+        // Only the first one keeps heartbeating
+        let later = start + OFFLINE_TIMEOUT + Duration::from_secs(1);
+        let events = state.heartbeat((1, 1), &ardupilot, source("/dev/ttyACM0", 13), 5, later);
+        assert_eq!(kinds(events), vec![RegistryEventKind::Changed]);
+
+        let events = state.check_offline(later + OFFLINE_TIMEOUT + Duration::from_secs(1));
+        assert_eq!(kinds(events), vec![RegistryEventKind::Offline]);
+
+        state.heartbeat((1, 191), &px4, source("/dev/ttyACM0", 14), 6, later);
+        let systems = state.systems();
+        assert_eq!(systems.len(), 1);
+        assert!(systems[0].online);
+        assert!(!systems[0].conflict);
+
+        let component = &systems[0].components[0];
+        assert_eq!(component.component_id, 1);
+        assert!(!component.online);
+        assert_eq!(component.first_seen_us, 1);
+        assert_eq!(component.last_seen_us, 5);
+        // The other origin is gone
+        assert_eq!(component.origins.len(), 1);
+        assert_eq!(component.drivers.len(), 1);
+    }
+
+    #[test]
+    fn test_shared_ids() {
+        let mut state = RegistryState::default();
+        let now = Instant::now();
+        let ardupilot = heartbeat(
+            MavAutopilot::MAV_AUTOPILOT_ARDUPILOTMEGA,
+            MavType::MAV_TYPE_QUADROTOR,
+        );
+
+        // The same vehicle through two links is a single component, even when one of them drops
+        // messages and their sequences don't match
+        for sequence in 0..6 {
+            let events =
+                state.heartbeat((1, 1), &ardupilot, source("/dev/ttyACM0", sequence), 0, now);
+            assert!(!kinds(events).contains(&RegistryEventKind::Conflict));
+
+            if sequence % 2 == 0 {
+                let events = state.heartbeat(
+                    (1, 1),
+                    &ardupilot,
+                    source("192.168.2.1:14550", sequence),
+                    0,
+                    now,
+                );
+                assert!(!kinds(events).contains(&RegistryEventKind::Conflict));
+            }
+        }
+
+        // Two identical vehicles on the same link interleave their sequences
+        state.heartbeat((2, 1), &ardupilot, source("/dev/ttyACM0", 10), 0, now);
+        for sequence in [200, 11, 201, 12, 202] {
+            state.message((2, 1), source("/dev/ttyACM0", sequence), now);
+        }
+        let events = state.heartbeat((2, 1), &ardupilot, source("/dev/ttyACM0", 13), 0, now);
+        assert_eq!(kinds(events), vec![RegistryEventKind::Conflict]);
+    }
+}
@@ -0,0 +1,190 @@
+use std::{collections::HashMap, sync::Arc};
+
+use lazy_static::lazy_static;
+use mavlink::{
+    ardupilotmega::{MavMessage, SerialControlDev, SerialControlFlag, SERIAL_CONTROL_DATA},
+    MessageData,
+};
+use tokio::{
+    sync::{broadcast, mpsc, Mutex},
+    time::Duration,
+};
+use tracing::*;
+
+use crate::{
+    protocol::Protocol,
+    vehicles::{decode, hub_sender, send, VehicleError, ORIGIN},
+};
+
+/// The output is only sent by the vehicle as an answer, so it is polled while the input is quiet
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const DATA_SIZE: usize = 70;
+
+lazy_static! {
+    /// The shell is exclusive, so a single client can use it at a time
+    static ref SHELLS: Mutex<HashMap<u8, Arc<Mutex<()>>>> = Mutex::new(HashMap::new());
+}
+
+/// Splits the input in `SERIAL_CONTROL` messages, an empty input being a poll for the output
+fn messages(
+    (system_id, component_id): (u8, u8),
+    data: &[u8],
+    flags: SerialControlFlag,
+) -> Vec<MavMessage> {
+    let message = |chunk: &[u8]| {
+        let mut data = [0; DATA_SIZE];
+        data[..chunk.len()].copy_from_slice(chunk);
+
+        MavMessage::SERIAL_CONTROL(SERIAL_CONTROL_DATA {
+            baudrate: 0,
+            timeout: 0,
+            device: SerialControlDev::SERIAL_CONTROL_DEV_SHELL,
+            flags,
+            count: chunk.len() as u8,
+            data,
+            target_system: system_id,
+            target_component: component_id,
+        })
+    };
+
+    if data.is_empty() {
+        return vec![message(&[])];
+    }
+
+    data.chunks(DATA_SIZE).map(message).collect()
+}
+
+fn send_all(
+    hub_sender: &broadcast::Sender<Arc<Protocol>>,
+    target: (u8, u8),
+    data: &[u8],
+    flags: SerialControlFlag,
+) -> Result<(), VehicleError> {
+    messages(target, data, flags)
+        .iter()
+        .try_for_each(|message| send(hub_sender, message))
+}
+
+/// The shell output in a message from the vehicle, if it has any
+async fn shell_output(message: &Protocol, (system_id, component_id): (u8, u8)) -> Option<Vec<u8>> {
+    if message.origin == ORIGIN
+        || *message.system_id() != system_id
+        || *message.component_id() != component_id
+        || message.message_id() != SERIAL_CONTROL_DATA::ID
+    {
+        return None;
+    }
+
+    match decode(message).await? {
+        (_, MavMessage::SERIAL_CONTROL(reply))
+            if reply.device == SerialControlDev::SERIAL_CONTROL_DEV_SHELL
+                && reply
+                    .flags
+                    .contains(SerialControlFlag::SERIAL_CONTROL_FLAG_REPLY)
+                && reply.count > 0 =>
+        {
+            Some(reply.data[..(reply.count as usize).min(DATA_SIZE)].to_vec())
+        }
+        _ => None,
+    }
+}
+
+/// Runs the shell of a vehicle, sending it the input and forwarding its output, until the input is closed
+#[instrument(level = "debug", skip(input, output))]
+pub async fn run(
+    system_id: u8,
+    component_id: u8,
+    mut input: mpsc::Receiver<Vec<u8>>,
+    output: mpsc::Sender<Vec<u8>>,
+) -> Result<(), VehicleError> {
+    let lock = SHELLS.lock().await.entry(system_id).or_default().clone();
+    let Ok(_shell) = lock.try_lock_owned() else {
+        return Err(VehicleError::Rejected(format!(
+            "The shell of {system_id} is already open"
+        )));
+    };
+
+    let target = (system_id, component_id);
+    let hub_sender = hub_sender().await?;
+    let mut hub_receiver = hub_sender.subscribe();
+
+    let flags = SerialControlFlag::SERIAL_CONTROL_FLAG_EXCLUSIVE
+        | SerialControlFlag::SERIAL_CONTROL_FLAG_RESPOND
+        | SerialControlFlag::SERIAL_CONTROL_FLAG_MULTI;
+
+    // Gets the prompt printed
+    send_all(&hub_sender, target, b"\n", flags)?;
+    let mut poll = tokio::time::interval(POLL_INTERVAL);
+
+    let result = loop {
+        tokio::select! {
+            data = input.recv() => match data {
+                Some(data) => {
+                    if let Err(error) = send_all(&hub_sender, target, &data, flags) {
+                        break Err(error);
+                    }
+                    poll.reset();
+                }
+                None => break Ok(()),
+            },
+            result = hub_receiver.recv() => match result {
+                Ok(message) => {
+                    let Some(data) = shell_output(&message, target).await else {
+                        continue;
+                    };
+                    if output.send(data).await.is_err() {
+                        break Ok(());
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(samples)) => {
+                    warn!("Channel lagged by {samples} messages.");
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    break Err(VehicleError::Unreachable("Hub closed".to_string()));
+                }
+            },
+            _ = poll.tick() => {
+                if let Err(error) = send_all(&hub_sender, target, &[], flags) {
+                    break Err(error);
+                }
+            }
+        }
+    };
+
+    // Gives the shell back to the vehicle console
+    if let Err(error) = send_all(&hub_sender, target, &[], SerialControlFlag::empty()) {
+        debug!("Failed releasing the shell of {system_id}: {error}");
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages() {
+        let flags = SerialControlFlag::SERIAL_CONTROL_FLAG_RESPOND;
+
+        let counts = |data: &[u8]| {
+            messages((1, 1), data, flags)
+                .into_iter()
+                .map(|message| match message {
+                    MavMessage::SERIAL_CONTROL(message) => message.count,
+                    _ => panic!("Expected a SERIAL_CONTROL"),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(counts(b""), vec![0]);
+        assert_eq!(counts(b"ls\n"), vec![3]);
+        assert_eq!(counts(&[b'a'; 150]), vec![70, 70, 10]);
+
+        let MavMessage::SERIAL_CONTROL(message) = &messages((1, 1), b"top\n", flags)[0] else {
+            panic!("Expected a SERIAL_CONTROL");
+        };
+        assert_eq!(&message.data[..4], b"top\n");
+        assert_eq!(message.device, SerialControlDev::SERIAL_CONTROL_DEV_SHELL);
+    }
+}
@@ -0,0 +1,355 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use lazy_static::lazy_static;
+use mavlink::{
+    ardupilotmega::{
+        GpsFixType, MavAutopilot, MavMessage, MavModeFlag, MavState, MavType,
+        GLOBAL_POSITION_INT_DATA, GPS_RAW_INT_DATA, HEARTBEAT_DATA, SYS_STATUS_DATA,
+    },
+    MessageData,
+};
+use serde::Serialize;
+use tokio::sync::{broadcast, RwLock};
+use tracing::*;
+
+use crate::{
+    cli,
+    protocol::Protocol,
+    vehicles::{decode, hub_sender, logs, modes, serialize_enum_name, ORIGIN},
+};
+
+const MESSAGE_IDS: [u32; 4] = [
+    HEARTBEAT_DATA::ID,
+    SYS_STATUS_DATA::ID,
+    GPS_RAW_INT_DATA::ID,
+    GLOBAL_POSITION_INT_DATA::ID,
+];
+
+lazy_static! {
+    static ref STATES: VehicleStates = VehicleStates::new();
+}
+
+struct VehicleStates {
+    components: Arc<RwLock<HashMap<(u8, u8), ComponentMessages>>>,
+    _task: Arc<Mutex<tokio::task::JoinHandle<()>>>,
+}
+
+/// The last messages of a component that make its state
+#[derive(Debug, Default)]
+struct ComponentMessages {
+    heartbeat: Option<HEARTBEAT_DATA>,
+    last_heartbeat_us: u64,
+    sys_status: Option<SYS_STATUS_DATA>,
+    gps: Option<GPS_RAW_INT_DATA>,
+    position: Option<GLOBAL_POSITION_INT_DATA>,
+}
+
+/// A summary of a vehicle, from its `HEARTBEAT`, `SYS_STATUS`, `GPS_RAW_INT` and `GLOBAL_POSITION_INT`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VehicleState {
+    pub system_id: u8,
+    pub component_id: u8,
+    #[serde(serialize_with = "serialize_enum_name")]
+    pub autopilot: MavAutopilot,
+    #[serde(rename = "type", serialize_with = "serialize_enum_name")]
+    pub mav_type: MavType,
+    /// The flight mode name, for ArduPilot and PX4
+    pub mode: Option<String>,
+    pub custom_mode: u32,
+    pub armed: bool,
+    #[serde(serialize_with = "serialize_enum_name")]
+    pub system_status: MavState,
+    pub battery: Option<Battery>,
+    pub gps: Option<Gps>,
+    pub position: Option<Position>,
+    pub last_heartbeat_us: u64,
+}
+
+/// Unknown values are null
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Battery {
+    /// In volts
+    pub voltage: Option<f32>,
+    /// In amperes
+    pub current: Option<f32>,
+    /// In percent
+    pub remaining: Option<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Gps {
+    #[serde(serialize_with = "serialize_enum_name")]
+    pub fix_type: GpsFixType,
+    pub satellites: Option<u8>,
+    pub hdop: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Position {
+    /// In degrees
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Above the mean sea level, in meters
+    pub altitude: f32,
+    /// Above the home position, in meters
+    pub relative_altitude: f32,
+    /// In degrees, null when unknown
+    pub heading: Option<f32>,
+}
+
+impl From<&SYS_STATUS_DATA> for Battery {
+    fn from(status: &SYS_STATUS_DATA) -> Self {
+        Self {
+            voltage: (status.voltage_battery != u16::MAX)
+                .then_some(status.voltage_battery as f32 / 1000.0),
+            current: (status.current_battery >= 0).then_some(status.current_battery as f32 / 100.0),
+            remaining: u8::try_from(status.battery_remaining).ok(),
+        }
+    }
+}
+
+impl From<&GPS_RAW_INT_DATA> for Gps {
+    fn from(gps: &GPS_RAW_INT_DATA) -> Self {
+        Self {
+            fix_type: gps.fix_type,
+            satellites: (gps.satellites_visible != u8::MAX).then_some(gps.satellites_visible),
+            hdop: (gps.eph != u16::MAX).then_some(gps.eph as f32 / 100.0),
+        }
+    }
+}
+
+impl From<&GLOBAL_POSITION_INT_DATA> for Position {
+    fn from(position: &GLOBAL_POSITION_INT_DATA) -> Self {
+        Self {
+            latitude: position.lat as f64 / 1e7,
+            longitude: position.lon as f64 / 1e7,
+            altitude: position.alt as f32 / 1000.0,
+            relative_altitude: position.relative_alt as f32 / 1000.0,
+            heading: (position.hdg != u16::MAX).then_some(position.hdg as f32 / 100.0),
+        }
+    }
+}
+
+impl ComponentMessages {
+    fn update(&mut self, message: MavMessage, timestamp_us: u64) {
+        match message {
+            MavMessage::HEARTBEAT(heartbeat) => {
+                self.heartbeat = Some(heartbeat);
+                self.last_heartbeat_us = timestamp_us;
+            }
+            MavMessage::SYS_STATUS(sys_status) => self.sys_status = Some(sys_status),
+            MavMessage::GPS_RAW_INT(gps) => self.gps = Some(gps),
+            MavMessage::GLOBAL_POSITION_INT(position) => self.position = Some(position),
+            _ => {}
+        }
+    }
+
+    /// Only autopilots are armed, other components like cameras can report the flag too
+    fn is_armed(&self) -> bool {
+        self.heartbeat.as_ref().is_some_and(|heartbeat| {
+            heartbeat.autopilot != MavAutopilot::MAV_AUTOPILOT_INVALID
+                && heartbeat
+                    .base_mode
+                    .contains(MavModeFlag::MAV_MODE_FLAG_SAFETY_ARMED)
+        })
+    }
+
+    /// Only the components with a heartbeat have a state
+    fn state(&self, (system_id, component_id): (u8, u8)) -> Option<VehicleState> {
+        let heartbeat = self.heartbeat.as_ref()?;
+
+        Some(VehicleState {
+            system_id,
+            component_id,
+            autopilot: heartbeat.autopilot,
+            mav_type: heartbeat.mavtype,
+            mode: modes::flight_mode(
+                heartbeat.autopilot,
+                heartbeat.mavtype,
+                heartbeat.base_mode,
+                heartbeat.custom_mode,
+            ),
+            custom_mode: heartbeat.custom_mode,
+            armed: heartbeat
+                .base_mode
+                .contains(MavModeFlag::MAV_MODE_FLAG_SAFETY_ARMED),
+            system_status: heartbeat.system_status,
+            battery: self.sys_status.as_ref().map(Battery::from),
+            gps: self.gps.as_ref().map(Gps::from),
+            position: self.position.as_ref().map(Position::from),
+            last_heartbeat_us: self.last_heartbeat_us,
+        })
+    }
+}
+
+impl VehicleStates {
+    fn new() -> Self {
+        let components = Arc::new(RwLock::new(HashMap::new()));
+
+        let _task = Arc::new(Mutex::new(tokio::spawn(Self::observe_task(
+            components.clone(),
+        ))));
+
+        Self { components, _task }
+    }
+
+    /// Keeps the last messages of each component that make its state
+    async fn observe_task(components: Arc<RwLock<HashMap<(u8, u8), ComponentMessages>>>) {
+        let hub_sender = match hub_sender().await {
+            Ok(hub_sender) => hub_sender,
+            Err(error) => {
+                error!("Vehicle states won't be known: {error}");
+                return;
+            }
+        };
+        let mut hub_receiver = hub_sender.subscribe();
+
+        loop {
+            let message = match hub_receiver.recv().await {
+                Ok(message) => message,
+                Err(broadcast::error::RecvError::Lagged(samples)) => {
+                    warn!("Channel lagged by {samples} messages.");
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+
+            Self::handle_message(&components, &message).await;
+        }
+
+        debug!("Vehicle states observer task stopped!");
+    }
+
+    async fn handle_message(
+        components: &RwLock<HashMap<(u8, u8), ComponentMessages>>,
+        message: &Protocol,
+    ) {
+        // Only the messages from the vehicles make their state, not the ones created by the server,
+        // like its own heartbeats, which have no origin
+        if !MESSAGE_IDS.contains(&message.message_id())
+            || message.origin.is_empty()
+            || message.origin == ORIGIN
+        {
+            return;
+        }
+
+        let Some((header, decoded)) = decode(message).await else {
+            return;
+        };
+
+        let disarmed = {
+            let mut components = components.write().await;
+            let component = components
+                .entry((header.system_id, header.component_id))
+                .or_default();
+
+            let was_armed = component.is_armed();
+            component.update(decoded, message.timestamp);
+            was_armed && !component.is_armed()
+        };
+
+        if disarmed && cli::download_logs_on_disarm() {
+            let (system_id, component_id) = (header.system_id, header.component_id);
+            info!("Vehicle {system_id}:{component_id} disarmed, downloading its newest log");
+            tokio::spawn(async move {
+                if let Err(error) = logs::start_latest(system_id, component_id).await {
+                    warn!(
+                        "Failed to download the newest log of {system_id}:{component_id}: {error}"
+                    );
+                }
+            });
+        }
+    }
+}
+
+/// The state of a vehicle component, once it has sent a heartbeat
+pub async fn state(system_id: u8, component_id: u8) -> Option<VehicleState> {
+    STATES
+        .components
+        .read()
+        .await
+        .get(&(system_id, component_id))?
+        .state((system_id, component_id))
+}
+
+/// Starts following the state of the vehicles
+pub(crate) fn init() {
+    lazy_static::initialize(&STATES);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state() {
+        let mut messages = ComponentMessages::default();
+        messages.update(
+            MavMessage::SYS_STATUS(SYS_STATUS_DATA {
+                voltage_battery: 16200,
+                current_battery: -1,
+                battery_remaining: 75,
+                ..Default::default()
+            }),
+            1,
+        );
+        assert_eq!(messages.state((1, 1)), None);
+
+        messages.update(
+            MavMessage::HEARTBEAT(HEARTBEAT_DATA {
+                custom_mode: 5,
+                mavtype: MavType::MAV_TYPE_QUADROTOR,
+                autopilot: MavAutopilot::MAV_AUTOPILOT_ARDUPILOTMEGA,
+                base_mode: MavModeFlag::MAV_MODE_FLAG_CUSTOM_MODE_ENABLED
+                    | MavModeFlag::MAV_MODE_FLAG_SAFETY_ARMED,
+                system_status: MavState::MAV_STATE_ACTIVE,
+                mavlink_version: 3,
+            }),
+            2,
+        );
+        messages.update(
+            MavMessage::GLOBAL_POSITION_INT(GLOBAL_POSITION_INT_DATA {
+                lat: -275_000_000,
+                lon: -485_000_000,
+                alt: 10_500,
+                relative_alt: 2_000,
+                hdg: u16::MAX,
+                ..Default::default()
+            }),
+            3,
+        );
+
+        let state = messages.state((1, 1)).unwrap();
+        assert_eq!(state.mode.as_deref(), Some("LOITER"));
+        assert!(state.armed);
+        assert!(messages.is_armed());
+        assert_eq!(state.last_heartbeat_us, 2);
+        assert_eq!(
+            state.battery,
+            Some(Battery {
+                voltage: Some(16.2),
+                current: None,
+                remaining: Some(75),
+            })
+        );
+        assert_eq!(state.gps, None);
+
+        let position = state.position.unwrap();
+        assert_eq!(position.latitude, -27.5);
+        assert_eq!(position.altitude, 10.5);
+        assert_eq!(position.heading, None);
+
+        messages.update(
+            MavMessage::HEARTBEAT(HEARTBEAT_DATA {
+                autopilot: MavAutopilot::MAV_AUTOPILOT_ARDUPILOTMEGA,
+                base_mode: MavModeFlag::MAV_MODE_FLAG_CUSTOM_MODE_ENABLED,
+                ..Default::default()
+            }),
+            4,
+        );
+        assert!(!messages.is_armed());
+    }
+}
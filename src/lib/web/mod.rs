@@ -8,7 +8,7 @@ use tower::Layer;
 use tower_http::normalize_path::NormalizePathLayer;
 use tracing::*;
 
-pub async fn run(address: std::net::SocketAddrV4) {
+pub async fn run(address: SocketAddr) {
     let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
     let mut first = true;
     loop {
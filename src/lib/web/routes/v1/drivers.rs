@@ -0,0 +1,95 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    extract::{Path, Query},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{delete, get},
+    Json, Router,
+};
+use serde::Deserialize;
+use tracing::*;
+use uuid::Uuid;
+
+use crate::{drivers::clients::ClientRegistry, hub};
+
+#[derive(Deserialize, Debug, Default)]
+pub struct DisconnectOptions {
+    /// Also refuse any further traffic from the client IP
+    #[serde(default)]
+    pub ban: bool,
+}
+
+#[instrument(level = "trace")]
+pub fn router() -> Router {
+    Router::new()
+        .route("/:uuid/clients", get(clients))
+        .route("/:uuid/clients/:address", delete(disconnect_client))
+        .route("/:uuid/banned", get(banned))
+        .route("/:uuid/banned/:address", delete(unban))
+}
+
+async fn driver_clients(uuid: Uuid) -> Result<ClientRegistry, Response> {
+    let driver = match hub::driver(uuid).await {
+        Ok(Some(driver)) => driver,
+        Ok(None) => return Err((StatusCode::NOT_FOUND, "404 Not Found").into_response()),
+        Err(error) => {
+            error!("Failed to get driver {uuid}: {error:?}");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        }
+    };
+
+    driver.clients().ok_or_else(|| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("Driver {uuid} does not serve clients"),
+        )
+            .into_response()
+    })
+}
+
+async fn clients(Path(uuid): Path<Uuid>) -> Response {
+    match driver_clients(uuid).await {
+        Ok(clients) => Json(clients.list().await).into_response(),
+        Err(response) => response,
+    }
+}
+
+async fn disconnect_client(
+    Path((uuid, address)): Path<(Uuid, SocketAddr)>,
+    Query(options): Query<DisconnectOptions>,
+) -> Response {
+    let clients = match driver_clients(uuid).await {
+        Ok(clients) => clients,
+        Err(response) => return response,
+    };
+
+    if options.ban {
+        return Json(clients.ban(address.ip()).await).into_response();
+    }
+
+    match clients.disconnect(&address).await {
+        Some(client) => Json(vec![client]).into_response(),
+        None => (StatusCode::NOT_FOUND, "404 Not Found").into_response(),
+    }
+}
+
+async fn banned(Path(uuid): Path<Uuid>) -> Response {
+    match driver_clients(uuid).await {
+        Ok(clients) => Json(clients.banned().await).into_response(),
+        Err(response) => response,
+    }
+}
+
+async fn unban(Path((uuid, address)): Path<(Uuid, IpAddr)>) -> Response {
+    let clients = match driver_clients(uuid).await {
+        Ok(clients) => clients,
+        Err(response) => return response,
+    };
+
+    if !clients.unban(&address).await {
+        return (StatusCode::NOT_FOUND, "404 Not Found").into_response();
+    }
+
+    StatusCode::OK.into_response()
+}
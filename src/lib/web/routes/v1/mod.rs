@@ -1,10 +1,12 @@
 use axum::Router;
 use tracing::*;
 
+pub mod drivers;
 pub mod info;
 pub mod log;
 pub mod rest;
 pub mod stats;
+pub mod vehicles;
 
 #[instrument(level = "trace")]
 pub fn router() -> Router {
@@ -13,4 +15,6 @@ pub fn router() -> Router {
         .nest("/stats", stats::router())
         .nest("/log", log::router())
         .nest("/info", info::router())
+        .nest("/drivers", drivers::router())
+        .nest("/vehicles", vehicles::router())
 }
@@ -3,11 +3,14 @@ use std::net::SocketAddr;
 use axum::{
     extract::{ConnectInfo, Path},
     http::StatusCode,
-    response::IntoResponse,
+    response::{IntoResponse, Response},
     Json,
 };
+use mavlink::ardupilotmega::MavMessage;
 use tracing::*;
 
+use crate::{mavlink_json::MAVLinkJSON, protocol::Protocol};
+
 pub(crate) async fn mavlink(path: Option<Path<String>>) -> impl IntoResponse {
     let path = match path {
         Some(path) => path.0.to_string(),
@@ -16,16 +19,74 @@ pub(crate) async fn mavlink(path: Option<Path<String>>) -> impl IntoResponse {
     crate::drivers::rest::data::messages(&path)
 }
 
+/// Receives a `MAVLinkJSON` message, or an array of them, and sends it to the hub.
+/// Arrays larger than the client input channel are refused, as part of them would be lost.
 pub(crate) async fn post_mavlink(
     ConnectInfo(address): ConnectInfo<SocketAddr>,
     message: String,
-) -> impl IntoResponse {
+) -> Response {
     use crate::web::routes::v1::rest::websocket;
 
     debug!("Got message from: {address:?}, {message}");
-    if let Err(error) = websocket::send(message) {
+
+    // HTTP callers are told apart by their IP, as each request can come from a different port
+    let address = SocketAddr::new(address.ip(), 0);
+    let origin = format!("http:{}", address.ip());
+
+    let contents = match parse_messages(&message) {
+        Ok(contents) => contents,
+        Err(error) => {
+            debug!("Failed to parse message from {address:?}: {error}");
+            return (StatusCode::BAD_REQUEST, error).into_response();
+        }
+    };
+
+    if contents.len() > websocket::CLIENT_INPUT_CAPACITY {
+        let error = format!(
+            "Batches are limited to {} messages",
+            websocket::CLIENT_INPUT_CAPACITY
+        );
+        return (StatusCode::PAYLOAD_TOO_LARGE, error).into_response();
+    }
+
+    let clients = websocket::clients();
+    if let Err(rejection) = clients.admit(&address).await {
+        warn!("Refusing HTTP client {address}: {rejection}");
+        return (StatusCode::FORBIDDEN, rejection.to_string()).into_response();
+    }
+    if !clients.contains(&address).await {
+        clients.insert(address).await;
+    }
+
+    let messages = contents
+        .iter()
+        .map(|content| Protocol::from_mavlink_raw(content.header.inner, &content.message, &origin))
+        .collect();
+
+    // Only fails when there is no REST driver to take the messages to the hub
+    if let Err(error) = websocket::send(address, messages) {
         error!("Failed to send message to main loop: {error:?}");
+        return (StatusCode::SERVICE_UNAVAILABLE, "Hub is unreachable").into_response();
     }
+
+    StatusCode::OK.into_response()
+}
+
+/// Parses a single `MAVLinkJSON` message or an array of them, with errors pointing at the bad field
+fn parse_messages(text: &str) -> Result<Vec<MAVLinkJSON<MavMessage>>, String> {
+    let mut deserializer =
+        json5::Deserializer::from_str(text).map_err(|error| error.to_string())?;
+
+    let result = if text.trim_start().starts_with('[') {
+        serde_path_to_error::deserialize(&mut deserializer)
+    } else {
+        serde_path_to_error::deserialize(&mut deserializer).map(|content| vec![content])
+    };
+
+    result.map_err(|error| match error.path().to_string().as_str() {
+        "." => error.inner().to_string(),
+        path => format!("{path}: {}", error.inner()),
+    })
 }
 
 pub(crate) async fn message_id_from_name(name: Path<String>) -> impl IntoResponse {
@@ -34,3 +95,33 @@ pub(crate) async fn message_id_from_name(name: Path<String>) -> impl IntoRespons
         .map(|id| (StatusCode::OK, Json(id)).into_response())
         .unwrap_or_else(|_| (StatusCode::NOT_FOUND, "404 Not Found").into_response())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_messages() {
+        let heartbeat = r#"{"header": {"system_id": 255, "component_id": 0, "sequence": 0},
+            "message": {"type": "HEARTBEAT", "custom_mode": 0, "mavtype": {"type": "MAV_TYPE_GCS"},
+            "autopilot": {"type": "MAV_AUTOPILOT_INVALID"}, "base_mode": {"bits": 0},
+            "system_status": {"type": "MAV_STATE_ACTIVE"}, "mavlink_version": 3}}"#;
+
+        assert_eq!(parse_messages(heartbeat).unwrap().len(), 1);
+        assert_eq!(
+            parse_messages(&format!("[{heartbeat}, {heartbeat}]"))
+                .unwrap()
+                .len(),
+            2
+        );
+
+        let error = parse_messages(&heartbeat.replace("\"system_id\": 255", "\"system_id\": 256"))
+            .unwrap_err();
+        assert!(error.starts_with("header"), "{error}");
+
+        let error = parse_messages(&format!("[{heartbeat}, {{\"header\": 1}}]")).unwrap_err();
+        assert!(error.starts_with("[1].header"), "{error}");
+
+        assert!(parse_messages("not json").is_err());
+    }
+}
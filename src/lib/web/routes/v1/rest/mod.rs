@@ -1,4 +1,5 @@
 pub mod mavlink;
+pub mod subscription;
 pub mod websocket;
 
 use axum::{routing::get, Router};
@@ -8,6 +9,7 @@ use tracing::*;
 pub fn router() -> Router {
     Router::new()
         .route("/ws", get(websocket::websocket_handler))
+        .route("/ws/binary", get(websocket::websocket_binary_handler))
         .route(
             "/mavlink",
             get(mavlink::mavlink).post(mavlink::post_mavlink),
@@ -0,0 +1,170 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use mavlink::Message;
+use serde::Deserialize;
+
+use crate::stats::messages::MessageId;
+
+/// What a WebSocket client wants to receive, from the connection query or a `subscribe` message
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct SubscriptionOptions {
+    /// Message names, as a list or separated by commas. All messages when not given.
+    pub messages: Option<MessageNames>,
+    pub system_id: Option<u8>,
+    pub component_id: Option<u8>,
+    /// Maximum rate, in Hz, for each message of each component
+    pub max_rate: Option<f64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum MessageNames {
+    Separated(String),
+    List(Vec<String>),
+}
+
+/// Control messages sent by clients as text, which are told apart from `MAVLinkJSON` by their key
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ControlMessage {
+    pub subscribe: SubscriptionOptions,
+}
+
+/// Identifies a MAVLink message, to match it against the subscriptions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MessageKey {
+    pub system_id: u8,
+    pub component_id: u8,
+    pub message_id: MessageId,
+}
+
+#[derive(Debug, Default)]
+pub struct Subscription {
+    message_ids: Option<HashSet<MessageId>>,
+    system_id: Option<u8>,
+    component_id: Option<u8>,
+    min_interval: Option<Duration>,
+    last_sent: HashMap<MessageKey, Instant>,
+}
+
+impl TryFrom<SubscriptionOptions> for Subscription {
+    type Error = anyhow::Error;
+
+    fn try_from(options: SubscriptionOptions) -> Result<Self> {
+        let names = match options.messages {
+            None => None,
+            Some(MessageNames::Separated(names)) => Some(
+                names
+                    .split(',')
+                    .map(|name| name.trim().to_string())
+                    .filter(|name| !name.is_empty())
+                    .collect::<Vec<_>>(),
+            ),
+            Some(MessageNames::List(names)) => Some(names),
+        };
+
+        let message_ids = names
+            .map(|names| {
+                names
+                    .iter()
+                    .map(|name| {
+                        mavlink::ardupilotmega::MavMessage::message_id_from_name(
+                            &name.to_ascii_uppercase(),
+                        )
+                        .map_err(|_| anyhow!("Unknown message {name:?}"))
+                    })
+                    .collect::<Result<HashSet<_>>>()
+            })
+            .transpose()?;
+
+        let min_interval = match options.max_rate {
+            None => None,
+            Some(max_rate) if max_rate.is_finite() && max_rate > 0.0 => {
+                Some(Duration::from_secs_f64(1.0 / max_rate))
+            }
+            Some(max_rate) => return Err(anyhow!("Invalid max_rate {max_rate:?}")),
+        };
+
+        Ok(Self {
+            message_ids,
+            system_id: options.system_id,
+            component_id: options.component_id,
+            min_interval,
+            last_sent: HashMap::new(),
+        })
+    }
+}
+
+impl Subscription {
+    /// Whether the message should be sent now, accounting it for the rate limit when it is
+    pub fn accepts(&mut self, key: &MessageKey) -> bool {
+        if self
+            .message_ids
+            .as_ref()
+            .is_some_and(|message_ids| !message_ids.contains(&key.message_id))
+            || self
+                .system_id
+                .is_some_and(|system_id| system_id != key.system_id)
+            || self
+                .component_id
+                .is_some_and(|component_id| component_id != key.component_id)
+        {
+            return false;
+        }
+
+        let Some(min_interval) = self.min_interval else {
+            return true;
+        };
+
+        let now = Instant::now();
+        match self.last_sent.get(key) {
+            Some(last_sent) if now.duration_since(*last_sent) < min_interval => false,
+            _ => {
+                self.last_sent.insert(*key, now);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(system_id: u8, message_id: MessageId) -> MessageKey {
+        MessageKey {
+            system_id,
+            component_id: 1,
+            message_id,
+        }
+    }
+
+    #[test]
+    fn test_subscription() {
+        let options: ControlMessage = serde_json::from_str(
+            r#"{"subscribe": {"messages": ["heartbeat", "ATTITUDE"], "system_id": 1, "max_rate": 20}}"#,
+        )
+        .unwrap();
+        let mut subscription = Subscription::try_from(options.subscribe).unwrap();
+
+        assert!(subscription.accepts(&key(1, 0)));
+        assert!(subscription.accepts(&key(1, 30)));
+        assert!(!subscription.accepts(&key(2, 0)), "Other vehicle");
+        assert!(!subscription.accepts(&key(1, 33)), "Not subscribed");
+        assert!(!subscription.accepts(&key(1, 0)), "Over the rate");
+
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(subscription.accepts(&key(1, 0)));
+
+        let uri = "/ws?messages=HEARTBEAT,NOT_A_MESSAGE".parse().unwrap();
+        let axum::extract::Query(options) =
+            axum::extract::Query::<SubscriptionOptions>::try_from_uri(&uri).unwrap();
+        assert!(Subscription::try_from(options).is_err());
+
+        assert!(Subscription::default().accepts(&key(2, 33)));
+    }
+}
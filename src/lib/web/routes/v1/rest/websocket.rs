@@ -1,24 +1,45 @@
 use std::{collections::HashMap, net::SocketAddr, sync::Arc};
 
+use anyhow::Result;
 use axum::{
     extract::{
         ws::{self, WebSocket},
-        ConnectInfo, WebSocketUpgrade,
+        ConnectInfo, Query, WebSocketUpgrade,
     },
-    response::Response,
+    http::StatusCode,
+    response::{IntoResponse, Response},
 };
+use bytes::BytesMut;
 use futures::{SinkExt, StreamExt};
 use lazy_static::lazy_static;
+use mavlink_codec::codec::MavlinkCodec;
 use tokio::sync::{broadcast, mpsc, RwLock};
+use tokio_util::codec::Decoder;
 use tracing::*;
 use uuid::Uuid;
 
+use crate::{
+    drivers::{clients::ClientRegistry, generic_tasks::should_send, websocket::WebsocketFormat},
+    mavlink_json::MAVLinkJSON,
+    protocol::Protocol,
+    web::routes::v1::rest::subscription::{
+        ControlMessage, MessageKey, Subscription, SubscriptionOptions,
+    },
+};
+
+/// HTTP callers are forgotten after this long without a request, as they never disconnect
+const HTTP_CLIENT_TIMEOUT: tokio::time::Duration = tokio::time::Duration::from_secs(30);
+
+/// Messages from the clients waiting for the REST driver. Larger batches would be cut short.
+pub(crate) const CLIENT_INPUT_CAPACITY: usize = 100;
+
 lazy_static! {
     static ref SERVER: Arc<SingletonServer> = {
-        let (message_tx, _message_rx) = broadcast::channel(100);
+        let (message_tx, _message_rx) = broadcast::channel(CLIENT_INPUT_CAPACITY);
         let clients = Arc::new(RwLock::new(HashMap::new()));
         let state = AppState {
             clients,
+            registry: ClientRegistry::default(),
             message_tx,
         };
         Arc::new(SingletonServer { state })
@@ -31,30 +52,96 @@ struct SingletonServer {
 
 #[derive(Clone, Debug)]
 struct AppState {
-    clients: Arc<RwLock<HashMap<Uuid, ClientSender>>>,
-    message_tx: broadcast::Sender<String>,
+    clients: Arc<RwLock<HashMap<Uuid, Client>>>,
+    /// Every WebSocket and HTTP client, with their stats
+    registry: ClientRegistry,
+    message_tx: broadcast::Sender<ClientInput>,
+}
+
+#[derive(Debug)]
+struct Client {
+    address: SocketAddr,
+    origin: String,
+    sender: ClientSender,
+    format: WebsocketFormat,
+    subscription: Subscription,
 }
 
 type ClientSender = mpsc::UnboundedSender<ws::Message>;
 
+/// A message sent by a WebSocket or HTTP client, on its way to the hub
+#[derive(Clone, Debug)]
+pub(crate) struct ClientInput {
+    pub address: SocketAddr,
+    pub message: Arc<Protocol>,
+}
+
+/// Clients exchange `MAVLinkJSON` text messages
 #[instrument(level = "debug", skip_all)]
 pub(crate) async fn websocket_handler(
     ws: WebSocketUpgrade,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(options): Query<SubscriptionOptions>,
+) -> Response {
+    upgrade(ws, addr, WebsocketFormat::Json, options).await
+}
+
+/// Clients exchange binary messages with raw MAVLink packets, which skips the JSON serialization
+/// and also carries messages outside of the `ardupilotmega` dialect
+#[instrument(level = "debug", skip_all)]
+pub(crate) async fn websocket_binary_handler(
+    ws: WebSocketUpgrade,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(options): Query<SubscriptionOptions>,
+) -> Response {
+    upgrade(ws, addr, WebsocketFormat::Binary, options).await
+}
+
+async fn upgrade(
+    ws: WebSocketUpgrade,
+    addr: SocketAddr,
+    format: WebsocketFormat,
+    options: SubscriptionOptions,
 ) -> Response {
-    ws.on_upgrade(move |socket| websocket_connection(socket, addr))
+    let subscription = match Subscription::try_from(options) {
+        Ok(subscription) => subscription,
+        Err(error) => return (StatusCode::BAD_REQUEST, error.to_string()).into_response(),
+    };
+
+    if let Err(rejection) = SERVER.state.registry.admit(&addr).await {
+        warn!("Refusing WS client {addr}: {rejection}");
+        return (StatusCode::FORBIDDEN, rejection.to_string()).into_response();
+    }
+
+    ws.on_upgrade(move |socket| websocket_connection(socket, addr, format, subscription))
 }
 
 #[instrument(level = "debug", skip(socket))]
-async fn websocket_connection(socket: WebSocket, addr: SocketAddr) {
+async fn websocket_connection(
+    socket: WebSocket,
+    addr: SocketAddr,
+    format: WebsocketFormat,
+    subscription: Subscription,
+) {
     let identifier = Uuid::new_v4();
-    debug!("WS client connected with ID: {identifier}");
+    let origin = format!("ws:{addr}");
+    debug!("WS client connected with ID: {identifier}, origin: {origin}");
 
     let state = &SERVER.state;
 
     let (mut sender, mut receiver) = socket.split();
     let (tx, mut rx) = mpsc::unbounded_channel::<ws::Message>();
-    state.clients.write().await.insert(identifier, tx);
+    state.registry.insert(addr).await;
+    state.clients.write().await.insert(
+        identifier,
+        Client {
+            address: addr,
+            origin: origin.clone(),
+            sender: tx,
+            format,
+            subscription,
+        },
+    );
 
     // Spawn a task to forward messages from the channel to the websocket
     let send_task = tokio::spawn(async move {
@@ -65,48 +152,194 @@ async fn websocket_connection(socket: WebSocket, addr: SocketAddr) {
         }
     });
 
-    // Handle incoming messages
-    while let Some(Ok(message)) = receiver.next().await {
-        match message {
-            ws::Message::Text(text) => {
-                trace!("WS client received from {identifier}: {text}");
-                if let Err(error) = state.message_tx.send(text.clone()) {
-                    error!("Failed to send message to main loop: {error:?}");
+    // Handle incoming messages, in a task that is aborted when the client is disconnected
+    let receive_task = tokio::spawn(async move {
+        while let Some(Ok(message)) = receiver.next().await {
+            // Text messages are either control messages or MAVLinkJSON ones
+            if let ws::Message::Text(text) = &message {
+                if let Ok(control) = serde_json::from_str::<ControlMessage>(text) {
+                    subscribe(identifier, control).await;
+                    continue;
                 }
-                broadcast(identifier, ws::Message::Text(text)).await;
             }
-            ws::Message::Close(frame) => {
-                debug!("WS client {identifier} disconnected: {frame:?}");
-                break;
+
+            match message {
+                ws::Message::Text(_) | ws::Message::Binary(_) => {
+                    trace!("WS client received from {identifier}: {message:?}");
+
+                    let messages = match decode(&message, &origin) {
+                        Ok(messages) => messages,
+                        Err(error) => {
+                            debug!(
+                                "Failed to parse message from WS client {identifier}: {error:?}"
+                            );
+                            continue;
+                        }
+                    };
+
+                    if let Err(error) = send(addr, messages) {
+                        error!("Failed to send message to main loop: {error:?}");
+                    }
+                }
+                ws::Message::Close(frame) => {
+                    debug!("WS client {identifier} disconnected: {frame:?}");
+                    break;
+                }
+                _ => {}
             }
-            _ => {}
         }
-    }
+    });
+    state
+        .registry
+        .set_task(&addr, receive_task.abort_handle())
+        .await;
+    let _ = receive_task.await;
 
     // We should be disconnected now, let's remove it
     state.clients.write().await.remove(&identifier);
+    state.registry.remove(&addr).await;
     debug!("WS client {identifier} removed");
     send_task.await.unwrap();
 }
 
-pub(crate) async fn broadcast(sender_identifier: Uuid, message: ws::Message) {
+/// Replaces the client subscription with the one in a control message
+async fn subscribe(identifier: Uuid, control: ControlMessage) {
+    let subscription = match Subscription::try_from(control.subscribe) {
+        Ok(subscription) => subscription,
+        Err(error) => {
+            warn!("Ignoring invalid subscription from WS client {identifier}: {error:?}");
+            return;
+        }
+    };
+
+    debug!("WS client {identifier} subscribed to {subscription:?}");
+
+    if let Some(client) = SERVER.state.clients.write().await.get_mut(&identifier) {
+        client.subscription = subscription;
+    }
+}
+
+/// Decodes a `MAVLinkJSON` text message, or a binary one with one or more raw MAVLink packets
+pub(crate) fn decode(message: &ws::Message, origin: &str) -> Result<Vec<Protocol>> {
+    match message {
+        ws::Message::Text(text) => {
+            let content = json5::from_str::<MAVLinkJSON<mavlink::ardupilotmega::MavMessage>>(text)?;
+
+            Ok(vec![Protocol::from_mavlink_raw(
+                content.header.inner,
+                &content.message,
+                origin,
+            )])
+        }
+        ws::Message::Binary(data) => {
+            let mut codec = MavlinkCodec::<true, true, false, false, false, false>::default();
+            let mut buffer = BytesMut::from(data.as_slice());
+            let mut messages = vec![];
+            while let Some(result) = codec.decode(&mut buffer)? {
+                match result {
+                    Ok(packet) => messages.push(Protocol::new(origin, packet)),
+                    Err(decode_error) => debug!("Failed to decode packet: {decode_error:?}"),
+                }
+            }
+
+            Ok(messages)
+        }
+        _ => Ok(vec![]),
+    }
+}
+
+/// Sends MAVLink messages to the clients that subscribed to them, except to the one they came from
+/// and to the others when it is an answer to a single client.
+/// Text messages go to the JSON clients, and binary ones to the binary clients.
+pub(crate) async fn broadcast(message: &Arc<Protocol>, ws_message: ws::Message) {
     let state = &SERVER.state;
 
-    let clients = state.clients.read().await;
+    let format = match &ws_message {
+        ws::Message::Binary(_) => WebsocketFormat::Binary,
+        _ => WebsocketFormat::Json,
+    };
 
-    for (&client_identifier, tx) in clients.iter() {
-        if client_identifier != sender_identifier {
-            if let Err(error) = tx.send(message.clone()) {
+    let key = MessageKey {
+        system_id: *message.system_id(),
+        component_id: *message.component_id(),
+        message_id: message.message_id(),
+    };
+
+    // The clients are snapshotted, so the lock isn't held while deciding who gets the message
+    let candidates: Vec<(Uuid, String)> = state
+        .clients
+        .read()
+        .await
+        .iter()
+        .filter(|(_, client)| client.format == format)
+        .map(|(&identifier, client)| (identifier, client.origin.clone()))
+        .collect();
+
+    let mut recipients = Vec::new();
+    for (identifier, origin) in candidates {
+        if should_send(message, &origin).await {
+            recipients.push(identifier);
+        }
+    }
+
+    let mut addresses = Vec::new();
+    {
+        let mut clients = state.clients.write().await;
+
+        for client_identifier in recipients {
+            // It may have disconnected meanwhile
+            let Some(client) = clients.get_mut(&client_identifier) else {
+                continue;
+            };
+
+            if !client.subscription.accepts(&key) {
+                continue;
+            }
+
+            if let Err(error) = client.sender.send(ws_message.clone()) {
                 error!("Failed to send message to client {client_identifier}: {error:?}",);
+                continue;
             }
+
+            addresses.push(client.address);
         }
     }
+
+    for address in addresses {
+        state.registry.update_output(&address, message).await;
+    }
 }
 
-pub(crate) fn create_message_receiver() -> broadcast::Receiver<String> {
+pub(crate) fn clients() -> ClientRegistry {
+    SERVER.state.registry.clone()
+}
+
+/// Forgets the HTTP callers that stopped sending, which are the clients registered with port 0
+pub(crate) async fn expire_http_clients() {
+    let registry = &SERVER.state.registry;
+
+    for address in registry.idle(HTTP_CLIENT_TIMEOUT).await {
+        if address.port() == 0 {
+            registry.remove(&address).await;
+        }
+    }
+}
+
+pub(crate) fn create_message_receiver() -> broadcast::Receiver<ClientInput> {
     SERVER.state.message_tx.subscribe()
 }
 
-pub(crate) fn send(message: String) -> Result<usize, broadcast::error::SendError<String>> {
-    SERVER.state.message_tx.send(message)
+pub(crate) fn send(
+    address: SocketAddr,
+    messages: Vec<Protocol>,
+) -> Result<usize, broadcast::error::SendError<ClientInput>> {
+    let mut receivers = 0;
+    for message in messages {
+        receivers = SERVER.state.message_tx.send(ClientInput {
+            address,
+            message: Arc::new(message),
+        })?;
+    }
+
+    Ok(receivers)
 }
@@ -0,0 +1,26 @@
+use axum::{
+    extract::Path,
+    response::{IntoResponse, Response},
+    Json,
+};
+use tracing::*;
+
+use crate::{
+    vehicles::command::{send_command, CommandRequest},
+    web::routes::v1::vehicles::error_response,
+};
+
+/// Sends a command to a component and answers with its `COMMAND_ACK`
+#[instrument(level = "debug")]
+pub(crate) async fn command(
+    Path((system_id, component_id)): Path<(u8, u8)>,
+    Json(request): Json<CommandRequest>,
+) -> Response {
+    match send_command(system_id, component_id, request).await {
+        Ok(result) => Json(result).into_response(),
+        Err(error) => {
+            warn!("Command to {system_id}:{component_id} failed: {error}");
+            error_response(error)
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use axum::{
+    body::Bytes,
+    extract::{
+        ws::{Message, WebSocket},
+        Path, WebSocketUpgrade,
+    },
+    http::header,
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::{SinkExt, StreamExt};
+use tokio::sync::broadcast;
+use tracing::*;
+
+use crate::{
+    vehicles::ftp::{self, EntryKind, FtpEntry},
+    web::routes::v1::vehicles::error_response,
+};
+
+/// Paths are absolute, apart from the virtual ones of ArduPilot like `@SYS/threads.txt`
+fn ftp_path(path: &str) -> String {
+    if path.starts_with('@') {
+        path.to_string()
+    } else {
+        format!("/{path}")
+    }
+}
+
+/// Lists the root directory
+pub(crate) async fn root(Path((system_id, component_id)): Path<(u8, u8)>) -> Response {
+    match ftp::list(system_id, component_id, "/").await {
+        Ok(entries) => Json(entries).into_response(),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Lists the directories, when the path ends with a `/`, or downloads the files
+pub(crate) async fn get(Path((system_id, component_id, path)): Path<(u8, u8, String)>) -> Response {
+    let path = ftp_path(&path);
+
+    if path.ends_with('/') {
+        return match ftp::list(system_id, component_id, &path).await {
+            Ok(entries) => Json(entries).into_response(),
+            Err(error) => {
+                warn!("Failed listing {path:?} on {system_id}:{component_id}: {error}");
+                error_response(error)
+            }
+        };
+    }
+
+    match ftp::download(system_id, component_id, &path).await {
+        Ok(data) => {
+            let name = path.rsplit('/').next().unwrap_or_default();
+            (
+                [
+                    (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+                    (
+                        header::CONTENT_DISPOSITION,
+                        format!("attachment; filename=\"{name}\""),
+                    ),
+                ],
+                data,
+            )
+                .into_response()
+        }
+        Err(error) => {
+            warn!("Failed downloading {path:?} from {system_id}:{component_id}: {error}");
+            error_response(error)
+        }
+    }
+}
+
+/// Creates or replaces a file with the body
+pub(crate) async fn put(
+    Path((system_id, component_id, path)): Path<(u8, u8, String)>,
+    body: Bytes,
+) -> Response {
+    let path = ftp_path(&path);
+
+    match ftp::upload(system_id, component_id, &path, &body).await {
+        Ok(()) => Json(FtpEntry {
+            name: path,
+            kind: EntryKind::File,
+            size: Some(body.len() as u32),
+        })
+        .into_response(),
+        Err(error) => {
+            warn!("Failed uploading {path:?} to {system_id}:{component_id}: {error}");
+            error_response(error)
+        }
+    }
+}
+
+/// Streams the progress of every FTP transfer, as JSON
+pub(crate) async fn progress_websocket_handler(ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(progress_websocket_connection)
+}
+
+#[instrument(level = "debug", skip_all)]
+async fn progress_websocket_connection(socket: WebSocket) {
+    let (mut websocket_sender, mut _websocket_receiver) = socket.split();
+    let mut receiver = ftp::progress();
+
+    loop {
+        let progress = match receiver.recv().await {
+            Ok(progress) => progress,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+
+        let Ok(json) = serde_json::to_string(&progress) else {
+            continue;
+        };
+        if websocket_sender.send(Message::Text(json)).await.is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use axum::{
+    body::Body,
+    extract::{Path, Query},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use tokio_util::io::ReaderStream;
+use tracing::*;
+
+use crate::{
+    vehicles::{
+        logs::{self, DownloadState},
+        VehicleError,
+    },
+    web::routes::v1::vehicles::{error_response, ComponentOptions},
+};
+
+pub(crate) async fn logs(
+    Path(system_id): Path<u8>,
+    Query(options): Query<ComponentOptions>,
+) -> Response {
+    match logs::list(system_id, options.component_id()).await {
+        Ok(entries) => Json(entries).into_response(),
+        Err(error) => {
+            warn!("Failed listing the logs of {system_id}: {error}");
+            error_response(error)
+        }
+    }
+}
+
+/// Starts downloading a log to the server, its progress is available from [`log`]
+pub(crate) async fn start_download(
+    Path((system_id, id)): Path<(u8, u16)>,
+    Query(options): Query<ComponentOptions>,
+) -> Response {
+    match logs::start(system_id, options.component_id(), id).await {
+        Ok(download) => (StatusCode::ACCEPTED, Json(download)).into_response(),
+        Err(error) => error_response(error),
+    }
+}
+
+/// The progress of a download
+pub(crate) async fn log(Path((system_id, id)): Path<(u8, u16)>) -> Response {
+    match logs::download(system_id, id).await {
+        Some(download) => Json(download).into_response(),
+        None => error_response(VehicleError::NotFound(format!(
+            "No download of log {id} from {system_id}"
+        ))),
+    }
+}
+
+/// The downloaded log file, streamed from the disk as logs can be large
+pub(crate) async fn log_file(Path((system_id, id)): Path<(u8, u16)>) -> Response {
+    let download = match logs::download(system_id, id).await {
+        Some(download) if download.state == DownloadState::Done => download,
+        Some(download) => {
+            return error_response(VehicleError::Rejected(format!(
+                "Log {id} from {system_id} is {:?}",
+                download.state
+            )))
+        }
+        None => {
+            return error_response(VehicleError::NotFound(format!(
+                "No download of log {id} from {system_id}"
+            )))
+        }
+    };
+
+    match tokio::fs::File::open(&download.path).await {
+        Ok(file) => {
+            let name = download
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let size = file.metadata().await.map(|metadata| metadata.len());
+
+            let mut response = (
+                [
+                    (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+                    (
+                        header::CONTENT_DISPOSITION,
+                        format!("attachment; filename=\"{name}\""),
+                    ),
+                ],
+                Body::from_stream(ReaderStream::new(file)),
+            )
+                .into_response();
+            if let Ok(size) = size {
+                response
+                    .headers_mut()
+                    .insert(header::CONTENT_LENGTH, HeaderValue::from(size));
+            }
+            response
+        }
+        Err(error) => error_response(VehicleError::NotFound(format!(
+            "{:?}: {error}",
+            download.path
+        ))),
+    }
+}
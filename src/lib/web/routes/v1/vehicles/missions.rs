@@ -0,0 +1,102 @@
+use axum::{
+    extract::{Path, Query},
+    http::header,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use tracing::*;
+
+use crate::{
+    vehicles::{
+        missions::{self, MissionItem},
+        VehicleError,
+    },
+    web::routes::v1::vehicles::{error_response, ComponentOptions},
+};
+
+#[derive(Deserialize, Debug, Default)]
+pub struct MissionOptions {
+    /// `json` by default, or `wpl` for QGC WPL 110 files
+    pub format: Option<String>,
+}
+
+pub(crate) async fn mission(
+    Path((system_id, mission_type)): Path<(u8, String)>,
+    Query(component): Query<ComponentOptions>,
+    Query(options): Query<MissionOptions>,
+) -> Response {
+    let mission_type = match missions::mission_type_from_name(&mission_type) {
+        Ok(mission_type) => mission_type,
+        Err(error) => return error_response(error),
+    };
+
+    let items = match missions::download(system_id, component.component_id(), mission_type).await {
+        Ok(items) => items,
+        Err(error) => {
+            warn!("Failed downloading {mission_type:?} from {system_id}: {error}");
+            return error_response(error);
+        }
+    };
+
+    match options.format.as_deref() {
+        None | Some("json") => Json(items).into_response(),
+        Some("wpl") => (
+            [(header::CONTENT_TYPE, "text/plain")],
+            missions::to_wpl(&items),
+        )
+            .into_response(),
+        Some(format) => error_response(VehicleError::Invalid(format!(
+            "Invalid format {format:?}, expected \"json\" or \"wpl\""
+        ))),
+    }
+}
+
+/// Replaces the items with the ones in the body, as JSON or as a QGC WPL 110 file
+pub(crate) async fn upload_mission(
+    Path((system_id, mission_type)): Path<(u8, String)>,
+    Query(options): Query<ComponentOptions>,
+    body: String,
+) -> Response {
+    let mission_type = match missions::mission_type_from_name(&mission_type) {
+        Ok(mission_type) => mission_type,
+        Err(error) => return error_response(error),
+    };
+
+    let items = if body.trim_start().starts_with("QGC WPL") {
+        missions::from_wpl(&body)
+    } else {
+        serde_json::from_str::<Vec<MissionItem>>(&body)
+            .map_err(|error| VehicleError::Invalid(error.to_string()))
+    };
+    let items = match items {
+        Ok(items) => items,
+        Err(error) => return error_response(error),
+    };
+
+    match missions::upload(system_id, options.component_id(), mission_type, &items).await {
+        Ok(()) => Json(items).into_response(),
+        Err(error) => {
+            warn!("Failed uploading {mission_type:?} to {system_id}: {error}");
+            error_response(error)
+        }
+    }
+}
+
+pub(crate) async fn clear_mission(
+    Path((system_id, mission_type)): Path<(u8, String)>,
+    Query(options): Query<ComponentOptions>,
+) -> Response {
+    let mission_type = match missions::mission_type_from_name(&mission_type) {
+        Ok(mission_type) => mission_type,
+        Err(error) => return error_response(error),
+    };
+
+    match missions::clear(system_id, options.component_id(), mission_type).await {
+        Ok(()) => Json(Vec::<MissionItem>::new()).into_response(),
+        Err(error) => {
+            warn!("Failed clearing {mission_type:?} of {system_id}: {error}");
+            error_response(error)
+        }
+    }
+}
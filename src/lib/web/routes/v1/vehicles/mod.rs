@@ -0,0 +1,82 @@
+pub mod command;
+pub mod ftp;
+pub mod logs;
+pub mod missions;
+pub mod parameters;
+pub mod registry;
+pub mod shell;
+pub mod state;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use serde::Deserialize;
+use tracing::*;
+
+use crate::vehicles::VehicleError;
+
+/// The autopilot, which runs most of the vehicle services
+const DEFAULT_COMPONENT_ID: u8 = 1;
+
+/// Selects the component for the routes that only have the system ID in their path
+#[derive(Deserialize, Debug, Default)]
+pub struct ComponentOptions {
+    pub component_id: Option<u8>,
+}
+
+impl ComponentOptions {
+    fn component_id(&self) -> u8 {
+        self.component_id.unwrap_or(DEFAULT_COMPONENT_ID)
+    }
+}
+
+#[instrument(level = "trace")]
+pub fn router() -> Router {
+    Router::new()
+        .route("/", get(registry::vehicles))
+        .route("/ws", get(registry::events_websocket_handler))
+        .route("/ftp/ws", get(ftp::progress_websocket_handler))
+        .route("/:system_id/:component_id/command", post(command::command))
+        .route(
+            "/:system_id/:component_id/parameters",
+            get(parameters::parameters).put(parameters::set_parameters),
+        )
+        .route(
+            "/:system_id/:component_id/parameters/:name",
+            get(parameters::parameter).put(parameters::set_parameter),
+        )
+        .route(
+            "/:system_id/missions/:mission_type",
+            get(missions::mission)
+                .put(missions::upload_mission)
+                .delete(missions::clear_mission),
+        )
+        .route("/:system_id/state", get(state::state))
+        .route("/:system_id/shell", get(shell::shell_websocket_handler))
+        .route("/:system_id/logs", get(logs::logs))
+        .route(
+            "/:system_id/logs/:id",
+            get(logs::log).post(logs::start_download),
+        )
+        .route("/:system_id/logs/:id/file", get(logs::log_file))
+        .route("/:system_id/:component_id/ftp", get(ftp::root))
+        .route(
+            "/:system_id/:component_id/ftp/*path",
+            get(ftp::get).put(ftp::put),
+        )
+}
+
+fn error_response(error: VehicleError) -> Response {
+    let status = match error {
+        VehicleError::Invalid(_) => StatusCode::BAD_REQUEST,
+        VehicleError::NotFound(_) => StatusCode::NOT_FOUND,
+        VehicleError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+        VehicleError::Rejected(_) => StatusCode::CONFLICT,
+        VehicleError::Unreachable(_) => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    (status, error.to_string()).into_response()
+}
@@ -0,0 +1,68 @@
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::Path,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use tracing::*;
+
+use crate::{vehicles::parameters, web::routes::v1::vehicles::error_response};
+
+#[derive(Deserialize, Debug)]
+pub struct ParameterValue {
+    pub value: f32,
+}
+
+/// Every parameter of a component, from the cache when it is complete
+pub(crate) async fn parameters(Path((system_id, component_id)): Path<(u8, u8)>) -> Response {
+    match parameters::parameters(system_id, component_id).await {
+        Ok(parameters) => Json(parameters).into_response(),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Sets several parameters, by name, stopping at the first one that fails
+#[instrument(level = "debug")]
+pub(crate) async fn set_parameters(
+    Path((system_id, component_id)): Path<(u8, u8)>,
+    Json(values): Json<BTreeMap<String, f32>>,
+) -> Response {
+    let mut parameters = Vec::with_capacity(values.len());
+
+    for (name, value) in values {
+        match parameters::set_parameter(system_id, component_id, &name, value).await {
+            Ok(parameter) => parameters.push(parameter),
+            Err(error) => {
+                warn!("Failed setting {name:?} on {system_id}:{component_id}: {error}");
+                return error_response(error);
+            }
+        }
+    }
+
+    Json(parameters).into_response()
+}
+
+pub(crate) async fn parameter(
+    Path((system_id, component_id, name)): Path<(u8, u8, String)>,
+) -> Response {
+    match parameters::parameter(system_id, component_id, &name).await {
+        Ok(parameter) => Json(parameter).into_response(),
+        Err(error) => error_response(error),
+    }
+}
+
+#[instrument(level = "debug")]
+pub(crate) async fn set_parameter(
+    Path((system_id, component_id, name)): Path<(u8, u8, String)>,
+    Json(ParameterValue { value }): Json<ParameterValue>,
+) -> Response {
+    match parameters::set_parameter(system_id, component_id, &name, value).await {
+        Ok(parameter) => Json(parameter).into_response(),
+        Err(error) => {
+            warn!("Failed setting {name:?} on {system_id}:{component_id}: {error}");
+            error_response(error)
+        }
+    }
+}
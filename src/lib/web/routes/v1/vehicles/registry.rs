@@ -0,0 +1,44 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        WebSocketUpgrade,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::{SinkExt, StreamExt};
+use tokio::sync::broadcast;
+use tracing::*;
+
+use crate::vehicles::registry;
+
+/// The systems and components seen through their heartbeats
+pub(crate) async fn vehicles() -> Response {
+    Json(registry::systems().await).into_response()
+}
+
+/// The changes of the registry, as they happen, with the component after the change
+pub(crate) async fn events_websocket_handler(ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(events_websocket_connection)
+}
+
+#[instrument(level = "debug", skip_all)]
+async fn events_websocket_connection(socket: WebSocket) {
+    let (mut websocket_sender, mut _websocket_receiver) = socket.split();
+    let mut receiver = registry::events();
+
+    loop {
+        let event = match receiver.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+
+        let Ok(json) = serde_json::to_string(&event) else {
+            continue;
+        };
+        if websocket_sender.send(Message::Text(json)).await.is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use axum::{
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket},
+        Path, Query, WebSocketUpgrade,
+    },
+    response::Response,
+};
+use futures::{SinkExt, StreamExt};
+use tokio::sync::mpsc;
+use tracing::*;
+
+use crate::{vehicles::shell, web::routes::v1::vehicles::ComponentOptions};
+
+/// The vehicle shell as a byte stream, the input can be sent as text or binary messages
+/// and the output is sent as binary messages
+pub(crate) async fn shell_websocket_handler(
+    ws: WebSocketUpgrade,
+    Path(system_id): Path<u8>,
+    Query(options): Query<ComponentOptions>,
+) -> Response {
+    let component_id = options.component_id();
+    ws.on_upgrade(move |socket| shell_websocket_connection(socket, system_id, component_id))
+}
+
+#[instrument(level = "debug", skip(socket))]
+async fn shell_websocket_connection(socket: WebSocket, system_id: u8, component_id: u8) {
+    let (mut websocket_sender, mut websocket_receiver) = socket.split();
+    let (input_sender, input_receiver) = mpsc::channel(100);
+    let (output_sender, mut output_receiver) = mpsc::channel(100);
+
+    let shell_task = tokio::spawn(shell::run(
+        system_id,
+        component_id,
+        input_receiver,
+        output_sender,
+    ));
+
+    loop {
+        tokio::select! {
+            message = websocket_receiver.next() => {
+                let data = match message {
+                    Some(Ok(Message::Text(text))) => text.into_bytes(),
+                    Some(Ok(Message::Binary(data))) => data,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                };
+                if input_sender.send(data).await.is_err() {
+                    break;
+                }
+            }
+            output = output_receiver.recv() => {
+                let Some(output) = output else {
+                    break;
+                };
+                if websocket_sender.send(Message::Binary(output)).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    // Closing the channels stops the shell
+    drop(input_sender);
+    drop(output_receiver);
+    let result = match shell_task.await {
+        Ok(result) => result,
+        Err(error) => {
+            warn!("Shell task of {system_id} failed: {error}");
+            return;
+        }
+    };
+
+    if let Err(error) = result {
+        warn!("Shell of {system_id}:{component_id} stopped: {error}");
+        let frame = CloseFrame {
+            code: close_code::ERROR,
+            reason: error.to_string().into(),
+        };
+        let _ = websocket_sender.send(Message::Close(Some(frame))).await;
+    }
+}
@@ -0,0 +1,25 @@
+use axum::{
+    extract::{Path, Query},
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use crate::{
+    vehicles::{state, VehicleError},
+    web::routes::v1::vehicles::{error_response, ComponentOptions},
+};
+
+/// The flight mode, armed state, system status, battery, GPS and position of a vehicle
+pub(crate) async fn state(
+    Path(system_id): Path<u8>,
+    Query(options): Query<ComponentOptions>,
+) -> Response {
+    let component_id = options.component_id();
+
+    match state::state(system_id, component_id).await {
+        Some(state) => Json(state).into_response(),
+        None => error_response(VehicleError::NotFound(format!(
+            "No heartbeat from {system_id}:{component_id}"
+        ))),
+    }
+}
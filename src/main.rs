@@ -1,7 +1,7 @@
 use anyhow::*;
 use tracing::*;
 
-use mavlink_server::{cli, hub, logger, web};
+use mavlink_server::{cli, hub, logger, vehicles, web};
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 10)]
 async fn main() -> Result<()> {
@@ -9,6 +9,8 @@ async fn main() -> Result<()> {
     cli::init();
     // Logger should start before everything else to register any log information
     logger::init();
+    // Vehicle services should start before the drivers to observe all the traffic
+    vehicles::init();
 
     for driver in cli::endpoints() {
         hub::add_driver(driver).await?;
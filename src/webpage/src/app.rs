@@ -14,6 +14,7 @@ use web_sys::window;
 
 use crate::{
     messages::{FieldInfo, MessageInfo, VehiclesMessages},
+    shell::ShellTerminal,
     stats::{
         drivers_stats::{DriversStatsHistorical, DriversStatsSample},
         hub_messages_stats::{HubMessagesStatsHistorical, HubMessagesStatsSample},
@@ -33,6 +34,7 @@ enum Tab {
     HubStats,
     MessagesStats,
     DriversStats,
+    Shell,
 }
 
 pub struct App {
@@ -53,6 +55,8 @@ pub struct App {
     hub_stats: HubStatsHistorical,
     /// Driver statistics
     drivers_stats: DriversStatsHistorical,
+    /// MAVLink shell of a vehicle
+    shell: ShellTerminal,
     search_query: String,
     collapse_all: bool,
     expand_all: bool,
@@ -77,7 +81,7 @@ impl Default for App {
         let (drivers_stats_sender, drivers_stats_receiver) =
             connect_websocket(DRIVERS_STATS_WEBSOCKET_PATH).unwrap();
 
-        let mut dock_state = DockState::new(vec![Tab::MessagesInspector]);
+        let mut dock_state = DockState::new(vec![Tab::MessagesInspector, Tab::Shell]);
 
         let [left, right] =
             dock_state
@@ -105,6 +109,7 @@ impl Default for App {
             hub_messages_stats: Default::default(),
             hub_stats: Default::default(),
             drivers_stats: Default::default(),
+            shell: Default::default(),
             search_query: String::new(),
             collapse_all: false,
             expand_all: false,
@@ -695,7 +700,7 @@ fn get_protocol() -> (String, String) {
     (host, protocol.to_string())
 }
 
-fn connect_websocket(path: &str) -> Result<(WsSender, WsReceiver), String> {
+pub(crate) fn connect_websocket(path: &str) -> Result<(WsSender, WsReceiver), String> {
     let (host, protocol) = get_protocol();
 
     let url = format!("{protocol}//{host}/{path}");
@@ -809,6 +814,7 @@ impl<'a> TabViewer for OurTabViewer<'a> {
             Tab::HubStats => "Hub Stats".into(),
             Tab::MessagesStats => "Messages Stats".into(),
             Tab::DriversStats => "Drivers Stats".into(),
+            Tab::Shell => "Vehicle Shell".into(),
         }
     }
 
@@ -852,6 +858,9 @@ impl<'a> TabViewer for OurTabViewer<'a> {
             Tab::DriversStats => {
                 self.app.create_drivers_stats_ui(ui);
             }
+            Tab::Shell => {
+                self.app.shell.ui(ui);
+            }
         }
     }
 }
@@ -863,6 +872,7 @@ impl eframe::App for App {
         self.process_hub_messages_stats_websocket();
         self.process_hub_stats_websocket();
         self.process_drivers_stats_websocket();
+        self.shell.process_websocket();
 
         self.top_bar(ctx);
 
@@ -2,5 +2,6 @@
 
 mod app;
 mod messages;
+mod shell;
 mod stats;
 pub use app::App;
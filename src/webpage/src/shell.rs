@@ -0,0 +1,167 @@
+use ewebsock::{WsEvent, WsMessage, WsReceiver, WsSender};
+
+use crate::app::connect_websocket;
+
+/// Characters kept from the output, the older ones are dropped
+const MAX_OUTPUT: usize = 100_000;
+const CTRL_C: u8 = 0x03;
+
+/// A terminal for the MAVLink shell of a vehicle, through `/vehicles/{system_id}/shell`
+pub struct ShellTerminal {
+    connection: Option<(WsSender, WsReceiver)>,
+    system_id: u8,
+    output: String,
+    input: String,
+}
+
+impl Default for ShellTerminal {
+    fn default() -> Self {
+        Self {
+            connection: None,
+            system_id: 1,
+            output: String::new(),
+            input: String::new(),
+        }
+    }
+}
+
+impl ShellTerminal {
+    fn connect(&mut self) {
+        match connect_websocket(&format!("vehicles/{}/shell", self.system_id)) {
+            Ok(connection) => self.connection = Some(connection),
+            Err(error) => self.print(&format!("\n[Failed connecting: {error}]\n")),
+        }
+    }
+
+    fn disconnect(&mut self) {
+        // Dropping the sender closes the WebSocket
+        self.connection = None;
+        self.print("\n[Disconnected]\n");
+    }
+
+    fn send(&mut self, data: Vec<u8>) {
+        if let Some((sender, _)) = &mut self.connection {
+            sender.send(WsMessage::Binary(data));
+        }
+    }
+
+    fn print(&mut self, text: &str) {
+        self.output.push_str(&strip_escapes(text));
+
+        if self.output.len() > MAX_OUTPUT {
+            let mut start = self.output.len() - MAX_OUTPUT;
+            while !self.output.is_char_boundary(start) {
+                start += 1;
+            }
+            self.output.drain(..start);
+        }
+    }
+
+    pub fn process_websocket(&mut self) {
+        loop {
+            let Some((_, receiver)) = &mut self.connection else {
+                return;
+            };
+
+            match receiver.try_recv() {
+                Some(WsEvent::Message(WsMessage::Binary(data))) => {
+                    self.print(&String::from_utf8_lossy(&data))
+                }
+                Some(WsEvent::Message(WsMessage::Text(text))) => self.print(&text),
+                Some(WsEvent::Closed) => {
+                    log::info!("Shell WebSocket closed");
+                    self.disconnect();
+                    return;
+                }
+                Some(WsEvent::Error(message)) => {
+                    log::error!("Shell WebSocket error: {message}");
+                    self.print(&format!("\n[{message}]"));
+                    self.disconnect();
+                    return;
+                }
+                Some(WsEvent::Opened) => {
+                    log::info!("Shell WebSocket opened");
+                }
+                something @ Some(_) => {
+                    log::trace!("Shell WebSocket got an unexpected event: {something:#?}");
+                }
+                None => return,
+            }
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal_top(|ui| {
+            ui.label("System ID:");
+            ui.add_enabled(
+                self.connection.is_none(),
+                egui::DragValue::new(&mut self.system_id).range(1..=255),
+            );
+
+            if self.connection.is_none() {
+                if ui.button("Connect").clicked() {
+                    self.connect();
+                }
+            } else {
+                if ui.button("Disconnect").clicked() {
+                    self.disconnect();
+                }
+                if ui.button("Ctrl+C").clicked() {
+                    self.send(vec![CTRL_C]);
+                }
+            }
+
+            if ui.button("Clear").clicked() {
+                self.output.clear();
+            }
+        });
+
+        let input_height = ui.spacing().interact_size.y * 2.;
+        egui::ScrollArea::both()
+            .auto_shrink([false; 2])
+            .stick_to_bottom(true)
+            .max_height(ui.available_height() - input_height)
+            .show(ui, |ui| {
+                ui.add(egui::Label::new(egui::RichText::new(&self.output).monospace()).extend());
+            });
+
+        let response = ui.add_enabled(
+            self.connection.is_some(),
+            egui::TextEdit::singleline(&mut self.input)
+                .font(egui::TextStyle::Monospace)
+                .hint_text("Command...")
+                .desired_width(f32::INFINITY),
+        );
+        if response.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter)) {
+            let mut line = std::mem::take(&mut self.input).into_bytes();
+            line.push(b'\n');
+            self.send(line);
+            response.request_focus();
+        }
+    }
+}
+
+/// Removes the ANSI escape sequences and carriage returns used by the shells to draw in real terminals
+fn strip_escapes(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut characters = text.chars().peekable();
+
+    while let Some(character) = characters.next() {
+        match character {
+            '\u{1b}' => {
+                if characters.next_if_eq(&'[').is_some() {
+                    // Parameters until the final byte, like `m` or `K`
+                    for character in characters.by_ref() {
+                        if ('\u{40}'..='\u{7e}').contains(&character) {
+                            break;
+                        }
+                    }
+                }
+            }
+            '\r' => {}
+            character => output.push(character),
+        }
+    }
+
+    output
+}